@@ -0,0 +1,40 @@
+//! Puts the git commit the binary was built from into `--version`, for bug reports
+
+use std::{fs, path::Path, process::Command};
+
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    // a commit moves the branch HEAD points at, not HEAD itself
+    let branch = fs::read_to_string(".git/HEAD")
+        .ok()
+        .and_then(|head| Some(head.strip_prefix("ref: ")?.trim().to_string()));
+    for path in branch
+        .map(|branch| format!(".git/{branch}"))
+        .into_iter()
+        .chain([String::from(".git/packed-refs")])
+    {
+        // cargo reruns on every build for a path that doesn't exist
+        if Path::new(&path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+
+    let version = env!("CARGO_PKG_VERSION");
+    // a build from a source tarball has no git to ask
+    let version = match git(&["log", "-1", "--format=%h %cs"]) {
+        Some(commit) => format!("{version} ({commit})"),
+        None => version.to_string(),
+    };
+
+    println!("cargo:rustc-env=TWITCHER_VERSION={version}");
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    let output = String::from_utf8(output.stdout)
+        .ok()
+        .filter(|_| output.status.success())?;
+
+    Some(output.trim().to_string()).filter(|output| !output.is_empty())
+}
@@ -0,0 +1,158 @@
+//! Command line arguments
+
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::{error::ErrorKind, CommandFactory, Parser};
+
+/// A terminal client for twitch chat with vim bindings
+#[derive(Debug, Parser)]
+#[command(version = env!("TWITCHER_VERSION"))]
+pub struct Args {
+    /// Channels to join, each one gets its own tab, a link like `https://twitch.tv/foo` works
+    /// too
+    #[arg(value_name = "CHANNEL")]
+    channels: Vec<String>,
+
+    /// Another channel to join, can be given more than once
+    #[arg(long = "channel", value_name = "CHANNEL")]
+    channel_args: Vec<String>,
+
+    /// OAuth token to send messages with, `TWITCH_TOKEN` is used otherwise
+    #[arg(long)]
+    token: Option<String>,
+
+    /// File to read the OAuth token from, so it doesn't show up in the shell history
+    #[arg(long, value_name = "PATH", conflicts_with = "token")]
+    token_file: Option<PathBuf>,
+
+    /// Name to log in as, it has to be the account the token belongs to
+    #[arg(long)]
+    pub nick: Option<String>,
+
+    /// Only watch chat, nothing is ever sent
+    #[arg(long)]
+    pub read_only: bool,
+
+    /// Write logs, also done when `RUST_LOG` is set
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Where logs go, `twitcher.log` in the temp directory by default
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// `socks5://host:port` or `http://host:port`, `ALL_PROXY` is used otherwise
+    #[arg(long, value_name = "URL")]
+    pub proxy: Option<String>,
+
+    /// Connect with TLS on port 6697 instead of in plain text on 6667
+    #[arg(long)]
+    pub tls: bool,
+
+    /// Config file to use instead of the default one
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Play back a log of raw IRC lines instead of connecting, at the pace they were sent.
+    /// Nothing is sent while replaying
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["token", "token_file"])]
+    pub replay: Option<PathBuf>,
+}
+
+impl Args {
+    /// Prints `message` with the usage to stderr and exits, like clap does for unknown arguments
+    pub fn exit_with_usage(kind: ErrorKind, message: impl std::fmt::Display) -> ! {
+        Self::command().error(kind, message).exit()
+    }
+
+    /// Positional channels first, then the `--channel` ones, as the bare channel login
+    pub fn channels(&self) -> anyhow::Result<Vec<String>> {
+        self.channels
+            .iter()
+            .chain(&self.channel_args)
+            .map(|channel| {
+                channel_name(channel)
+                    .ok_or_else(|| anyhow::anyhow!("{channel} isn't a twitch channel"))
+            })
+            .collect()
+    }
+
+    /// From `--token`, `--token-file` or `TWITCH_TOKEN`, `None` connects anonymously
+    pub fn token(&self) -> anyhow::Result<Option<String>> {
+        if let Some(path) = &self.token_file {
+            let token = std::fs::read_to_string(path)
+                .with_context(|| format!("can't read the token from {}", path.display()))?;
+            return Ok(Some(token.trim().to_string()));
+        }
+
+        Ok(self
+            .token
+            .clone()
+            .or_else(|| std::env::var("TWITCH_TOKEN").ok()))
+    }
+}
+
+/// The login from a channel name, `#foo` or a link to the channel like people copy from the
+/// browser, `None` if it can't be a twitch login
+pub fn channel_name(arg: &str) -> Option<String> {
+    let arg = arg
+        .strip_prefix("https://")
+        .or_else(|| arg.strip_prefix("http://"))
+        .unwrap_or(arg);
+    let arg = ["www.twitch.tv/", "m.twitch.tv/", "twitch.tv/"]
+        .iter()
+        .find_map(|host| arg.strip_prefix(host))
+        // e.g. `twitch.tv/foo/videos` or `twitch.tv/foo?referrer=raid`
+        .map_or(arg, |path| {
+            path.split(['/', '?']).next().unwrap_or_default()
+        });
+
+    let channel = arg.trim_start_matches('#').to_lowercase();
+    // logins are at most 25 letters, numbers and underscores
+    let valid = !channel.is_empty()
+        && channel.len() <= 25
+        && channel
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then_some(channel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_name() {
+        assert_eq!(channel_name("Foo").as_deref(), Some("foo"));
+        assert_eq!(
+            channel_name("https://twitch.tv/foo").as_deref(),
+            Some("foo")
+        );
+        assert_eq!(
+            channel_name("https://www.twitch.tv/Foo_Bar/videos?filter=all").as_deref(),
+            Some("foo_bar")
+        );
+        assert_eq!(
+            channel_name("twitch.tv/foo?referrer=raid").as_deref(),
+            Some("foo")
+        );
+
+        assert_eq!(channel_name("https://example.com/foo"), None);
+        assert_eq!(channel_name("https://twitch.tv/"), None);
+        assert_eq!(channel_name("#"), None);
+    }
+
+    #[test]
+    fn test_channels() {
+        let args = Args::parse_from(["twitcher", "#Foo", "bar", "--channel", "baz"]);
+        assert_eq!(args.channels().unwrap(), ["foo", "bar", "baz"]);
+
+        assert!(
+            Args::try_parse_from(["twitcher", "foo", "--token", "a", "--token-file", "b"]).is_err()
+        );
+        assert!(
+            Args::try_parse_from(["twitcher", "foo", "--token", "a", "--replay", "b"]).is_err()
+        );
+    }
+}
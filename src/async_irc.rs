@@ -0,0 +1,386 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use crate::{
+    framer::LineFramer,
+    irc::{check_cap_reply, Outgoing},
+    net::{self, CONNECT_TIMEOUT},
+    IRCCommand, IRCMessage, RateLimiter,
+};
+
+/// Same as [`crate::IRC`], but driven by tokio tasks instead of threads
+///
+/// Messages are queued and paced by the rate limit the same way, and the limit carries over
+/// reconnects. It only connects directly though, without a [`crate::Proxy`] or TLS, and doesn't
+/// send pings of its own, replay logs or switch accounts
+#[allow(clippy::upper_case_acronyms)]
+pub struct AsyncIRC {
+    irc_message_receiver: mpsc::UnboundedReceiver<IRCMessage>,
+    message_sender: mpsc::UnboundedSender<String>,
+    tasks: [JoinHandle<()>; 2],
+    /// set once the reader task stopped, e.g. because twitch closed the connection
+    closed: Arc<AtomicBool>,
+    address: String,
+    auth_token: Option<String>,
+    capabilities: Vec<String>,
+    rate_limiter: RateLimiter,
+    /// messages waiting for the rate limiter
+    queue: VecDeque<Outgoing>,
+    /// every channel we joined, in the order they were joined
+    channels: Vec<String>,
+    nick: String,
+}
+
+impl AsyncIRC {
+    #[tracing::instrument(skip(auth_token))]
+    pub async fn new(
+        address: &str,
+        auth_token: Option<&str>,
+        nick: &str,
+        capabilities: &[String],
+        channel: &str,
+    ) -> anyhow::Result<Self> {
+        let connection = net::connect_async(address, CONNECT_TIMEOUT).await?;
+        let (mut reader, mut writer) = connection.into_split();
+
+        let (message_sender, mut message_receiver) = mpsc::unbounded_channel::<String>();
+
+        let writer_task = tokio::spawn(async move {
+            while let Some(message) = message_receiver.recv().await {
+                if writer.write_all(message.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (irc_message_sender, mut irc_message_receiver) =
+            mpsc::unbounded_channel::<IRCMessage>();
+
+        let closed = Arc::new(AtomicBool::new(false));
+        let reader_closed = closed.clone();
+        let pong_sender = message_sender.clone();
+        let reader_task = tokio::spawn(async move {
+            let mut framer = LineFramer::default();
+            let mut buf = [0; 4096];
+            'read: while let Ok(read @ 1..) = reader.read(&mut buf).await {
+                framer.push(&buf[..read]);
+
+                while let Some(line) = framer.next_line() {
+                    let Some(irc_message) = IRCMessage::parse(&net::decode_line(&line)) else {
+                        continue;
+                    };
+                    tracing::debug!(%irc_message, "received");
+
+                    if let IRCCommand::Ping = irc_message.command {
+                        let _ = pong_sender.send(String::from("PONG :tmi.twitch.tv\r\n"));
+                    }
+
+                    if irc_message_sender.send(irc_message).is_err() {
+                        break 'read;
+                    }
+                }
+            }
+
+            // before `irc_message_sender` is dropped, so it's set once `recv` returns `None`
+            reader_closed.store(true, Ordering::Relaxed);
+        });
+
+        if !capabilities.is_empty() {
+            message_sender.send(format!("CAP REQ :{}\r\n", capabilities.join(" ")))?;
+
+            let received =
+                tokio::time::timeout(Duration::from_secs(5), irc_message_receiver.recv()).await?;
+            let received = received.ok_or_else(|| anyhow::anyhow!("connection closed"))?;
+            check_cap_reply(&received.command)?;
+        }
+
+        // anonymous otherwise, see `IRC::anonymous_nick`
+        if let Some(auth_token) = auth_token {
+            message_sender.send(format!("PASS oauth:{auth_token}\r\n"))?;
+        }
+
+        message_sender.send(format!("NICK {}\r\n", nick))?;
+
+        message_sender.send(format!("JOIN #{channel}\r\n"))?;
+
+        Ok(Self {
+            irc_message_receiver,
+            message_sender,
+            tasks: [writer_task, reader_task],
+            closed,
+            address: address.to_string(),
+            auth_token: auth_token.map(str::to_string),
+            capabilities: capabilities.to_vec(),
+            rate_limiter: RateLimiter::default(),
+            queue: VecDeque::new(),
+            channels: vec![channel.to_string()],
+            nick: nick.to_string(),
+        })
+    }
+
+    /// Queues the message and sends it as soon as the rate limit allows, see
+    /// [`AsyncIRC::flush`], returns the `client-nonce` it's sent with
+    pub fn send_message(&mut self, channel: &str, message: &str) -> anyhow::Result<String> {
+        let outgoing = Outgoing::new(channel, message)?;
+        let nonce = outgoing.nonce.clone();
+        self.queue.push_back(outgoing);
+
+        self.flush()?;
+        Ok(nonce)
+    }
+
+    /// Sends queued messages while the rate limit allows, this needs to be called regularly
+    /// for the rest of the queue to go out
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        // the connection might be gone, keep the messages for after reconnecting
+        if self.is_closed() || self.message_sender.is_closed() {
+            return Ok(());
+        }
+
+        while !self.queue.is_empty() && self.rate_limiter.try_acquire(Instant::now()) {
+            let Some(outgoing) = self.queue.pop_front() else {
+                break;
+            };
+
+            self.message_sender.send(outgoing.line())?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends `line` as it is, for commands there's no method for, it doesn't wait for the rate
+    /// limit
+    pub fn send_raw(&mut self, line: &str) -> anyhow::Result<()> {
+        // one line, a `\r\n` in it would send a second command
+        anyhow::ensure!(
+            !line.contains(['\r', '\n']),
+            "raw lines can't contain line breaks"
+        );
+
+        self.message_sender.send(format!("{line}\r\n"))?;
+
+        Ok(())
+    }
+
+    /// Messages that are waiting to be sent, oldest first
+    pub fn queued(&self) -> &VecDeque<Outgoing> {
+        &self.queue
+    }
+
+    /// Removes everything from the queue, returning what won't be sent anymore
+    pub fn cancel_queued(&mut self) -> Vec<Outgoing> {
+        self.queue.drain(..).collect()
+    }
+
+    /// Whether the connection was closed, after which nothing else will be received
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    /// Waits for the next message, `None` means the connection was closed
+    pub async fn recv(&mut self) -> Option<IRCMessage> {
+        self.irc_message_receiver.recv().await
+    }
+
+    /// Drops the current connection and goes through the whole handshake again,
+    /// rejoining every channel
+    #[tracing::instrument(skip(self))]
+    pub async fn reconnect(&mut self) -> anyhow::Result<()> {
+        let channels = self.channels.clone();
+        let (first, rest) = channels
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("not in any channel"))?;
+
+        let irc = AsyncIRC::new(
+            &self.address,
+            self.auth_token.as_deref(),
+            &self.nick,
+            &self.capabilities,
+            first,
+        )
+        .await?;
+
+        // the rate limit is per account, and queued messages are still meant to go out
+        let rate_limiter = std::mem::take(&mut self.rate_limiter);
+        let queue = std::mem::take(&mut self.queue);
+        *self = irc;
+        self.rate_limiter = rate_limiter;
+        self.queue = queue;
+
+        for channel in rest {
+            self.join(channel)?;
+        }
+
+        self.flush()
+    }
+
+    pub fn join(&mut self, channel: &str) -> anyhow::Result<()> {
+        if self.channels.iter().any(|joined| joined == channel) {
+            return Ok(());
+        }
+
+        self.message_sender.send(format!("JOIN #{channel}\r\n"))?;
+        self.channels.push(channel.to_string());
+
+        Ok(())
+    }
+
+    pub fn part(&mut self, channel: &str) -> anyhow::Result<()> {
+        self.message_sender.send(format!("PART #{channel}\r\n"))?;
+        self.channels.retain(|joined| joined != channel);
+
+        Ok(())
+    }
+}
+
+impl Drop for AsyncIRC {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader, Lines},
+        net::{tcp::OwnedReadHalf, TcpListener},
+    };
+
+    use super::*;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// The next `count` lines the client sent
+    async fn next_lines(lines: &mut Lines<BufReader<OwnedReadHalf>>, count: usize) -> Vec<String> {
+        let mut next = Vec::new();
+        for _ in 0..count {
+            let line = tokio::time::timeout(Duration::from_secs(1), lines.next_line())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            next.push(line);
+        }
+        next
+    }
+
+    #[test]
+    fn test_send_and_receive() {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+
+            let mut irc = AsyncIRC::new(&address, None, "justinfan123", &[], "foo")
+                .await
+                .unwrap();
+            let (connection, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = connection.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            assert_eq!(
+                next_lines(&mut lines, 2).await,
+                ["NICK justinfan123", "JOIN #foo"]
+            );
+
+            let nonce = irc.send_message("foo", "hi chat").unwrap();
+            assert_eq!(
+                next_lines(&mut lines, 1).await,
+                [format!("@client-nonce={nonce} PRIVMSG #foo :hi chat")]
+            );
+            assert!(irc.send_message("foo", "hi\r\nPART #foo").is_err());
+
+            writer
+                .write_all(b"@display-name=Bar :bar!bar@bar.tmi.twitch.tv PRIVMSG #foo :hello\r\nPING :tmi.twitch.tv\r\n")
+                .await
+                .unwrap();
+            let received = irc.recv().await.unwrap();
+            assert_eq!(received.sender(), Some("Bar"));
+            assert_eq!(received.message(), Some("hello"));
+            assert!(matches!(
+                irc.recv().await.unwrap().command,
+                IRCCommand::Ping
+            ));
+            assert_eq!(next_lines(&mut lines, 1).await, ["PONG :tmi.twitch.tv"]);
+        });
+    }
+
+    #[test]
+    fn test_queue_and_reconnect() {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+
+            let mut irc = AsyncIRC::new(&address, None, "justinfan123", &[], "foo")
+                .await
+                .unwrap();
+            let _first = listener.accept().await.unwrap();
+
+            // one more than the limit, the last one waits instead of failing
+            for i in 0..21 {
+                irc.send_message("foo", &i.to_string()).unwrap();
+            }
+            assert_eq!(irc.queued().len(), 1);
+
+            irc.join("bar").unwrap();
+            irc.reconnect().await.unwrap();
+            let (connection, _) = listener.accept().await.unwrap();
+            let (reader, _writer) = connection.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            assert_eq!(
+                next_lines(&mut lines, 3).await,
+                ["NICK justinfan123", "JOIN #foo", "JOIN #bar"]
+            );
+
+            // reconnecting doesn't reset the limit
+            irc.flush().unwrap();
+            assert_eq!(irc.queued().len(), 1);
+            assert_eq!(irc.cancel_queued()[0].message, "20");
+        });
+    }
+
+    #[test]
+    fn test_keeps_messages_after_close() {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+
+            let mut irc = AsyncIRC::new(&address, None, "justinfan123", &[], "foo")
+                .await
+                .unwrap();
+            drop(listener.accept().await.unwrap());
+
+            // the writer doesn't know yet, the reader does
+            assert!(irc.recv().await.is_none());
+            assert!(irc.is_closed());
+            irc.send_message("foo", "hi chat").unwrap();
+            assert_eq!(irc.queued().len(), 1);
+        });
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    cell::{Cell, RefCell},
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+use twitcher::{HookAction, IRCCommand, IRCMessage};
+
+/// An [`crate::config::AutoReplyRule`] with its trigger compiled
+pub struct AutoReply {
+    pub trigger: Regex,
+    /// `{user}` and `{channel}` are replaced
+    pub response: String,
+    pub cooldown: Duration,
+    /// when this rule's answer last went out, shared by all channels
+    pub last_sent: Cell<Option<Instant>>,
+    /// the channel and text of an answer that main hasn't sent yet
+    pub pending: RefCell<Option<(String, String)>>,
+}
+
+impl AutoReply {
+    /// The response to `message`, if it matches the trigger and the rule isn't cooling down, the
+    /// cooldown only starts once [`Self::settle`] hears that it was sent
+    pub fn respond(&self, message: &IRCMessage, now: Instant) -> Option<HookAction> {
+        let IRCCommand::Privmsg {
+            channel,
+            message: text,
+        } = &message.command
+        else {
+            return None;
+        };
+
+        if !self.trigger.is_match(text) {
+            return None;
+        }
+
+        if self
+            .last_sent
+            .get()
+            .is_some_and(|last_sent| now.duration_since(last_sent) < self.cooldown)
+        {
+            tracing::debug!(trigger = %self.trigger, "auto reply is cooling down");
+            return None;
+        }
+
+        let user = message.sender().unwrap_or_default();
+        let response = self
+            .response
+            .replace("{user}", user)
+            .replace("{channel}", channel);
+        self.pending
+            .replace(Some((channel.clone(), response.clone())));
+
+        Some(HookAction::Send {
+            channel: channel.clone(),
+            message: response,
+        })
+    }
+
+    /// Starts the cooldown if the pending answer is one of the `(channel, message)`s that were
+    /// `sent`, and forgets it either way
+    pub fn settle(&self, sent: &[(String, String)], now: Instant) {
+        if self
+            .pending
+            .take()
+            .is_some_and(|pending| sent.contains(&pending))
+        {
+            self.last_sent.set(Some(now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_reply() {
+        let auto_reply = AutoReply {
+            trigger: Regex::new("^!discord").unwrap(),
+            response: String::from("@{user} join at example.com"),
+            cooldown: Duration::from_secs(30),
+            last_sent: Cell::new(None),
+            pending: RefCell::new(None),
+        };
+        let message = IRCMessage::parse(
+            "@display-name=Foo :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :!discord pls\r\n",
+        )
+        .unwrap();
+        let start = Instant::now();
+
+        assert_eq!(
+            auto_reply.respond(&message, start),
+            Some(HookAction::Send {
+                channel: String::from("bar"),
+                message: String::from("@Foo join at example.com")
+            })
+        );
+        // it wasn't sent, so there's no cooldown
+        auto_reply.settle(&[], start);
+        assert!(auto_reply.respond(&message, start).is_some());
+
+        auto_reply.settle(
+            &[(
+                String::from("bar"),
+                String::from("@Foo join at example.com"),
+            )],
+            start,
+        );
+        assert_eq!(
+            auto_reply.respond(&message, start + Duration::from_secs(10)),
+            None
+        );
+        assert!(auto_reply
+            .respond(&message, start + Duration::from_secs(30))
+            .is_some());
+
+        let message = IRCMessage::parse(
+            "@display-name= :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :!discord\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            auto_reply.respond(&message, start + Duration::from_secs(30)),
+            Some(HookAction::Send {
+                channel: String::from("bar"),
+                message: String::from("@foo join at example.com")
+            })
+        );
+
+        let message =
+            IRCMessage::parse(":foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :what's the !discord\r\n")
+                .unwrap();
+        assert_eq!(
+            auto_reply.respond(&message, start + Duration::from_secs(60)),
+            None
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use chrono::{DateTime, Local};
+
+pub struct Chatter {
+    pub name: String,
+    pub last_seen: DateTime<Local>,
+}
+
+/// The people who talked recently, most recent first
+///
+/// Once there are more than `capacity` chatters the ones who haven't talked in the longest time are dropped
+pub struct Chatters {
+    chatters: Vec<Chatter>,
+    capacity: usize,
+}
+
+impl Chatters {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            chatters: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// Records that `name` sent a message at `at`
+    pub fn seen(&mut self, name: &str, at: DateTime<Local>) {
+        self.chatters.retain(|chatter| chatter.name != name);
+        self.chatters.insert(
+            0,
+            Chatter {
+                name: name.to_string(),
+                last_seen: at,
+            },
+        );
+        self.chatters.truncate(self.capacity);
+    }
+
+    /// Drops the least recent chatters if there are more than `capacity` now
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.chatters.truncate(capacity);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Chatter> {
+        self.chatters.iter()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.chatters.iter().map(|chatter| chatter.name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    #[test]
+    fn test_chatters_eviction() {
+        let start = Local::now();
+        let mut chatters = Chatters::new(2);
+
+        chatters.seen("foo", start);
+        chatters.seen("bar", start + TimeDelta::seconds(1));
+        chatters.seen("foo", start + TimeDelta::seconds(2));
+        assert_eq!(chatters.names().collect::<Vec<_>>(), ["foo", "bar"]);
+        assert_eq!(
+            chatters.iter().next().unwrap().last_seen,
+            start + TimeDelta::seconds(2)
+        );
+
+        // bar is the least recent, so it goes
+        chatters.seen("baz", start + TimeDelta::seconds(3));
+        assert_eq!(chatters.names().collect::<Vec<_>>(), ["baz", "foo"]);
+    }
+}
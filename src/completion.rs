@@ -0,0 +1,129 @@
+//! Tab completion of the word before the cursor in the compose line
+
+/// An in progress completion, repeated Tab/Shift-Tab cycles through the candidates
+pub struct Completion {
+    /// byte offset where the completed word starts
+    start: usize,
+    /// byte offset where the currently inserted candidate ends
+    end: usize,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+impl Completion {
+    /// Starts completing the word that ends at byte `cursor` in `line`
+    ///
+    /// Names complete to `@name`, a word that already starts with `@` only completes names
+    pub fn new<'a>(
+        line: &str,
+        cursor: usize,
+        names: impl IntoIterator<Item = &'a str>,
+        emotes: &[String],
+    ) -> Option<Self> {
+        let start = line[..cursor]
+            .rfind(char::is_whitespace)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let word = &line[start..cursor];
+        if word.is_empty() {
+            return None;
+        }
+
+        let (mention, prefix) = match word.strip_prefix('@') {
+            Some(prefix) => (true, prefix.to_lowercase()),
+            None => (false, word.to_lowercase()),
+        };
+
+        let mut candidates = names
+            .into_iter()
+            .filter(|name| name.to_lowercase().starts_with(&prefix))
+            .map(|name| format!("@{name}"))
+            .collect::<Vec<_>>();
+
+        if !mention {
+            candidates.extend(
+                emotes
+                    .iter()
+                    .filter(|emote| emote.to_lowercase().starts_with(&prefix))
+                    .cloned(),
+            );
+        }
+
+        if candidates.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            start,
+            end: cursor,
+            candidates,
+            index: 0,
+        })
+    }
+
+    /// Puts the current candidate into `line`, returns the byte offset of the end of it
+    pub fn apply(&mut self, line: &mut String) -> usize {
+        let candidate = &self.candidates[self.index];
+        line.replace_range(self.start..self.end, candidate);
+        self.end = self.start + candidate.len();
+
+        self.end
+    }
+
+    /// Moves to the next candidate (or the previous one if `forward` is false) and applies it
+    pub fn cycle(&mut self, line: &mut String, forward: bool) -> usize {
+        let len = self.candidates.len();
+        self.index = if forward {
+            (self.index + 1) % len
+        } else {
+            (self.index + len - 1) % len
+        };
+
+        self.apply(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completion() {
+        let names = ["Foo", "foobar", "baz"];
+        let emotes = vec![String::from("forsenE"), String::from("Kappa")];
+
+        let mut line = String::from("hi fo");
+        let mut completion = Completion::new(&line, line.len(), names, &emotes).unwrap();
+
+        assert_eq!(completion.apply(&mut line), 7);
+        assert_eq!(line, "hi @Foo");
+
+        completion.cycle(&mut line, true);
+        assert_eq!(line, "hi @foobar");
+
+        completion.cycle(&mut line, true);
+        assert_eq!(line, "hi forsenE");
+
+        // wraps around
+        completion.cycle(&mut line, true);
+        assert_eq!(line, "hi @Foo");
+
+        completion.cycle(&mut line, false);
+        assert_eq!(line, "hi forsenE");
+    }
+
+    #[test]
+    fn test_mention_completion() {
+        let emotes = vec![String::from("bazinga")];
+
+        let mut line = String::from("@ba says hi");
+        let mut completion = Completion::new(&line, 3, ["baz"], &emotes).unwrap();
+
+        assert_eq!(completion.apply(&mut line), 4);
+        assert_eq!(line, "@baz says hi");
+
+        // completing from the middle of nowhere does nothing
+        assert!(Completion::new("hi ", 3, ["baz"], &emotes).is_none());
+        assert!(Completion::new("hi qux", 6, ["baz"], &emotes).is_none());
+    }
+}
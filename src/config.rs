@@ -0,0 +1,578 @@
+use std::{
+    cell::{Cell, RefCell},
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::format::{Item, StrftimeItems};
+use crossterm::style::Color;
+use regex::Regex;
+use serde::Deserialize;
+use twitcher::DEFAULT_CAPABILITIES;
+
+use crate::{args::channel_name, auto_reply::AutoReply};
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// What `yY` copies, `{time}`, `{channel}`, `{name}` and `{message}` are replaced
+    pub quote_format: String,
+    /// Checked in order, the first rule that matches a message decides its color
+    pub highlights: Vec<HighlightRule>,
+    /// Emotes that Tab completes to, along with the names of recent chatters
+    pub emotes: Vec<String>,
+    /// How many recent chatters to remember
+    pub chatters_limit: usize,
+    /// Columns taken by the sidebar, when it's shown
+    pub sidebar_width: u16,
+    /// Show the name only once for consecutive messages from the same person
+    pub group_messages: bool,
+    /// Rows of chat shown at most, right above the compose line, 0 fills the screen
+    pub max_messages: usize,
+    /// Messages we answer automatically, checked against every incoming message
+    pub auto_replies: Vec<AutoReplyRule>,
+    /// Put in front of messages when more than one channel is joined, `{channel}` is replaced
+    pub channel_prefix: String,
+    /// Show channels with a `#` in front, in the tab bar, the sidebar and `#{channel}` in
+    /// templates
+    pub channel_hash: bool,
+    /// Show runs of spaces in messages as a single one
+    pub collapse_whitespace: bool,
+    /// Columns names are right-aligned in, 0 means they aren't aligned
+    pub nick_width: usize,
+    /// Combining marks kept on a single character, against "zalgo" text, 0 means no limit
+    pub max_combining_marks: usize,
+    /// Add an invisible character to a message that repeats the last one, which twitch would
+    /// drop otherwise, instead of not sending it
+    pub bypass_duplicates: bool,
+    /// Characters a message can have, longer ones and pasted text with line breaks are split
+    /// into several messages
+    pub max_message_length: usize,
+    /// Send split messages without asking first
+    pub auto_split: bool,
+    /// Scroll the chat rows with the terminal and only redraw the ones that changed, instead of
+    /// clearing the whole screen for every frame
+    pub scroll_region: bool,
+    /// Seconds without a key press before a scrolled up view follows new messages again, 0
+    /// means it stays until scrolled back down
+    pub scroll_resume: u64,
+    /// How messages deleted by moderators are shown
+    pub deleted: DeletedMessages,
+    /// How closely messages are packed
+    pub density: Density,
+    /// A chrono format string for the time in front of messages, [`RELATIVE_TIMESTAMPS`] for
+    /// how long ago they were sent, or empty for no timestamps
+    pub timestamp_format: String,
+    /// Which messages get a desktop notification while the terminal isn't focused
+    pub notifications: Notifications,
+    /// What plays on subs, raids and the like
+    pub sounds: Sounds,
+    /// Chat commands that ask for a `y` before they're sent
+    pub confirm_commands: Vec<String>,
+    /// Accounts `:account` can switch to
+    pub accounts: Vec<Account>,
+    /// Shown in front of the compose line, `{channel}` and `{nick}` are replaced
+    pub prompt: String,
+    /// Draw emotes as images in terminals that support kitty's or iTerm2's image protocol
+    pub emote_images: bool,
+    /// Seconds after which messages are drawn dimmed, 0 means never
+    pub fade_after: u64,
+    /// Let `:raw` send lines to twitch as they are, for debugging
+    pub allow_raw: bool,
+    /// Draw our messages dimmed until twitch confirms them, and red if it doesn't
+    pub confirm_sent: bool,
+    /// Requested from twitch when connecting
+    pub capabilities: Vec<String>,
+    /// Files with more channels to join, see [`parse_channels`], relative to the config's
+    /// directory
+    pub include: Vec<PathBuf>,
+    /// Read from the `include` files when loading
+    #[serde(skip)]
+    pub channels: Vec<ChannelEntry>,
+}
+
+/// A channel from an `include` file, with the words that are highlighted in it
+#[derive(Debug, PartialEq)]
+pub struct ChannelEntry {
+    pub channel: String,
+    pub highlights: Vec<String>,
+    pub color: Color,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Account {
+    pub nick: String,
+    pub token: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Notifications {
+    Off,
+    /// messages that mention our nick
+    #[default]
+    Mentions,
+    /// mentions and messages matching a highlight rule
+    Highlights,
+}
+
+/// For each event `"bell"` rings the terminal bell, anything else is a shell command to run and
+/// empty is silent
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sounds {
+    /// subs, resubs and gifted subs
+    pub sub: String,
+    pub raid: String,
+    /// messages cheering with bits
+    pub bits: String,
+    /// the channel starting to host another one
+    pub host: String,
+    /// Seconds before the same event plays its sound again
+    pub cooldown: u64,
+}
+
+impl Default for Sounds {
+    fn default() -> Self {
+        Self {
+            sub: String::new(),
+            raid: String::new(),
+            bits: String::new(),
+            host: String::new(),
+            cooldown: 5,
+        }
+    }
+}
+
+pub const RELATIVE_TIMESTAMPS: &str = "relative";
+
+/// Whether `format` works as a `timestamp_format`, chrono panics on invalid ones when formatting
+pub fn valid_timestamp_format(format: &str) -> bool {
+    format == RELATIVE_TIMESTAMPS
+        || StrftimeItems::new(format).all(|item| !matches!(item, Item::Error))
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeletedMessages {
+    /// not at all, like twitch does for regular viewers
+    Hide,
+    /// crossed out and dimmed, so moderators can still read them
+    Strikethrough,
+    /// `<message deleted>` in place of the text
+    #[default]
+    Placeholder,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Density {
+    /// a row for every message
+    #[default]
+    Compact,
+    /// an empty row between messages, or between groups of them with `group_messages`
+    Cozy,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HighlightRule {
+    pub pattern: String,
+    pub color: Color,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AutoReplyRule {
+    pub trigger: String,
+    pub response: String,
+    /// Seconds before the rule answers again
+    #[serde(default = "default_cooldown")]
+    pub cooldown: u64,
+}
+
+fn default_cooldown() -> u64 {
+    30
+}
+
+/// A [`HighlightRule`] with its pattern compiled
+pub struct Highlight {
+    pub regex: Regex,
+    pub color: Color,
+    /// only messages in this channel are highlighted, for the words from an `include` file
+    pub channel: Option<String>,
+}
+
+impl Highlight {
+    pub fn matches(&self, channel: &str, text: &str) -> bool {
+        self.channel.as_ref().is_none_or(|only| only == channel) && self.regex.is_match(text)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            quote_format: String::from("[{time}] {name}: {message}"),
+            highlights: Vec::new(),
+            emotes: Vec::new(),
+            chatters_limit: 500,
+            sidebar_width: 24,
+            group_messages: false,
+            max_messages: 0,
+            auto_replies: Vec::new(),
+            channel_prefix: String::from("[#{channel}] "),
+            channel_hash: true,
+            collapse_whitespace: false,
+            nick_width: 0,
+            max_combining_marks: 4,
+            bypass_duplicates: true,
+            max_message_length: 500,
+            auto_split: false,
+            scroll_region: false,
+            scroll_resume: 30,
+            deleted: DeletedMessages::default(),
+            density: Density::default(),
+            timestamp_format: String::from("%H:%M"),
+            notifications: Notifications::default(),
+            sounds: Sounds::default(),
+            confirm_commands: ["/ban", "/unban", "/timeout", "/clear", "/raid"]
+                .map(String::from)
+                .to_vec(),
+            accounts: Vec::new(),
+            prompt: String::new(),
+            emote_images: false,
+            fade_after: 0,
+            allow_raw: false,
+            confirm_sent: false,
+            capabilities: DEFAULT_CAPABILITIES.map(String::from).to_vec(),
+            include: Vec::new(),
+            channels: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config from `path`, or from the default location if no path is given
+    ///
+    /// A missing config file at the default location is not an error, the defaults are used instead
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let mut config: Self = match std::fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents)
+                .map_err(|e| anyhow::anyhow!("invalid config {}: {e}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !explicit => {
+                return Ok(Self::default())
+            }
+            Err(e) => return Err(anyhow::anyhow!("failed to read {}: {e}", path.display())),
+        };
+        anyhow::ensure!(
+            config.max_message_length > 0,
+            "invalid config {}: max_message_length has to be at least 1",
+            path.display()
+        );
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for include in &config.include {
+            let include = dir.join(include);
+            let contents = std::fs::read_to_string(&include)
+                .map_err(|e| anyhow::anyhow!("failed to read {}: {e}", include.display()))?;
+            let channels = parse_channels(&contents)
+                .map_err(|e| anyhow::anyhow!("invalid channels file {}: {e}", include.display()))?;
+            config.channels.extend(channels);
+        }
+
+        Ok(config)
+    }
+
+    /// Compiles the highlight rules, rules with invalid patterns are left out and returned as errors
+    pub fn highlights(&self) -> (Vec<Highlight>, Vec<String>) {
+        let mut highlights = Vec::new();
+        let mut errors = Vec::new();
+
+        for rule in &self.highlights {
+            match Regex::new(&rule.pattern) {
+                Ok(regex) => highlights.push(Highlight {
+                    regex,
+                    color: rule.color,
+                    channel: None,
+                }),
+                Err(e) => errors.push(format!("invalid highlight pattern {:?}: {e}", rule.pattern)),
+            }
+        }
+
+        for entry in self
+            .channels
+            .iter()
+            .filter(|entry| !entry.highlights.is_empty())
+        {
+            let words = entry
+                .highlights
+                .iter()
+                .map(|word| regex::escape(word))
+                .collect::<Vec<_>>();
+            // escaped words always make a valid pattern
+            let regex = Regex::new(&format!(r"(?i)\b(?:{})\b", words.join("|"))).unwrap();
+            highlights.push(Highlight {
+                regex,
+                color: entry.color,
+                channel: Some(entry.channel.clone()),
+            });
+        }
+
+        (highlights, errors)
+    }
+
+    /// Compiles the auto reply triggers, like [`Config::highlights`]
+    pub fn auto_replies(&self) -> (Vec<AutoReply>, Vec<String>) {
+        let mut auto_replies = Vec::new();
+        let mut errors = Vec::new();
+
+        for rule in &self.auto_replies {
+            match Regex::new(&rule.trigger) {
+                Ok(trigger) => auto_replies.push(AutoReply {
+                    trigger,
+                    response: rule.response.clone(),
+                    cooldown: Duration::from_secs(rule.cooldown),
+                    last_sent: Cell::new(None),
+                    pending: RefCell::new(None),
+                }),
+                Err(e) => errors.push(format!(
+                    "invalid auto reply trigger {:?}: {e}",
+                    rule.trigger
+                )),
+            }
+        }
+
+        (auto_replies, errors)
+    }
+}
+
+/// A channels file has a channel on each line, optionally followed by words to highlight in it
+/// and their color, which is yellow if it's not given:
+///
+/// ```text
+/// # comments and empty lines are skipped
+/// forsen
+/// #nymn
+/// xqc highlight=giveaway,drops color=green
+/// ```
+pub fn parse_channels(contents: &str) -> anyhow::Result<Vec<ChannelEntry>> {
+    let mut channels = Vec::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        // `#forsen` is a channel, not a comment
+        if line.is_empty() || line == "#" || line.starts_with("# ") {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let name = fields.next().unwrap_or_default();
+        let channel = channel_name(name)
+            .ok_or_else(|| anyhow::anyhow!("line {}: {name} isn't a twitch channel", number + 1))?;
+        let mut entry = ChannelEntry {
+            channel,
+            highlights: Vec::new(),
+            color: Color::Yellow,
+        };
+
+        for field in fields {
+            match field.split_once('=') {
+                Some(("highlight", words)) => entry.highlights.extend(
+                    words
+                        .split(',')
+                        .filter(|word| !word.is_empty())
+                        .map(String::from),
+                ),
+                Some(("color", color)) => {
+                    entry.color =
+                        toml::Value::String(color.to_string())
+                            .try_into()
+                            .map_err(|_| {
+                                anyhow::anyhow!("line {}: invalid color {color}", number + 1)
+                            })?;
+                }
+                _ => anyhow::bail!("line {}: unknown setting {field}", number + 1),
+            }
+        }
+
+        channels.push(entry);
+    }
+
+    Ok(channels)
+}
+
+/// `$XDG_CONFIG_HOME/twitcher/config.toml`, falling back to `~/.config/twitcher/config.toml`
+fn default_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_dir.join("twitcher").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accounts() {
+        let config: Config = toml::from_str(
+            r#"
+            [[accounts]]
+            nick = "me"
+            token = "abc"
+
+            [[accounts]]
+            nick = "my_bot"
+            token = "def"
+            "#,
+        )
+        .unwrap();
+
+        let nicks = config
+            .accounts
+            .iter()
+            .map(|account| account.nick.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(nicks, ["me", "my_bot"]);
+
+        assert!(toml::from_str::<Config>("[[accounts]]\nnick = \"me\"").is_err());
+    }
+
+    #[test]
+    fn test_readme_config() {
+        // the sample config in the README has to be one that loads
+        let readme = include_str!("../README.md");
+        let (_, sample) = readme.split_once("```toml\n").unwrap();
+        let (sample, _) = sample.split_once("```").unwrap();
+
+        let config: Config = toml::from_str(sample).unwrap();
+        assert_eq!(config.sounds.cooldown, 5);
+        assert!(!config.confirm_commands.is_empty());
+    }
+
+    #[test]
+    fn test_max_message_length() {
+        let path = std::env::temp_dir().join(format!("twitcher-test-{}.toml", std::process::id()));
+        std::fs::write(&path, "max_message_length = 0").unwrap();
+        let loaded = Config::load(Some(&path));
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(loaded.is_err());
+    }
+
+    #[test]
+    fn test_timestamp_format() {
+        assert!(valid_timestamp_format("%H:%M"));
+        assert!(valid_timestamp_format("%I:%M %p"));
+        assert!(valid_timestamp_format("relative"));
+        assert!(valid_timestamp_format(""));
+        assert!(!valid_timestamp_format("%H:%"));
+        assert!(!valid_timestamp_format("%Q"));
+    }
+
+    #[test]
+    fn test_highlight_rules() {
+        let config: Config = toml::from_str(
+            r##"
+            [[highlights]]
+            pattern = "!giveaway"
+            color = "yellow"
+
+            [[highlights]]
+            pattern = "(unclosed"
+            color = "red"
+
+            [[highlights]]
+            pattern = "(?i)competitor"
+            color = "#ff8800"
+            "##,
+        )
+        .unwrap();
+
+        let (highlights, errors) = config.highlights();
+
+        assert_eq!(highlights.len(), 2);
+        assert_eq!(highlights[0].color, Color::Yellow);
+        assert_eq!(
+            highlights[1].color,
+            Color::Rgb {
+                r: 0xff,
+                g: 0x88,
+                b: 0x00
+            }
+        );
+        assert!(highlights[1].regex.is_match("the COMPETITOR stream"));
+
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("(unclosed"));
+    }
+
+    #[test]
+    fn test_parse_channels() {
+        let channels =
+            parse_channels("# my channels\n\nforsen\n#Nymn highlight=giveaway,drops color=green\n")
+                .unwrap();
+        assert_eq!(
+            channels,
+            [
+                ChannelEntry {
+                    channel: String::from("forsen"),
+                    highlights: Vec::new(),
+                    color: Color::Yellow,
+                },
+                ChannelEntry {
+                    channel: String::from("nymn"),
+                    highlights: vec![String::from("giveaway"), String::from("drops")],
+                    color: Color::Green,
+                },
+            ]
+        );
+
+        assert!(parse_channels("forsen volume=3").is_err());
+        assert!(parse_channels("forsen color=nope").is_err());
+        assert!(parse_channels("not a channel!").is_err());
+
+        let config = Config {
+            channels,
+            ..Config::default()
+        };
+        let (highlights, _) = config.highlights();
+        assert!(highlights[0].matches("nymn", "GIVEAWAY soon"));
+        assert!(!highlights[0].matches("forsen", "giveaway soon"));
+        assert!(!highlights[0].matches("nymn", "giveaways soon"));
+    }
+
+    #[test]
+    fn test_auto_reply_rules() {
+        let config: Config = toml::from_str(
+            r#"
+            [[auto_replies]]
+            trigger = "^!discord"
+            response = "join at example.com"
+
+            [[auto_replies]]
+            trigger = "^!lurk"
+            response = "enjoy the lurk {user}"
+            cooldown = 5
+            "#,
+        )
+        .unwrap();
+
+        let (auto_replies, errors) = config.auto_replies();
+
+        assert!(errors.is_empty());
+        assert_eq!(auto_replies[0].cooldown, Duration::from_secs(30));
+        assert_eq!(auto_replies[1].cooldown, Duration::from_secs(5));
+    }
+}
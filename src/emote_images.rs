@@ -0,0 +1,260 @@
+//! Emotes drawn as images in terminals that can show them, everywhere else they stay text
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Cells an emote image takes up, emotes are square and a cell is about twice as tall as wide
+pub const IMAGE_WIDTH: usize = 2;
+
+/// Payload bytes per kitty graphics escape, the protocol doesn't allow larger chunks
+const KITTY_CHUNK: usize = 4096;
+
+/// The inline image protocols we know how to speak
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    Kitty,
+    /// also understood by wezterm
+    Iterm,
+}
+
+impl Protocol {
+    /// From the environment the terminal sets, `None` if it doesn't look like it shows images
+    pub fn detect() -> Option<Self> {
+        let var = |name| std::env::var(name).unwrap_or_default();
+
+        if std::env::var_os("KITTY_WINDOW_ID").is_some() || var("TERM") == "xterm-kitty" {
+            Some(Self::Kitty)
+        } else if matches!(var("TERM_PROGRAM").as_str(), "iTerm.app" | "WezTerm") {
+            Some(Self::Iterm)
+        } else {
+            None
+        }
+    }
+}
+
+enum Image {
+    Downloading,
+    /// kitty keeps the image after it's sent once and draws it by its id
+    Ready {
+        png: Vec<u8>,
+        kitty_id: u32,
+    },
+    /// not tried again, the text is shown instead
+    Failed,
+}
+
+/// Downloads emote images in the background and keeps them for as long as we run, they're
+/// also saved in the temp directory so restarts don't download them again
+pub struct EmoteImages {
+    protocol: Protocol,
+    images: HashMap<String, Image>,
+    sender: Sender<(String, Option<Vec<u8>>)>,
+    downloads: Receiver<(String, Option<Vec<u8>>)>,
+    next_kitty_id: u32,
+}
+
+impl EmoteImages {
+    pub fn new(protocol: Protocol) -> Self {
+        let (sender, downloads) = mpsc::channel();
+
+        Self {
+            protocol,
+            images: HashMap::new(),
+            sender,
+            downloads,
+            next_kitty_id: 1,
+        }
+    }
+
+    /// Starts downloading the emote with `id` unless it's been tried before
+    pub fn fetch(&mut self, id: &str) {
+        if self.images.contains_key(id) {
+            return;
+        }
+        self.images.insert(id.to_string(), Image::Downloading);
+
+        let id = id.to_string();
+        let sender = self.sender.clone();
+        std::thread::spawn(move || {
+            let png = download(&id)
+                .map_err(|e| tracing::warn!(%id, %e, "failed to download an emote"))
+                .ok();
+            let _ = sender.send((id, png));
+        });
+    }
+
+    /// Takes in finished downloads, kitty gets sent the new images here so drawing them later
+    /// only needs their id
+    pub fn poll(&mut self, stdout: &mut impl Write) -> std::io::Result<()> {
+        while let Ok((id, png)) = self.downloads.try_recv() {
+            let Some(png) = png else {
+                self.images.insert(id, Image::Failed);
+                continue;
+            };
+
+            let kitty_id = self.next_kitty_id;
+            self.next_kitty_id += 1;
+            if self.protocol == Protocol::Kitty {
+                write!(stdout, "{}", kitty_transmit(kitty_id, &png))?;
+            }
+            self.images.insert(id, Image::Ready { png, kitty_id });
+        }
+
+        Ok(())
+    }
+
+    /// Escapes that draw the emote at the cursor, `None` while it's not downloaded
+    pub fn image(&self, id: &str) -> Option<String> {
+        let Some(Image::Ready { png, kitty_id }) = self.images.get(id) else {
+            return None;
+        };
+
+        Some(match self.protocol {
+            // C=1 leaves the cursor where it is
+            Protocol::Kitty => format!("\x1b_Ga=p,i={kitty_id},c={IMAGE_WIDTH},r=1,C=1,q=2\x1b\\"),
+            Protocol::Iterm => format!(
+                "\x1b]1337;File=inline=1;width={IMAGE_WIDTH};height=1;preserveAspectRatio=1:{}\x07",
+                STANDARD.encode(png)
+            ),
+        })
+    }
+
+    /// Removes the images drawn in the last frame, clearing the screen doesn't do it for kitty
+    pub fn clear(&self) -> &'static str {
+        match self.protocol {
+            // lowercase `a` keeps the image data around for the next frame
+            Protocol::Kitty => "\x1b_Ga=d,d=a,q=2\x1b\\",
+            Protocol::Iterm => "",
+        }
+    }
+}
+
+fn cache_path(id: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("twitcher-emotes")
+        .join(format!("{id}.png"))
+}
+
+fn download(id: &str) -> anyhow::Result<Vec<u8>> {
+    // ids come from chat, they shouldn't be able to point anywhere else
+    anyhow::ensure!(
+        id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        "invalid emote id"
+    );
+
+    let path = cache_path(id);
+    if let Ok(png) = std::fs::read(&path) {
+        return Ok(png);
+    }
+
+    let url = format!("https://static-cdn.jtvnw.net/emoticons/v2/{id}/static/dark/1.0");
+    let png = reqwest::blocking::get(url)?
+        .error_for_status()?
+        .bytes()?
+        .to_vec();
+
+    if let Some(dir) = path.parent() {
+        if let Err(e) = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&path, &png)) {
+            tracing::debug!(%e, "failed to cache an emote");
+        }
+    }
+
+    Ok(png)
+}
+
+/// Sends `png` to kitty to keep as image `id`, in as many escapes as it takes
+fn kitty_transmit(id: u32, png: &[u8]) -> String {
+    let encoded = STANDARD.encode(png);
+    let chunks = encoded.as_bytes().chunks(KITTY_CHUNK).collect::<Vec<_>>();
+
+    let mut escapes = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        // base64 is ASCII
+        let chunk = std::str::from_utf8(chunk).unwrap();
+        if i == 0 {
+            escapes.push_str(&format!(
+                "\x1b_Ga=t,f=100,i={id},q=2,m={more};{chunk}\x1b\\"
+            ));
+        } else {
+            escapes.push_str(&format!("\x1b_Gm={more};{chunk}\x1b\\"));
+        }
+    }
+
+    escapes
+}
+
+/// The emotes in a message from its `emotes` tag, as their id and the text they replace
+///
+/// The tag looks like `25:0-4,12-16/1902:6-10`, with positions counted in characters
+pub fn parse_emotes(tag: &str, text: &str) -> Vec<(String, String)> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut emotes = Vec::<(String, String)>::new();
+
+    for emote in tag.split('/') {
+        let Some((id, ranges)) = emote.split_once(':') else {
+            continue;
+        };
+
+        for range in ranges.split(',') {
+            let Some((start, end)) = range.split_once('-') else {
+                continue;
+            };
+            let (Ok(start), Ok(end)) = (start.parse::<usize>(), end.parse::<usize>()) else {
+                continue;
+            };
+            let Some(name) = chars.get(start..=end) else {
+                continue;
+            };
+
+            let name = name.iter().collect::<String>();
+            if !emotes.iter().any(|(_, existing)| *existing == name) {
+                emotes.push((id.to_string(), name));
+            }
+        }
+    }
+
+    emotes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_emotes() {
+        assert_eq!(
+            parse_emotes("25:0-4,12-16/1902:6-10", "Kappa Keepo Kappa"),
+            [
+                (String::from("25"), String::from("Kappa")),
+                (String::from("1902"), String::from("Keepo"))
+            ]
+        );
+        // positions are in characters, not bytes
+        assert_eq!(
+            parse_emotes("25:2-6", "é Kappa"),
+            [(String::from("25"), String::from("Kappa"))]
+        );
+        assert!(parse_emotes("25:10-14", "Kappa").is_empty());
+        assert!(parse_emotes("", "Kappa").is_empty());
+    }
+
+    #[test]
+    fn test_kitty_transmit() {
+        assert_eq!(
+            kitty_transmit(3, b"png"),
+            "\x1b_Ga=t,f=100,i=3,q=2,m=0;cG5n\x1b\\"
+        );
+
+        let escapes = kitty_transmit(3, &[0; KITTY_CHUNK]);
+        assert_eq!(escapes.matches("\x1b_G").count(), 2);
+        assert!(escapes.contains("m=1;"));
+        assert!(escapes.ends_with("\x1b\\"));
+    }
+}
@@ -0,0 +1,166 @@
+//! Redrawing only the chat rows that changed since the last frame, new messages are scrolled in
+//! by the terminal with a scroll region (DECSTBM) instead of reprinting the whole screen
+
+use std::io::{self, Write};
+
+use crossterm::{cursor, style, terminal, QueueableCommand};
+
+/// Everything around the chat rows that has to stay the same for them to be updated in place
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Screen {
+    /// the first chat row
+    pub top: u16,
+    /// the row below the last chat row
+    pub bottom: u16,
+    pub columns: u16,
+    pub rows: u16,
+    pub foreground: style::Color,
+    pub background: style::Color,
+}
+
+/// The chat rows as they were drawn in the last frame, each as the bytes that draw it from its
+/// first column
+#[derive(Default)]
+pub struct ChatRows {
+    screen: Option<Screen>,
+    rows: Vec<Vec<u8>>,
+}
+
+impl ChatRows {
+    /// Whether what's on the screen can be updated to a frame on `screen`
+    pub fn can_update(&self, screen: &Screen) -> bool {
+        self.screen.as_ref() == Some(screen)
+    }
+
+    /// Forgets what's on the screen, e.g. after something else drew over it, so the next frame
+    /// is drawn from scratch
+    pub fn invalidate(&mut self) {
+        self.screen = None;
+    }
+
+    /// Draws every row on a screen that was just cleared
+    pub fn replace(
+        &mut self,
+        out: &mut impl Write,
+        screen: Screen,
+        rows: Vec<Vec<u8>>,
+    ) -> io::Result<()> {
+        for (i, row) in rows.iter().enumerate().filter(|(_, row)| !row.is_empty()) {
+            out.queue(cursor::MoveTo(0, screen.top + i as u16))?;
+            out.write_all(row)?;
+        }
+
+        self.screen = Some(screen);
+        self.rows = rows;
+        Ok(())
+    }
+
+    /// Gets from the last frame's rows to `rows`, see [`ChatRows::can_update`], by scrolling the
+    /// chat rows up as far as they moved and then drawing the ones that still differ
+    ///
+    /// Each changed row is cleared to the end of the screen, what's drawn to the right of the
+    /// chat has to be drawn again after this
+    pub fn update(
+        &mut self,
+        out: &mut impl Write,
+        screen: Screen,
+        rows: Vec<Vec<u8>>,
+    ) -> io::Result<()> {
+        let shift = scroll_shift(&self.rows, &rows);
+        if shift > 0 {
+            // the region is 1-based and inclusive, resetting it moves the cursor home
+            write!(out, "\x1b[{};{}r", screen.top + 1, screen.bottom)?;
+            out.queue(terminal::ScrollUp(shift as u16))?;
+            write!(out, "\x1b[r")?;
+        }
+
+        let empty = Vec::new();
+        let on_screen = self
+            .rows
+            .iter()
+            .skip(shift)
+            .chain(std::iter::repeat(&empty))
+            .take(rows.len());
+
+        for (i, (row, shown)) in rows.iter().zip(on_screen).enumerate() {
+            if row != shown {
+                out.queue(cursor::MoveTo(0, screen.top + i as u16))?;
+                out.queue(terminal::Clear(terminal::ClearType::UntilNewLine))?;
+                out.write_all(row)?;
+            }
+        }
+
+        self.screen = Some(screen);
+        self.rows = rows;
+        Ok(())
+    }
+}
+
+/// How many rows `old` has to be moved up by to be the start of `new`, 0 if they're the same or
+/// didn't just move, which means they're redrawn where they are
+fn scroll_shift(old: &[Vec<u8>], new: &[Vec<u8>]) -> usize {
+    if old.len() != new.len() || old == new {
+        return 0;
+    }
+
+    (1..old.len())
+        .find(|shift| old[*shift..] == new[..new.len() - shift])
+        // the rows that are left have nothing blank to be scrolled into
+        .filter(|shift| old[*shift..].iter().any(|row| !row.is_empty()))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(rows: &[&str]) -> Vec<Vec<u8>> {
+        rows.iter().map(|row| row.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_scroll_shift() {
+        let old = rows(&["", "a", "b", "c"]);
+        assert_eq!(scroll_shift(&old, &rows(&["a", "b", "c", "d"])), 1);
+        assert_eq!(scroll_shift(&old, &rows(&["b", "c", "d", "e"])), 2);
+        assert_eq!(scroll_shift(&old, &old), 0);
+        // something changed in place, e.g. a deleted message
+        assert_eq!(scroll_shift(&old, &rows(&["", "a", "x", "c"])), 0);
+        // everything's new
+        assert_eq!(scroll_shift(&old, &rows(&["w", "x", "y", "z"])), 0);
+        assert_eq!(scroll_shift(&old, &rows(&["a", "b"])), 0);
+    }
+
+    #[test]
+    fn test_update() {
+        let screen = Screen {
+            top: 1,
+            bottom: 4,
+            columns: 80,
+            rows: 5,
+            foreground: style::Color::Reset,
+            background: style::Color::Reset,
+        };
+        let mut chat_rows = ChatRows::default();
+        assert!(!chat_rows.can_update(&screen));
+
+        let mut out = Vec::new();
+        chat_rows
+            .replace(&mut out, screen, rows(&["", "a", "b"]))
+            .unwrap();
+        assert!(chat_rows.can_update(&screen));
+
+        let mut out = Vec::new();
+        chat_rows
+            .update(&mut out, screen, rows(&["a", "b", "c"]))
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        // scrolled by one in rows 2 to 4, then only the new row is drawn
+        assert!(out.starts_with("\x1b[2;4r\x1b[1S\x1b[r"));
+        assert!(out.ends_with("\x1b[4;1H\x1b[Kc"));
+        assert!(!out.contains('a'));
+
+        chat_rows.invalidate();
+        assert!(!chat_rows.can_update(&screen));
+    }
+}
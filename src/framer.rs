@@ -0,0 +1,212 @@
+//! Splitting what's read from twitch into lines and those into messages
+
+use std::io::{self, BufRead};
+
+use crate::{net, IRCMessage};
+
+/// Twitch's lines end well before this, anything longer without a line ending is garbage
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Splits what's read from the connection into lines, a single read can end in the middle of
+/// a line or contain several of them
+#[derive(Debug, Default)]
+pub(crate) struct LineFramer {
+    buf: Vec<u8>,
+}
+
+impl LineFramer {
+    pub(crate) fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+
+        if self.buf.len() > MAX_LINE_LENGTH && !self.buf.contains(&b'\n') {
+            tracing::warn!(length = self.buf.len(), "dropping overlong line");
+            self.buf.clear();
+        }
+    }
+
+    /// The next complete line without its `\r\n`, a bare `\n` ends a line too
+    pub(crate) fn next_line(&mut self) -> Option<Vec<u8>> {
+        let end = self.buf.iter().position(|byte| *byte == b'\n')?;
+
+        let mut line = self.buf.drain(..=end).collect::<Vec<_>>();
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+
+        Some(line)
+    }
+
+    /// Ends what's left as a line of its own, for when nothing more is coming
+    fn finish(&mut self) {
+        if !self.buf.is_empty() {
+            self.buf.push(b'\n');
+        }
+    }
+}
+
+/// Why [`messages`] couldn't give the next message
+#[derive(Debug)]
+pub enum ParseError {
+    /// reading failed, nothing comes after this
+    Io(io::Error),
+    /// a line that isn't an IRC message we understand, reading goes on after it
+    Invalid(String),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read: {e}"),
+            Self::Invalid(line) => write!(f, "failed to parse {line:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Invalid(_) => None,
+        }
+    }
+}
+
+/// The messages in what `reader` gives, framed and decoded like [`crate::IRC`] does with what
+/// it reads from twitch, e.g. to go through a log of raw lines
+///
+/// Empty lines are skipped and a last line without a line ending is still parsed
+pub fn messages<R: BufRead>(reader: R) -> impl Iterator<Item = Result<IRCMessage, ParseError>> {
+    Messages {
+        reader,
+        framer: LineFramer::default(),
+        done: false,
+    }
+}
+
+struct Messages<R> {
+    reader: R,
+    framer: LineFramer,
+    done: bool,
+}
+
+impl<R: BufRead> Iterator for Messages<R> {
+    type Item = Result<IRCMessage, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.framer.next_line() {
+                if line.is_empty() {
+                    continue;
+                }
+
+                let line = net::decode_line(&line);
+                return Some(
+                    IRCMessage::parse(&line).ok_or_else(|| ParseError::Invalid(line.into_owned())),
+                );
+            }
+
+            if self.done {
+                return None;
+            }
+
+            let read = match self.reader.fill_buf() {
+                Ok(buf) => {
+                    self.framer.push(buf);
+                    buf.len()
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(ParseError::Io(e)));
+                }
+            };
+
+            if read == 0 {
+                self.done = true;
+                self.framer.finish();
+            }
+            self.reader.consume(read);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IRCCommand;
+
+    #[test]
+    fn test_line_framer() {
+        let mut framer = LineFramer::default();
+        assert_eq!(framer.next_line(), None);
+
+        framer.push(b"PING :tmi.twitch.tv\r\n:foo!foo@foo.tmi");
+        assert_eq!(
+            framer.next_line().as_deref(),
+            Some(&b"PING :tmi.twitch.tv"[..])
+        );
+        assert_eq!(framer.next_line(), None);
+
+        // the `\r\n` split across reads
+        framer.push(b".twitch.tv PRIVMSG #bar :hi\r");
+        assert_eq!(framer.next_line(), None);
+        framer.push(b"\n\r\nJOIN #bar\n");
+        assert_eq!(
+            framer.next_line().as_deref(),
+            Some(&b":foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :hi"[..])
+        );
+        assert_eq!(framer.next_line().as_deref(), Some(&b""[..]));
+        assert_eq!(framer.next_line().as_deref(), Some(&b"JOIN #bar"[..]));
+        assert_eq!(framer.next_line(), None);
+
+        framer.push(&[b'a'; MAX_LINE_LENGTH + 1]);
+        framer.push(b"\r\n");
+        assert_eq!(framer.next_line().as_deref(), Some(&b""[..]));
+    }
+
+    #[test]
+    fn test_messages() {
+        let fixture = "PING :tmi.twitch.tv\r\n\
+            \r\n\
+            @color=#FF0000;first-msg :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :hi chat\r\n\
+            :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar\n\
+            :tmi.twitch.tv CLEARCHAT #bar :foo";
+        // a tiny buffer so lines are split across reads
+        let reader = io::BufReader::with_capacity(8, fixture.as_bytes());
+        let mut messages = messages(reader);
+
+        assert!(matches!(
+            messages.next(),
+            Some(Ok(IRCMessage {
+                command: IRCCommand::Ping,
+                ..
+            }))
+        ));
+
+        let privmsg = messages.next().unwrap().unwrap();
+        assert_eq!(
+            privmsg.tags.get("color").map(String::as_str),
+            Some("#FF0000")
+        );
+        // a tag without a value
+        assert_eq!(privmsg.tags.get("first-msg").map(String::as_str), Some(""));
+        assert!(matches!(
+            privmsg.command,
+            IRCCommand::Privmsg { channel, message } if channel == "bar" && message == "hi chat"
+        ));
+
+        assert!(matches!(
+            messages.next(),
+            Some(Err(ParseError::Invalid(line))) if line == ":foo!foo@foo.tmi.twitch.tv PRIVMSG #bar"
+        ));
+        assert!(matches!(
+            messages.next(),
+            Some(Ok(IRCMessage {
+                command: IRCCommand::ClearChat { .. },
+                ..
+            }))
+        ));
+        assert!(messages.next().is_none());
+    }
+}
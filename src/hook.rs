@@ -0,0 +1,73 @@
+use crate::IRCMessage;
+
+/// What a [`Hook`] wants done with a message
+#[derive(Clone, Debug, PartialEq)]
+pub enum HookAction {
+    /// drop the message before it's handled or shown
+    Suppress,
+    /// send a chat message, e.g. to answer a command
+    Send { channel: String, message: String },
+    /// show a note next to the message
+    Annotate(String),
+}
+
+/// Runs on every message received from twitch
+pub type Hook = Box<dyn Fn(&IRCMessage) -> Option<HookAction>>;
+
+/// Callbacks that get a look at each incoming message, for bots or auto-responders
+#[derive(Default)]
+pub struct Hooks(Vec<Hook>);
+
+impl Hooks {
+    pub fn register(&mut self, hook: impl Fn(&IRCMessage) -> Option<HookAction> + 'static) {
+        self.0.push(Box::new(hook));
+    }
+
+    /// Runs every hook in the order they were registered
+    pub fn run(&self, message: &IRCMessage) -> Vec<HookAction> {
+        self.0.iter().filter_map(|hook| hook(message)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IRCCommand;
+
+    #[test]
+    fn test_hooks() {
+        let mut hooks = Hooks::default();
+        hooks.register(|message| match &message.command {
+            IRCCommand::Privmsg { channel, message } if message == "!ping" => {
+                Some(HookAction::Send {
+                    channel: channel.clone(),
+                    message: String::from("pong"),
+                })
+            }
+            _ => None,
+        });
+        hooks.register(|message| match &message.command {
+            IRCCommand::Privmsg { message, .. } if message.contains("spam") => {
+                Some(HookAction::Suppress)
+            }
+            _ => None,
+        });
+
+        let message =
+            IRCMessage::parse(":foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :!ping\r\n").unwrap();
+        assert_eq!(
+            hooks.run(&message),
+            [HookAction::Send {
+                channel: String::from("bar"),
+                message: String::from("pong")
+            }]
+        );
+
+        let message =
+            IRCMessage::parse(":foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :buy spam\r\n").unwrap();
+        assert_eq!(hooks.run(&message), [HookAction::Suppress]);
+
+        let message = IRCMessage::parse(":tmi.twitch.tv USERSTATE #bar\r\n").unwrap();
+        assert!(hooks.run(&message).is_empty());
+    }
+}
@@ -0,0 +1,656 @@
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, Write},
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    framer::{messages, ParseError},
+    net::{self, Connection},
+    IRCCommand, IRCMessage, Proxy, RateLimiter,
+};
+
+/// What [`IRC::new`] is usually given, `twitch.tv/membership` is left out since the JOINs and
+/// PARTs of everyone in a busy channel are a lot of traffic
+pub const DEFAULT_CAPABILITIES: [&str; 2] = ["twitch.tv/tags", "twitch.tv/commands"];
+
+/// Whether `command`, the answer to our `CAP REQ`, enabled the capabilities
+pub(crate) fn check_cap_reply(command: &IRCCommand) -> anyhow::Result<()> {
+    match command {
+        IRCCommand::CapAck => Ok(()),
+        IRCCommand::CapNak { capabilities } => {
+            tracing::error!(?capabilities, "capabilities refused");
+            Err(anyhow::anyhow!(
+                "twitch refused the capabilities {}, check `capabilities` in the config",
+                capabilities.join(", ")
+            ))
+        }
+        received => {
+            tracing::error!(?received, "expected CAP ACK");
+            Err(anyhow::anyhow!(
+                "twitch didn't answer the capability request"
+            ))
+        }
+    }
+}
+
+/// Replayed lines without a `tmi-sent-ts` to go by come this long after the one before
+const REPLAY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Quiet stretches in a replayed log are cut down to this
+const MAX_REPLAY_GAP: Duration = Duration::from_secs(10);
+
+/// How long to wait before replaying a line sent at `sent_at`, in milliseconds like
+/// `tmi-sent-ts`, after the last one that had a time, sent at `previous`
+pub(crate) fn replay_delay(previous: Option<i64>, sent_at: Option<i64>) -> Duration {
+    match (previous, sent_at) {
+        (Some(previous), Some(sent_at)) => {
+            Duration::from_millis(sent_at.saturating_sub(previous).max(0) as u64)
+                .min(MAX_REPLAY_GAP)
+        }
+        _ => REPLAY_INTERVAL,
+    }
+}
+
+/// A chat message that wasn't handed to the connection yet
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outgoing {
+    pub channel: String,
+    pub message: String,
+    /// sent as the `client-nonce` tag, twitch puts it on its answers to the message
+    pub nonce: String,
+}
+
+impl Outgoing {
+    /// A message with a new nonce, `message` has to be a single line
+    pub(crate) fn new(channel: &str, message: &str) -> anyhow::Result<Self> {
+        // like `send_raw`, a line break would end the PRIVMSG and send whatever comes after it
+        anyhow::ensure!(
+            !message.contains(['\r', '\n']),
+            "messages can't contain line breaks"
+        );
+
+        Ok(Self {
+            channel: channel.to_string(),
+            message: message.to_string(),
+            nonce: client_nonce(),
+        })
+    }
+
+    /// The PRIVMSG line that sends it, the same for [`IRC`] and `AsyncIRC`
+    pub(crate) fn line(&self) -> String {
+        format!(
+            "@client-nonce={} PRIVMSG #{} :{}\r\n",
+            self.nonce, self.channel, self.message
+        )
+    }
+}
+
+/// A random `client-nonce`, 32 hex digits like the web client's
+fn client_nonce() -> String {
+    use std::hash::{BuildHasher, Hasher};
+
+    // seeded from the OS's randomness, different for every `RandomState`
+    let random = || {
+        std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish()
+    };
+    format!("{:016x}{:016x}", random(), random())
+}
+
+#[allow(clippy::upper_case_acronyms)]
+pub struct IRC {
+    /// `None` while replaying a log, see [`IRC::replay`]
+    connection: Option<TcpStream>,
+    /// when the reader thread last got a line, twitch pings about every 5 minutes
+    last_received: Arc<Mutex<Instant>>,
+    /// set once the reader thread stopped, e.g. because twitch closed the connection
+    closed: Arc<AtomicBool>,
+    /// the token of the PING we're waiting on an answer for, and when it was sent
+    pending_ping: Arc<Mutex<Option<(String, Instant)>>>,
+    /// how long the last PING took to be answered
+    latency: Arc<Mutex<Option<Duration>>>,
+    pings_sent: u64,
+    irc_message_receiver: crossbeam::channel::Receiver<IRCMessage>,
+    address: String,
+    proxy: Option<Proxy>,
+    tls: bool,
+    auth_token: Option<String>,
+    capabilities: Vec<String>,
+    message_sender: crossbeam::channel::Sender<String>,
+    rate_limiter: RateLimiter,
+    /// messages waiting for the rate limiter or for the connection to come back
+    queue: VecDeque<Outgoing>,
+    /// every channel we joined, in the order they were joined
+    channels: Vec<String>,
+    nick: String,
+}
+
+impl IRC {
+    /// Connects and joins `channel`, without an `auth_token` the connection is anonymous and
+    /// read-only, which twitch only allows with a `justinfan` nick (see [`IRC::anonymous_nick`])
+    ///
+    /// `capabilities` are requested before logging in, e.g. [`DEFAULT_CAPABILITIES`], none skips
+    /// the `CAP REQ` entirely. With `tls` the certificate has to be for the host in `address`,
+    /// through the `proxy` too
+    // the proxy can contain credentials too
+    #[tracing::instrument(skip(proxy, auth_token))]
+    pub fn new(
+        address: &str,
+        proxy: Option<Proxy>,
+        tls: bool,
+        auth_token: Option<&str>,
+        nick: &str,
+        capabilities: &[String],
+        channel: &str,
+    ) -> anyhow::Result<Self> {
+        let stream = match &proxy {
+            Some(proxy) => proxy.connect(address)?,
+            None => net::connect(address, net::CONNECT_TIMEOUT)?,
+        };
+        let connection = if tls {
+            net::tls(address, stream.try_clone()?, net::CONNECT_TIMEOUT)?
+        } else {
+            Connection::Plain(stream.try_clone()?)
+        };
+        tracing::info!("connected");
+
+        let (message_sender, message_receiver) = crossbeam::channel::unbounded::<String>();
+
+        {
+            let mut connection = connection.try_clone()?;
+
+            std::thread::spawn(move || {
+                for message in message_receiver {
+                    if message.starts_with("PASS ") {
+                        tracing::trace!("sending PASS");
+                    } else {
+                        tracing::trace!(message = message.trim_end(), "sending");
+                    }
+
+                    if let Err(e) = connection.write_all(message.as_bytes()) {
+                        tracing::error!(%e, "failed to write to the connection");
+                        break;
+                    }
+                }
+
+                tracing::debug!("writer thread stopped");
+            });
+        }
+
+        let (irc_message_sender, irc_message_receiver) =
+            crossbeam::channel::unbounded::<IRCMessage>();
+
+        let last_received = Arc::new(Mutex::new(Instant::now()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let pending_ping: Arc<Mutex<Option<(String, Instant)>>> = Arc::new(Mutex::new(None));
+        let latency = Arc::new(Mutex::new(None));
+
+        {
+            let connection = connection.try_clone()?;
+            let message_sender = message_sender.clone();
+            let last_received = last_received.clone();
+            let closed = closed.clone();
+            let pending_ping = pending_ping.clone();
+            let latency = latency.clone();
+            std::thread::spawn(move || {
+                // ends once the connection is closed or reading from it fails
+                for irc_message in messages(BufReader::new(connection)) {
+                    let irc_message = match irc_message {
+                        Ok(irc_message) => irc_message,
+                        Err(ParseError::Invalid(line)) => {
+                            tracing::warn!(%line, "failed to parse message");
+                            continue;
+                        }
+                        Err(ParseError::Io(e)) => {
+                            tracing::debug!(%e, "failed to read");
+                            break;
+                        }
+                    };
+                    *last_received.lock().unwrap() = Instant::now();
+                    tracing::debug!(%irc_message, "received");
+
+                    match &irc_message.command {
+                        // twitch closes the connection if we don't answer
+                        IRCCommand::Ping => {
+                            let _ = message_sender.send(String::from("PONG :tmi.twitch.tv\r\n"));
+                        }
+                        IRCCommand::Pong { token } => {
+                            let mut pending_ping = pending_ping.lock().unwrap();
+                            if let Some((_, sent)) =
+                                pending_ping.take_if(|(pending, _)| pending == token)
+                            {
+                                *latency.lock().unwrap() = Some(sent.elapsed());
+                            }
+                        }
+                        _ => {}
+                    }
+
+                    if irc_message_sender.send(irc_message).is_err() {
+                        break;
+                    }
+                }
+
+                closed.store(true, Ordering::Relaxed);
+                tracing::debug!("reader thread stopped");
+            });
+        }
+
+        if !capabilities.is_empty() {
+            tracing::debug!(?capabilities, "requesting capabilities");
+            message_sender.send(format!("CAP REQ :{}\r\n", capabilities.join(" ")))?;
+
+            let received = irc_message_receiver.recv_timeout(Duration::from_secs(5))?;
+            check_cap_reply(&received.command)?;
+        }
+
+        if let Some(auth_token) = auth_token {
+            tracing::debug!("authenticating");
+            message_sender.send(format!("PASS oauth:{auth_token}\r\n"))?;
+        } else {
+            tracing::debug!("connecting anonymously");
+        }
+
+        message_sender.send(format!("NICK {}\r\n", nick))?;
+
+        tracing::info!("joining #{channel}");
+        message_sender.send(format!("JOIN #{channel}\r\n"))?;
+
+        Ok(Self {
+            connection: Some(stream),
+            last_received,
+            closed,
+            pending_ping,
+            latency,
+            pings_sent: 0,
+            irc_message_receiver,
+            address: address.to_string(),
+            proxy,
+            tls,
+            auth_token: auth_token.map(str::to_string),
+            capabilities: capabilities.to_vec(),
+            message_sender,
+            rate_limiter: RateLimiter::default(),
+            queue: VecDeque::new(),
+            channels: vec![channel.to_string()],
+            nick: nick.to_string(),
+        })
+    }
+
+    /// Plays back a log of raw IRC lines as if twitch sent them, spaced out like they were by
+    /// their `tmi-sent-ts` with long gaps cut short. Nothing is sent anywhere and it can't
+    /// reconnect, [`IRC::is_closed`] turns true once the whole log was played
+    pub fn replay<R: BufRead + Send + 'static>(reader: R, nick: &str, channel: &str) -> Self {
+        let (irc_message_sender, irc_message_receiver) =
+            crossbeam::channel::unbounded::<IRCMessage>();
+        let (message_sender, message_receiver) = crossbeam::channel::unbounded::<String>();
+
+        let last_received = Arc::new(Mutex::new(Instant::now()));
+        let closed = Arc::new(AtomicBool::new(false));
+
+        std::thread::spawn(move || {
+            for message in message_receiver {
+                tracing::trace!(message = message.trim_end(), "not sending while replaying");
+            }
+        });
+
+        {
+            let last_received = last_received.clone();
+            let closed = closed.clone();
+            std::thread::spawn(move || {
+                let mut previous = None;
+                for irc_message in messages(reader) {
+                    let irc_message = match irc_message {
+                        Ok(irc_message) => irc_message,
+                        Err(ParseError::Invalid(line)) => {
+                            tracing::warn!(%line, "failed to parse replayed message");
+                            continue;
+                        }
+                        Err(ParseError::Io(e)) => {
+                            tracing::error!(%e, "failed to read the replayed log");
+                            break;
+                        }
+                    };
+
+                    let sent_at = irc_message
+                        .tags
+                        .get("tmi-sent-ts")
+                        .and_then(|sent_at| sent_at.parse().ok());
+                    std::thread::sleep(replay_delay(previous, sent_at));
+                    previous = sent_at.or(previous);
+
+                    *last_received.lock().unwrap() = Instant::now();
+                    if irc_message_sender.send(irc_message).is_err() {
+                        break;
+                    }
+                }
+
+                closed.store(true, Ordering::Relaxed);
+                tracing::info!("replay finished");
+            });
+        }
+
+        Self {
+            connection: None,
+            last_received,
+            closed,
+            pending_ping: Arc::new(Mutex::new(None)),
+            latency: Arc::new(Mutex::new(None)),
+            pings_sent: 0,
+            irc_message_receiver,
+            address: String::new(),
+            proxy: None,
+            tls: false,
+            auth_token: None,
+            capabilities: Vec::new(),
+            message_sender,
+            rate_limiter: RateLimiter::default(),
+            queue: VecDeque::new(),
+            channels: vec![channel.to_string()],
+            nick: nick.to_string(),
+        }
+    }
+
+    /// Queues the message and sends it as soon as the rate limit allows, see [`IRC::flush`],
+    /// returns the `client-nonce` it's sent with
+    pub fn send_message(&mut self, channel: &str, message: &str) -> anyhow::Result<String> {
+        let outgoing = Outgoing::new(channel, message)?;
+        let nonce = outgoing.nonce.clone();
+        self.queue.push_back(outgoing);
+
+        self.flush()?;
+        Ok(nonce)
+    }
+
+    /// Sends queued messages while the rate limit allows, this needs to be called regularly
+    /// for the rest of the queue to go out
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        // the writer thread might be gone, keep the messages for after reconnecting
+        if self.is_closed() {
+            return Ok(());
+        }
+
+        while !self.queue.is_empty() && self.rate_limiter.try_acquire(Instant::now()) {
+            let Some(outgoing) = self.queue.pop_front() else {
+                break;
+            };
+
+            self.message_sender.send(outgoing.line())?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends `line` as it is, for commands there's no method for, it doesn't wait for the rate
+    /// limit
+    pub fn send_raw(&mut self, line: &str) -> anyhow::Result<()> {
+        // one line, a `\r\n` in it would send a second command
+        anyhow::ensure!(
+            !line.contains(['\r', '\n']),
+            "raw lines can't contain line breaks"
+        );
+
+        self.message_sender.send(format!("{line}\r\n"))?;
+
+        Ok(())
+    }
+
+    /// Messages that are waiting to be sent, oldest first
+    pub fn queued(&self) -> &VecDeque<Outgoing> {
+        &self.queue
+    }
+
+    /// Removes everything from the queue, returning what won't be sent anymore
+    pub fn cancel_queued(&mut self) -> Vec<Outgoing> {
+        self.queue.drain(..).collect()
+    }
+
+    /// A `justinfan` nick for anonymous connections, twitch accepts any number after it
+    pub fn anonymous_nick() -> String {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+
+        format!("justinfan{}", 10000 + seed % 90000)
+    }
+
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    /// Sends a PING of our own, [`IRC::latency`] is updated once twitch answers it
+    pub fn ping(&mut self) -> anyhow::Result<()> {
+        self.pings_sent += 1;
+        let token = format!("twitcher-{}", self.pings_sent);
+
+        self.message_sender.send(format!("PING :{token}\r\n"))?;
+        *self.pending_ping.lock().unwrap() = Some((token, Instant::now()));
+
+        Ok(())
+    }
+
+    /// How long the last [`IRC::ping`] has been waiting for its PONG, `None` once it's answered
+    pub fn unanswered_ping(&self) -> Option<Duration> {
+        self.pending_ping
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(_, sent)| sent.elapsed())
+    }
+
+    /// How long twitch took to answer the last [`IRC::ping`], `None` before the first answer
+    pub fn latency(&self) -> Option<Duration> {
+        *self.latency.lock().unwrap()
+    }
+
+    /// When anything, including PINGs, was last received
+    pub fn last_received(&self) -> Instant {
+        *self.last_received.lock().unwrap()
+    }
+
+    /// Whether the connection was closed, after which nothing else will be received
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    pub fn try_recv(&mut self) -> anyhow::Result<IRCMessage> {
+        Ok(self.irc_message_receiver.try_recv()?)
+    }
+
+    /// Drops the current connection and goes through the whole handshake again, rejoining
+    /// every channel that's joined now, see [`IRC::channels`]. Twitch asks for this with an
+    /// [`IRCCommand::Reconnect`] before it restarts a server
+    #[tracing::instrument(skip(self))]
+    pub fn reconnect(&mut self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.connection.is_some(), "a replay can't reconnect");
+
+        let channels = self.channels.clone();
+        let (first, rest) = channels
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("not in any channel"))?;
+
+        let irc = IRC::new(
+            &self.address,
+            self.proxy.clone(),
+            self.tls,
+            self.auth_token.as_deref(),
+            &self.nick,
+            &self.capabilities,
+            first,
+        )?;
+
+        // the rate limit is per account, and queued messages are still meant to go out
+        let rate_limiter = std::mem::take(&mut self.rate_limiter);
+        let queue = std::mem::take(&mut self.queue);
+        *self = irc;
+        self.rate_limiter = rate_limiter;
+        self.queue = queue;
+
+        for channel in rest {
+            self.join(channel)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reconnects as another account, keeping the same channels
+    ///
+    /// Messages still queued were meant to come from the old account, they're dropped and
+    /// returned instead of being sent
+    #[tracing::instrument(skip(self, auth_token))]
+    pub fn switch_account(
+        &mut self,
+        auth_token: Option<&str>,
+        nick: &str,
+    ) -> anyhow::Result<Vec<Outgoing>> {
+        let old_token = std::mem::replace(&mut self.auth_token, auth_token.map(str::to_string));
+        let old_nick = std::mem::replace(&mut self.nick, nick.to_string());
+
+        if let Err(e) = self.reconnect() {
+            self.auth_token = old_token;
+            self.nick = old_nick;
+            return Err(e);
+        }
+
+        self.rate_limiter = RateLimiter::default();
+        Ok(self.cancel_queued())
+    }
+
+    /// Joins `channel`, and again after every reconnect until it's parted. While the connection
+    /// is down it's only joined once [`IRC::reconnect`] is done
+    pub fn join(&mut self, channel: &str) -> anyhow::Result<()> {
+        if self.channels.iter().any(|joined| joined == channel) {
+            return Ok(());
+        }
+        self.channels.push(channel.to_string());
+
+        if self.is_closed() {
+            tracing::info!("joining #{channel} after reconnecting");
+            return Ok(());
+        }
+
+        tracing::info!("joining #{channel}");
+        self.message_sender.send(format!("JOIN #{channel}\r\n"))?;
+
+        Ok(())
+    }
+
+    /// Leaves `channel`, it isn't joined again when reconnecting
+    pub fn part(&mut self, channel: &str) -> anyhow::Result<()> {
+        self.channels.retain(|joined| joined != channel);
+
+        // a new connection doesn't join it in the first place
+        if self.is_closed() {
+            return Ok(());
+        }
+
+        tracing::info!("leaving #{channel}");
+        self.message_sender.send(format!("PART #{channel}\r\n"))?;
+
+        Ok(())
+    }
+}
+
+impl Drop for IRC {
+    fn drop(&mut self) {
+        // unblocks the reader thread so it can exit
+        if let Some(connection) = &self.connection {
+            let _ = connection.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_delay() {
+        assert_eq!(
+            replay_delay(Some(1_000), Some(1_250)),
+            Duration::from_millis(250)
+        );
+        assert_eq!(replay_delay(Some(1_000), Some(900)), Duration::ZERO);
+        assert_eq!(replay_delay(Some(0), Some(3_600_000)), MAX_REPLAY_GAP);
+        assert_eq!(replay_delay(None, Some(1_000)), REPLAY_INTERVAL);
+        assert_eq!(replay_delay(Some(1_000), None), REPLAY_INTERVAL);
+    }
+
+    #[test]
+    fn test_outgoing() {
+        let outgoing = Outgoing::new("bar", "hi chat").unwrap();
+        assert_eq!(outgoing.nonce.len(), 32);
+        assert_eq!(
+            outgoing.line(),
+            format!("@client-nonce={} PRIVMSG #bar :hi chat\r\n", outgoing.nonce)
+        );
+
+        assert!(Outgoing::new("bar", "hi\nJOIN #baz").is_err());
+        assert!(Outgoing::new("bar", "hi\r").is_err());
+    }
+
+    #[test]
+    fn test_reconnect_rejoins() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let mut irc = IRC::new(&address, None, false, None, "justinfan123", &[], "foo").unwrap();
+        irc.join("bar").unwrap();
+        irc.join("baz").unwrap();
+        irc.part("foo").unwrap();
+        assert_eq!(irc.channels(), ["bar", "baz"]);
+
+        irc.reconnect().unwrap();
+        // the first connection, then the one after reconnecting
+        listener.accept().unwrap();
+        let (connection, _) = listener.accept().unwrap();
+        connection
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+
+        let lines = BufReader::new(connection)
+            .lines()
+            .take(3)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(lines, ["NICK justinfan123", "JOIN #bar", "JOIN #baz"]);
+    }
+
+    #[test]
+    fn test_replay() {
+        let log = "@tmi-sent-ts=1000 :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :hi\r\n\
+            @tmi-sent-ts=1010 :baz!baz@baz.tmi.twitch.tv PRIVMSG #bar :hello\r\n";
+        let mut irc = IRC::replay(std::io::Cursor::new(log), "justinfan123", "bar");
+
+        let received = irc
+            .irc_message_receiver
+            .recv_timeout(Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(received.message(), Some("hi"));
+        let received = irc
+            .irc_message_receiver
+            .recv_timeout(Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(received.message(), Some("hello"));
+
+        // what would be sent goes nowhere
+        irc.send_message("bar", "not sent").unwrap();
+        let queued = irc.queued().len();
+        assert!(irc.send_message("bar", "hi\r\nPART #bar").is_err());
+        assert_eq!(irc.queued().len(), queued);
+        irc.join("baz").unwrap();
+        assert_eq!(irc.channels(), ["bar", "baz"]);
+        assert!(irc.reconnect().is_err());
+    }
+}
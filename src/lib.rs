@@ -0,0 +1,23 @@
+//! Parsing and connection handling for twitch's IRC interface
+//!
+//! The twitcher TUI is built on top of this, but it can be used on its own
+//! to write bots or other clients.
+
+#[cfg(feature = "tokio")]
+mod async_irc;
+mod framer;
+mod hook;
+mod irc;
+mod message;
+mod net;
+mod proxy;
+mod rate_limit;
+
+#[cfg(feature = "tokio")]
+pub use async_irc::AsyncIRC;
+pub use framer::{messages, ParseError};
+pub use hook::{Hook, HookAction, Hooks};
+pub use irc::{Outgoing, DEFAULT_CAPABILITIES, IRC};
+pub use message::{IRCCommand, IRCMessage, Prefix, Sub, SubPlan, Tags};
+pub use proxy::Proxy;
+pub use rate_limit::RateLimiter;
@@ -1,316 +1,1071 @@
-use core::panic;
 use std::{
-    collections::HashMap,
-    io::{BufRead, BufReader, Stdout, Write},
-    net::TcpStream,
-    time::Duration,
+    borrow::Cow,
+    cell::OnceCell,
+    collections::{HashMap, HashSet, VecDeque},
+    io::{BufReader, Stdout, Write},
+    ops::RangeInclusive,
+    path::PathBuf,
+    rc::Rc,
+    sync::Mutex,
+    time::{Duration, Instant},
 };
 
+mod args;
+mod auto_reply;
+mod chatters;
+mod completion;
+mod config;
+mod emote_images;
+mod frame;
+mod mention_popup;
+mod motion;
+mod notify;
+mod registers;
+mod sanitize;
+mod search;
+mod sound;
+mod width;
+
 use arboard::Clipboard;
+use args::Args;
+use auto_reply::AutoReply;
+use chatters::Chatters;
+use chrono::{DateTime, Local, TimeDelta};
+use clap::{error::ErrorKind, Parser};
+use completion::Completion;
+use config::{Config, DeletedMessages, Density, Highlight, Notifications};
 use crossterm::{
     cursor,
-    event::{self, Event, KeyModifiers},
+    event::{self, Event, KeyEvent, KeyModifiers},
     style,
     terminal::{self, disable_raw_mode, enable_raw_mode},
     ExecutableCommand, QueueableCommand,
 };
+use emote_images::{EmoteImages, Protocol};
+use frame::ChatRows;
+use mention_popup::MentionPopup;
+use notify::Notifier;
+use regex::Regex;
+use registers::Registers;
+use sanitize::sanitize;
+use search::{Query, Searched};
 
+use tracing_subscriber::EnvFilter;
+use twitcher::{HookAction, Hooks, IRCCommand, Prefix, Proxy, Sub, Tags, IRC};
 use unicode_segmentation::UnicodeSegmentation;
 
+/// Twitch pings every 5 minutes or so, going this long without hearing anything means the
+/// connection is most likely gone even if it wasn't closed
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(6 * 60);
+
+/// How long twitch gets to answer a message we sent before it's marked as failed
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Twitch drops a message that's the same as the last one we sent within this long
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(30);
+
+/// An invisible character other clients add to get around the duplicate check
+const DUPLICATE_SUFFIX: &str = " \u{E0000}";
+
+/// Line breaks in pasted text, kept in the compose line so it stays one row until it's split
+/// into one message per line when sent
+const PASTED_NEWLINE: char = '\u{2424}';
+
+/// How long to wait before trying again after a failed reconnect
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// The chat isn't squeezed below this many columns to make room for the sidebar
+const MIN_CHAT_WIDTH: u16 = 20;
+
+/// What the sidebar on the right shows
+struct Sidebar<'a> {
+    /// as [`channel_label`] shows it
+    channel: String,
+    chatters: &'a Chatters,
+    width: u16,
+}
+
+/// The lines `/raid` and `/shoutout` leave in chat
+const COMMAND_COLOR: style::Color = style::Color::Cyan;
+
+/// Pins kept at once, pinning another one unpins the oldest
+const MAX_PINS: usize = 5;
+
+/// A message pinned with `m`, shown above chat in the views it's from until it's unpinned
+struct Pin {
+    channel: String,
+    line: String,
+}
+
+/// Chatters matching the `@mention` being typed, above it
+struct Popup<'a> {
+    names: &'a [String],
+    selected: usize,
+    /// grapheme of the compose line the `@` is at
+    column: u16,
+}
+
+/// Which rows show what, worked out once per frame so drawing and moving the cursor agree
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Layout {
+    /// right below the tab bar and the pins
+    messages_top: u16,
+    /// chat ends right above the compose area, these rows are its bottom
+    message_rows: u16,
+    /// the compose area goes from here to the bottom of the screen
+    compose_top: u16,
+}
+
+impl Layout {
+    /// `top` rows are taken by the tab bar and pins, `max_messages` limits the chat to fewer
+    /// rows than fit, 0 means no limit
+    fn new(top: u16, compose_rows: u16, max_messages: usize, total_rows: u16) -> Self {
+        let compose_top = total_rows.saturating_sub(compose_rows);
+        let mut message_rows = compose_top.saturating_sub(top);
+        if max_messages > 0 {
+            message_rows = message_rows.min(max_messages.min(u16::MAX as usize) as u16);
+        }
+
+        Self {
+            messages_top: compose_top - message_rows,
+            message_rows,
+            compose_top,
+        }
+    }
+
+    /// The row the first of `shown` messages is drawn at, so the newest one ends up right
+    /// above the compose area
+    fn first_message_row(&self, shown: usize) -> u16 {
+        self.compose_top
+            .saturating_sub(shown as u16)
+            .max(self.messages_top)
+    }
+}
+
 struct CursorPos {
     /// 0 is the top most row
     row: u16,
     column: u16,
 }
 
-enum Mode {
-    Normal,
-    Insert,
-    Y,
-    D,
+/// How often we PING twitch, to measure the latency shown in the status and to notice a dead
+/// connection sooner than `HEARTBEAT_TIMEOUT` would
+const PING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a PING can go unanswered before the connection counts as lost
+const PONG_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long to wait for a ROOMSTATE after joining before warning that the channel might not exist
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Keys a macro replay can queue up, a macro that replays itself would never end otherwise
+const MAX_REPLAY_KEYS: usize = 10_000;
+
+/// Twitch doesn't refuse joins to channels that don't exist, the only hint is that ROOMSTATE
+/// never comes
+enum JoinState {
+    /// JOIN sent at this point, no ROOMSTATE yet
+    Pending(Instant),
+    Joined,
+    /// ROOMSTATE didn't show up in time, and the user was told
+    Unconfirmed,
 }
 
-#[derive(Clone, Debug, Default)]
-struct Tags(HashMap<String, String>);
+/// A joined channel with its own chat buffer
+struct Tab {
+    channel: String,
+    join_state: JoinState,
+    /// how long twitch makes us wait between messages, from the ROOMSTATE `slow` tag
+    slow_mode: Option<Duration>,
+    /// only emotes can be sent, from the ROOMSTATE `emote-only` tag
+    emote_only: bool,
+    messages: Vec<ChatLine>,
+    /// messages that came in while another tab was active
+    unread: usize,
+    /// where the cursor was when we switched away from this tab
+    cursor_pos: CursorPos,
+}
 
-impl Tags {
-    fn get(&self, tag: &str) -> Option<&String> {
-        self.0.get(tag)
+impl Tab {
+    fn new(channel: String, compose_row: u16) -> Self {
+        Self {
+            join_state: JoinState::Pending(Instant::now()),
+            slow_mode: None,
+            emote_only: false,
+            messages: vec![ChatLine::SessionStart {
+                channel: channel.clone(),
+                timestamp: Local::now(),
+            }],
+            unread: 0,
+            cursor_pos: CursorPos {
+                row: compose_row,
+                column: 0,
+            },
+            channel,
+        }
     }
 
-    fn parse(raw_message: &str, pos: &mut usize) -> Option<Self> {
-        if raw_message[*pos..].starts_with('@') {
-            if let Some(space_index) = raw_message[*pos..].find(' ') {
-                let mut map = HashMap::new();
+    /// Adds a line that isn't someone's message
+    fn notice(&mut self, text: String, active: bool) {
+        self.colored_notice(text, None, active);
+    }
 
-                let message = &raw_message[*pos..space_index];
-                for tag in message.split(';') {
-                    let (key, value) = tag.split_once('=').unwrap();
+    fn colored_notice(&mut self, text: String, color: Option<style::Color>, active: bool) {
+        let line = ChatLine::System {
+            channel: self.channel.clone(),
+            text,
+            color,
+            timestamp: Local::now(),
+        };
 
-                    map.insert(key.to_string(), value.to_string());
-                }
+        self.push(line, active);
+    }
 
-                *pos = space_index + 1;
+    /// A USERNOTICE, the `subgift`s of a gift bomb go under it instead of each getting a line
+    fn user_notice(&mut self, tags: &Tags, message: Option<&str>, active: bool) {
+        let msg_id = tags.get("msg-id").map(String::as_str);
+        if let (Some("submysterygift"), Some(id), Some(text)) =
+            (msg_id, tags.community_gift_id(), gift_bomb_text(tags))
+        {
+            let bomb = GiftBomb {
+                channel: self.channel.clone(),
+                id: id.to_string(),
+                text,
+                recipients: Vec::new(),
+                timestamp: Local::now(),
+            };
+            self.push(ChatLine::GiftBomb(bomb), active);
+            return;
+        }
 
-                return Some(Self(map));
+        let Some((text, color)) = user_notice_line(tags, message) else {
+            return;
+        };
+        match (msg_id, tags.community_gift_id()) {
+            (Some("subgift"), Some(id)) if self.gift_bomb(id).is_some() => {
+                let recipient = ChatLine::System {
+                    channel: self.channel.clone(),
+                    text: format!("  {text}"),
+                    color,
+                    timestamp: Local::now(),
+                };
+                if let Some(bomb) = self.gift_bomb(id) {
+                    bomb.recipients.push(recipient);
+                }
             }
+            _ => self.colored_notice(text, color, active),
         }
-
-        None
     }
-}
 
-#[derive(Debug)]
-struct Prefix {
-    nick: Option<String>,
-    user: Option<String>,
-    host: String,
-}
+    /// The gift bomb with this `msg-param-community-gift-id`, gifts only trickle in for a bit
+    /// so it's one of the last lines
+    fn gift_bomb(&mut self, id: &str) -> Option<&mut GiftBomb> {
+        self.messages.iter_mut().rev().find_map(|line| match line {
+            ChatLine::GiftBomb(bomb) if bomb.id == id => Some(bomb),
+            _ => None,
+        })
+    }
 
-impl Prefix {
-    fn parse(raw_message: &str, pos: &mut usize) -> Option<Self> {
-        if raw_message[*pos..].starts_with(':') {
-            let host_start = *pos + 1;
-            let mut nick = None;
-            let mut user = None;
-            let host;
+    /// Moves the "new messages" line to the end, so what comes in after it stands out
+    fn mark_read(&mut self) {
+        if matches!(
+            self.messages.last(),
+            None | Some(ChatLine::Unread { .. } | ChatLine::SessionStart { .. })
+        ) {
+            return;
+        }
 
-            let Some(end_index) = raw_message[*pos..].find(' ') else {
-                return None;
-            };
+        self.messages
+            .retain(|line| !matches!(line, ChatLine::Unread { .. }));
+        self.messages.push(ChatLine::Unread {
+            channel: self.channel.clone(),
+            timestamp: Local::now(),
+        });
+    }
 
-            if let Some(user_index) = raw_message[*pos..].find('!') {
-                nick = Some(raw_message[host_start..*pos + user_index].to_string());
-                let Some(host_start) = raw_message[*pos..].find('@') else {
-                    return None;
-                };
+    /// Takes the "new messages" line away again if nothing came in after it
+    fn drop_unread_line(&mut self) {
+        if matches!(self.messages.last(), Some(ChatLine::Unread { .. })) {
+            self.messages.pop();
+        }
+    }
 
-                user = Some(raw_message[*pos + user_index + 1..*pos + host_start].to_string());
-                host = raw_message[*pos + host_start + 1..*pos + end_index].to_string();
-            } else {
-                host = raw_message[host_start..*pos + end_index].to_string();
+    /// Our oldest message twitch hasn't answered yet, it answers them in order
+    fn oldest_pending(&mut self) -> Option<&mut Privmsg> {
+        self.messages.iter_mut().find_map(|line| match line {
+            ChatLine::Privmsg(privmsg) if matches!(privmsg.delivery, Delivery::Pending(_)) => {
+                Some(privmsg)
             }
+            _ => None,
+        })
+    }
+
+    /// Our message an answer from twitch with `tags` is about, found by its `client-nonce`, or
+    /// the oldest one still pending for answers that don't have it
+    fn answered(&mut self, tags: &Tags) -> Option<&mut Privmsg> {
+        let Some(nonce) = tags.get("client-nonce") else {
+            return self.oldest_pending();
+        };
+
+        self.messages.iter_mut().rev().find_map(|line| match line {
+            ChatLine::Privmsg(privmsg) if privmsg.nonce.as_ref() == Some(nonce) => Some(privmsg),
+            _ => None,
+        })
+    }
 
-            *pos += end_index + 1;
+    /// Marks our messages sent more than `timeout` ago without an answer as failed
+    fn expire_pending(&mut self, now: Instant, timeout: Duration) {
+        for line in &mut self.messages {
+            if let ChatLine::Privmsg(privmsg) = line {
+                if let Delivery::Pending(sent_at) = privmsg.delivery {
+                    if now.duration_since(sent_at) > timeout {
+                        privmsg.delivery = Delivery::Failed;
+                    }
+                }
+            }
+        }
+    }
 
-            return Some(Self { nick, user, host });
+    fn push(&mut self, line: ChatLine, active: bool) {
+        if !active {
+            self.unread += 1;
         }
 
-        None
+        self.messages.push(line);
     }
 }
 
-struct Privmsg {
-    tags: Tags,
-    prefix: Prefix,
-    channel: String,
-    message: String,
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Normal,
+    Insert,
+    Y,
+    D,
+    /// waiting for the second key of a `g` motion
+    G,
+    /// waiting for the direction to scroll the message under the cursor after `z`
+    Z,
+    /// waiting for the register name after `"`
+    Register,
+    /// waiting for the character `r` puts under the cursor
+    Replace,
+    /// waiting for the register to record a macro into after `q`
+    Record,
+    /// waiting for the register to replay after `@`, this many times
+    Replay(usize),
+    /// asked whether the message should really be sent, `y` sends it unless there's something
+    /// else to ask about
+    Confirm(Confirmation),
+    /// selecting whole messages with `V`, from the row `V` was pressed on to the cursor
+    Visual {
+        /// the message's index in everything the view shows, so it stays on the same message
+        /// while new ones come in
+        anchor: usize,
+    },
+    /// reading a `:` command into the command line
+    Command,
+    /// reading what `/` searches for into the command line
+    Search,
 }
 
-impl Privmsg {
-    fn message_line(&self) -> String {
-        format!(
-            "{}: {}",
-            self.tags
-                .get("display-name")
-                .unwrap_or(self.prefix.user.as_ref().unwrap_or(&self.channel)),
-            self.message
-        )
+impl Mode {
+    /// A bar while typing, an underline while a key is still expected to finish what was started
+    /// and a block otherwise, like vim does
+    fn cursor_style(self) -> cursor::SetCursorStyle {
+        match self {
+            Self::Insert | Self::Command | Self::Search => cursor::SetCursorStyle::SteadyBar,
+            Self::Y
+            | Self::D
+            | Self::G
+            | Self::Z
+            | Self::Register
+            | Self::Replace
+            | Self::Record
+            | Self::Replay(_) => cursor::SetCursorStyle::SteadyUnderScore,
+            Self::Normal | Self::Confirm(_) | Self::Visual { .. } => {
+                cursor::SetCursorStyle::SteadyBlock
+            }
+        }
     }
+}
 
-    fn message_line_len(&self) -> usize {
-        self.message_line().graphemes(true).count()
-    }
+/// What a `y/n` question before sending a message is about, each one is asked at most once
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Confirmation {
+    /// one of `confirm_commands`
+    Command,
+    /// words in emote-only mode
+    EmoteOnly,
+    /// more than one message, see `split_message`
+    Split,
 }
 
-#[derive(Debug)]
-struct IRCMessage {
-    tags: Tags,
-    prefix: Prefix,
-    command: IRCCommand,
+/// The last change to the compose line, which `.` does again
+#[derive(Clone, Debug, PartialEq)]
+enum Change {
+    /// text typed in one go in insert mode, or pasted
+    Insert(String),
+    /// `dd`
+    DeleteLine,
+    /// `r` with this character
+    Replace(char),
 }
 
-impl IRCMessage {
-    fn parse(raw_message: &str) -> Option<Self> {
-        let mut pos = 0;
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Theme {
+    /// use whatever colors the terminal is configured with
+    Default,
+    Dark,
+    Light,
+}
 
-        let tags = Tags::parse(raw_message, &mut pos).unwrap_or_default();
-        let prefix = Prefix::parse(raw_message, &mut pos)?;
-        let command = IRCCommand::parse(raw_message, &mut pos)?;
+impl Theme {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::Default),
+            "dark" => Some(Self::Dark),
+            "light" => Some(Self::Light),
+            _ => None,
+        }
+    }
 
-        Some(Self {
-            tags,
-            prefix,
-            command,
-        })
+    fn foreground(&self) -> style::Color {
+        match self {
+            Theme::Default => style::Color::Reset,
+            Theme::Dark => style::Color::White,
+            Theme::Light => style::Color::Black,
+        }
+    }
+
+    fn background(&self) -> style::Color {
+        match self {
+            Theme::Default => style::Color::Reset,
+            Theme::Dark => style::Color::Black,
+            Theme::Light => style::Color::White,
+        }
     }
 }
 
-#[derive(Debug)]
-enum IRCCommand {
-    Privmsg { channel: String, message: String },
-    GlobalUserState,
-    Unknown(String),
-    CapAck,
-    Ping,
+/// Client actions entered with `:`, these are handled locally and never sent to twitch
+#[derive(Debug, PartialEq)]
+enum Action {
+    Quit,
+    /// clear the local chat buffer
+    Clear,
+    Reconnect,
+    /// leave the current channel and join this one
+    Channel(String),
+    /// join a channel in a new tab
+    Join(String),
+    /// leave the current channel and close its tab
+    Part,
+    Theme(Theme),
+    /// only show messages matching the regex, `None` clears the filter
+    Filter(Option<String>),
+    /// list who talked recently
+    Chatters,
+    /// list the messages waiting to be sent
+    Queue,
+    /// drop the messages waiting to be sent
+    Cancel,
+    /// show or hide timestamps, or show them in this format
+    Timestamps(Option<String>),
+    /// send as another account from the config, `None` lists them
+    Account(Option<String>),
+    /// switch between the current tab and all channels merged into one view
+    ToggleMerged,
+    ToggleSidebar,
+    /// read the config file again and apply it
+    Reload,
+    /// send a line to twitch as it is, if `allow_raw` is set
+    Raw(String),
 }
 
-impl IRCCommand {
-    fn parse(raw_message: &str, pos: &mut usize) -> Option<Self> {
-        if let Some(privmsg) = raw_message[*pos..].strip_prefix("PRIVMSG ") {
-            let Some(channel_start) = privmsg.find('#') else {
-                return None;
-            };
+impl Action {
+    fn parse(command: &str) -> anyhow::Result<Self> {
+        let command = command.trim();
+        let (name, argument) = command
+            .split_once(' ')
+            .map(|(name, argument)| (name, argument.trim()))
+            .unwrap_or((command, ""));
 
-            let Some(message_start) = privmsg.find(':') else {
-                return None;
-            };
+        match (name, argument) {
+            ("q" | "quit", "") => Ok(Action::Quit),
+            ("clear", "") => Ok(Action::Clear),
+            ("reconnect", "") => Ok(Action::Reconnect),
+            ("reload", "") => Ok(Action::Reload),
+            ("raw", "") => Err(anyhow::anyhow!("usage: :raw <line>")),
+            ("raw", line) => Ok(Action::Raw(line.to_string())),
+            ("channel", "") => Err(anyhow::anyhow!("usage: :channel <name>")),
+            ("channel", channel) => args::channel_name(channel)
+                .map(Action::Channel)
+                .ok_or_else(|| anyhow::anyhow!("invalid channel: {channel}")),
+            ("join", "") => Err(anyhow::anyhow!("usage: :join <name>")),
+            ("join", channel) => args::channel_name(channel)
+                .map(Action::Join)
+                .ok_or_else(|| anyhow::anyhow!("invalid channel: {channel}")),
+            ("part", "") => Ok(Action::Part),
+            ("chatters", "") => Ok(Action::Chatters),
+            ("queue", "") => Ok(Action::Queue),
+            ("cancel", "") => Ok(Action::Cancel),
+            ("sidebar", "") => Ok(Action::ToggleSidebar),
+            ("all", "") => Ok(Action::ToggleMerged),
+            ("account", "") => Ok(Action::Account(None)),
+            ("account", nick) => Ok(Action::Account(Some(nick.to_lowercase()))),
+            ("timestamps", "") => Ok(Action::Timestamps(None)),
+            ("timestamps", format) if config::valid_timestamp_format(format) => {
+                Ok(Action::Timestamps(Some(format.to_string())))
+            }
+            ("timestamps", format) => Err(anyhow::anyhow!("invalid timestamp format: {format}")),
+            ("filter", "") => Ok(Action::Filter(None)),
+            ("filter", pattern) => Ok(Action::Filter(Some(pattern.to_string()))),
+            ("theme", theme) => Theme::parse(theme)
+                .map(Action::Theme)
+                .ok_or_else(|| anyhow::anyhow!("unknown theme: {theme}")),
+            _ => Err(anyhow::anyhow!("unknown command: {command}")),
+        }
+    }
+}
 
-            return Some(IRCCommand::Privmsg {
-                channel: privmsg[channel_start + 1..message_start - 1].to_string(),
-                message: privmsg[message_start + 1..].to_string(),
-            });
+/// How message text is cleaned up for drawing, from the config
+#[derive(Clone, Copy, Debug, Default)]
+struct RenderOptions {
+    collapse_whitespace: bool,
+    /// 0 means no limit
+    max_combining_marks: usize,
+    deleted: DeletedMessages,
+    /// names are right-aligned in this many columns, 0 leaves them as they are
+    nick_width: usize,
+}
+
+impl RenderOptions {
+    fn from_config(config: &Config) -> Self {
+        Self {
+            collapse_whitespace: config.collapse_whitespace,
+            max_combining_marks: config.max_combining_marks,
+            deleted: config.deleted,
+            nick_width: config.nick_width,
         }
+    }
+}
+
+/// What's compiled from the config once instead of on every message, again on `:reload`
+struct Rules {
+    highlights: Vec<Highlight>,
+    /// the auto replies
+    hooks: Hooks,
+    /// the same rules as `hooks`, told which of their answers went out
+    auto_replies: Vec<Rc<AutoReply>>,
+    timestamp_format: String,
+    /// invalid patterns and formats, which are left out
+    errors: Vec<String>,
+}
 
-        if let Some(_) = raw_message[*pos..].strip_prefix("GLOBALUSERSTATE") {
-            return Some(IRCCommand::GlobalUserState);
+impl Rules {
+    fn compile(config: &Config) -> Self {
+        let (highlights, mut errors) = config.highlights();
+        let (auto_replies, auto_reply_errors) = config.auto_replies();
+        errors.extend(auto_reply_errors);
+
+        let auto_replies: Vec<_> = auto_replies.into_iter().map(Rc::new).collect();
+        let mut hooks = Hooks::default();
+        for auto_reply in &auto_replies {
+            let auto_reply = Rc::clone(auto_reply);
+            hooks.register(move |message| auto_reply.respond(message, Instant::now()));
         }
 
-        if let Some(_) = raw_message[*pos..].strip_prefix("CAP * ACK") {
-            return Some(IRCCommand::CapAck);
+        let mut timestamp_format = config.timestamp_format.clone();
+        if !config::valid_timestamp_format(&timestamp_format) {
+            errors.push(format!("invalid timestamp format {timestamp_format:?}"));
+            timestamp_format = Config::default().timestamp_format;
         }
 
-        if let Some(_) = raw_message[*pos..].strip_prefix("PING :tmi.twitch.tv\r\n") {
-            return Some(IRCCommand::Ping);
+        for error in &errors {
+            tracing::warn!("{error}");
         }
 
-        Some(IRCCommand::Unknown(
-            raw_message[*pos..raw_message.len()].to_string(),
-        ))
+        Self {
+            highlights,
+            hooks,
+            auto_replies,
+            timestamp_format,
+            errors,
+        }
     }
 }
 
-struct IRC {
-    irc_message_receiver: crossbeam::channel::Receiver<IRCMessage>,
-    auth_token: String,
-    message_sender: crossbeam::channel::Sender<String>,
+/// A chat message, with the tags that are needed parsed once here instead of on every draw
+struct Privmsg {
     channel: String,
-    nick: String,
+    message: String,
+    /// the `id` tag, what twitch refers to the message by, our own messages don't have one
+    id: Option<String>,
+    /// the sender's login, which timeouts and bans refer to them by
+    login: Option<String>,
+    /// deleted by a moderator, or the sender was timed out or banned
+    deleted: bool,
+    /// from `display-name`, falling back to the login
+    display_name: String,
+    /// the sender's badge names, like `moderator`, which searches can look for
+    badges: Vec<String>,
+    /// the sender's name color, see [`name_color`]
+    color: style::Color,
+    /// when twitch received the message, or when we did if it has no `tmi-sent-ts`
+    timestamp: DateTime<Local>,
+    /// notes added by hooks, shown after the message
+    annotations: Vec<String>,
+    /// ids of the emotes in the message and their text, from the `emotes` tag
+    emotes: Vec<(String, String)>,
+    /// only changes what's drawn, `message` keeps the original for yanking
+    render: RenderOptions,
+    /// `message_line`, how many graphemes it has and the cells it takes up, worked out the first
+    /// time they're needed since every frame checks whether it fits and every key press checks
+    /// the cursor against its length
+    line: OnceCell<(String, usize, usize)>,
+    delivery: Delivery,
+    /// the `client-nonce` our message was sent with, twitch's answers to it have it too
+    nonce: Option<String>,
+}
+
+/// `#channel <name> text` like the library's messages, for logs
+impl std::fmt::Display for Privmsg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "#{} <{}> {}",
+            self.channel, self.display_name, self.message
+        )
+    }
 }
 
-impl IRC {
-    fn new(address: &str, auth_token: &str, nick: &str, channel: &str) -> anyhow::Result<Self> {
-        let connection = TcpStream::connect(address)?;
+/// Whether twitch got a message we sent, with `confirm_sent` on
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Delivery {
+    /// someone else's message, or ours that twitch answered with a USERSTATE
+    Delivered,
+    /// ours, sent at this point and not answered yet
+    Pending(Instant),
+    /// twitch refused it with a NOTICE or never answered
+    Failed,
+}
 
-        let (message_sender, message_receiver) = crossbeam::channel::unbounded::<String>();
+impl Privmsg {
+    fn new(tags: Tags, prefix: Prefix, channel: String, message: String) -> Self {
+        let timestamp = tags
+            .get("tmi-sent-ts")
+            .and_then(|ts| ts.parse().ok())
+            .and_then(DateTime::from_timestamp_millis)
+            .map(|timestamp| timestamp.with_timezone(&Local))
+            .unwrap_or_else(Local::now);
 
-        {
-            let mut connection = connection.try_clone()?;
+        let display_name = tags
+            .get("display-name")
+            .filter(|name| !name.is_empty())
+            .or(prefix.user.as_ref())
+            .unwrap_or(&channel)
+            .clone();
 
-            std::thread::spawn(move || {
-                for message in message_receiver {
-                    connection.write_all(message.as_bytes()).unwrap();
-                }
+        let text = message
+            .strip_prefix("\u{1}ACTION ")
+            .map_or(message.as_str(), |action| {
+                action.strip_suffix('\u{1}').unwrap_or(action)
             });
+        // an action's positions are in the text without the `\x01ACTION` around it
+        let emotes = tags
+            .get("emotes")
+            .map(|tag| emote_images::parse_emotes(tag, text))
+            .unwrap_or_default();
+
+        let badges = tags
+            .get("badges")
+            .map(|badges| {
+                badges
+                    .split(',')
+                    .filter_map(|badge| badge.split('/').next())
+                    .filter(|name| !name.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            id: tags.get("id").cloned(),
+            deleted: false,
+            color: name_color(&tags, prefix.user.as_deref().unwrap_or(&display_name)),
+            login: prefix.user,
+            display_name,
+            badges,
+            channel,
+            message,
+            timestamp,
+            annotations: Vec::new(),
+            emotes,
+            render: RenderOptions::default(),
+            line: OnceCell::new(),
+            delivery: Delivery::Delivered,
+            nonce: None,
+        }
+    }
+
+    fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    fn display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    /// The text of a `/me` message, which twitch sends wrapped in `\x01ACTION ...\x01`
+    fn action(&self) -> Option<&str> {
+        let action = self.message.strip_prefix("\u{1}ACTION ")?;
+        Some(action.strip_suffix('\u{1}').unwrap_or(action))
+    }
+
+    /// What gets yanked, the original message but without the `/me` wrapper
+    fn text(&self) -> &str {
+        self.action().unwrap_or(&self.message)
+    }
+
+    fn set_annotations(&mut self, annotations: Vec<String>) {
+        self.annotations = annotations;
+        self.line = OnceCell::new();
+    }
+
+    fn set_render(&mut self, render: RenderOptions) {
+        self.render = render;
+        self.line = OnceCell::new();
+    }
+
+    fn set_deleted(&mut self) {
+        self.deleted = true;
+        self.line = OnceCell::new();
+    }
+
+    fn message_line(&self) -> &str {
+        &self.cached_line().0
+    }
+
+    fn message_line_len(&self) -> usize {
+        self.cached_line().1
+    }
+
+    /// `message_line` as it fits in `width` columns, see [`fit_line`]
+    fn visible_line(&self, scroll: usize, width: usize) -> Cow<'_, str> {
+        // most messages fit, which is checked without going through the line again
+        let (line, _, cells) = self.cached_line();
+        if scroll == 0 && *cells <= width {
+            return Cow::Borrowed(line);
         }
 
-        let (irc_message_sender, irc_message_receiver) =
-            crossbeam::channel::unbounded::<IRCMessage>();
+        fit_line(line, scroll, width)
+    }
 
-        {
-            let mut connection = BufReader::new(connection);
-            std::thread::spawn(move || loop {
-                let mut buf = String::new();
-                while let Ok(bytes_read) = connection.read_line(&mut buf) {
-                    if bytes_read > 0 {
-                        if let Some(irc_message) = IRCMessage::parse(&buf) {
-                            irc_message_sender.send(irc_message).unwrap();
-                        }
+    fn cached_line(&self) -> &(String, usize, usize) {
+        self.line.get_or_init(|| {
+            let line = self.render_line();
+            let graphemes = line.graphemes(true).count();
+            let cells = width::width(&line);
+            (line, graphemes, cells)
+        })
+    }
 
-                        buf.clear();
-                    }
-                }
-            });
+    fn render_line(&self) -> String {
+        let text = if self.deleted && self.render.deleted == DeletedMessages::Placeholder {
+            Cow::Borrowed("<message deleted>")
+        } else {
+            sanitize(self.text(), self.render.max_combining_marks)
+        };
+        let text = if self.render.collapse_whitespace {
+            Cow::Owned(text.split_whitespace().collect::<Vec<_>>().join(" "))
+        } else {
+            text
+        };
+
+        let name = align_name(self.display_name(), self.render.nick_width);
+        let mut line = match self.action() {
+            Some(_) => format!("{name} {text}"),
+            None => format!("{name}: {text}"),
+        };
+
+        for annotation in &self.annotations {
+            line.push_str(&format!(" [{annotation}]"));
         }
 
-        message_sender.send(String::from(
-            "CAP REQ :twitch.tv/membership twitch.tv/tags twitch.tv/commands\r\n",
-        ))?;
+        line
+    }
 
-        let received = irc_message_receiver.recv_timeout(Duration::from_secs(5))?;
-        if !matches!(
-            received,
-            IRCMessage {
-                tags: _,
-                prefix: _,
-                command: IRCCommand::CapAck
-            },
-        ) {
-            eprintln!("{received:?}");
-            return Err(anyhow::anyhow!("no ack"));
+    /// The name as it starts `message_line`
+    fn shown_name(&self) -> Cow<'_, str> {
+        align_name(self.display_name(), self.render.nick_width)
+    }
+
+    /// Fills in the `quote_format` template
+    fn quote(&self, template: &str) -> String {
+        template
+            .replace("{time}", &self.timestamp.format("%H:%M").to_string())
+            .replace("{channel}", &self.channel)
+            .replace("{name}", self.display_name())
+            .replace("{message}", self.text())
+    }
+}
+
+/// A line in the chat buffer, either someone's message or an event from twitch
+// nearly every line is a message, boxing them wouldn't save anything
+#[allow(clippy::large_enum_variant)]
+enum ChatLine {
+    Privmsg(Privmsg),
+    System {
+        channel: String,
+        text: String,
+        /// drawn in this color instead of the theme's, like announcements
+        color: Option<style::Color>,
+        timestamp: DateTime<Local>,
+    },
+    /// where the messages that came in since the tab was last looked at start
+    Unread {
+        channel: String,
+        timestamp: DateTime<Local>,
+    },
+    /// the top of a tab's buffer, twitch doesn't send what was said before we joined
+    SessionStart {
+        channel: String,
+        timestamp: DateTime<Local>,
+    },
+    /// an empty row between messages with the cozy density, only ever in the list that's drawn
+    Spacer,
+    GiftBomb(GiftBomb),
+}
+
+/// Someone gifting several subs at once, twitch sends a notice for every recipient after the
+/// one for the whole bomb, those are kept here and only shown after `za`
+struct GiftBomb {
+    channel: String,
+    /// `msg-param-community-gift-id`, the recipients' notices have it too
+    id: String,
+    text: String,
+    recipients: Vec<ChatLine>,
+    timestamp: DateTime<Local>,
+}
+
+impl ChatLine {
+    fn message_line(&self) -> Cow<'_, str> {
+        match self {
+            ChatLine::Privmsg(privmsg) => Cow::Borrowed(privmsg.message_line()),
+            ChatLine::System { text, .. } => Cow::Owned(format!("* {text}")),
+            ChatLine::Unread { .. } => Cow::Borrowed("── new messages ──"),
+            ChatLine::SessionStart { .. } => {
+                Cow::Borrowed("── joined, earlier messages aren't sent by twitch ──")
+            }
+            ChatLine::Spacer => Cow::Borrowed(""),
+            ChatLine::GiftBomb(bomb) if bomb.recipients.is_empty() => {
+                Cow::Owned(format!("* {}", bomb.text))
+            }
+            ChatLine::GiftBomb(bomb) => {
+                Cow::Owned(format!("* {} (+{})", bomb.text, bomb.recipients.len()))
+            }
         }
+    }
+
+    fn channel(&self) -> &str {
+        match self {
+            ChatLine::Privmsg(privmsg) => &privmsg.channel,
+            ChatLine::GiftBomb(bomb) => &bomb.channel,
+            ChatLine::System { channel, .. }
+            | ChatLine::Unread { channel, .. }
+            | ChatLine::SessionStart { channel, .. } => channel,
+            ChatLine::Spacer => "",
+        }
+    }
 
-        message_sender.send(format!("PASS oauth:{}\r\n", auth_token))?;
+    fn timestamp(&self) -> DateTime<Local> {
+        match self {
+            ChatLine::Privmsg(privmsg) => privmsg.timestamp,
+            ChatLine::GiftBomb(bomb) => bomb.timestamp,
+            ChatLine::System { timestamp, .. }
+            | ChatLine::Unread { timestamp, .. }
+            | ChatLine::SessionStart { timestamp, .. } => *timestamp,
+            ChatLine::Spacer => DateTime::default(),
+        }
+    }
+
+    fn message_line_len(&self) -> usize {
+        match self {
+            ChatLine::Privmsg(privmsg) => privmsg.message_line_len(),
+            ChatLine::System { .. }
+            | ChatLine::Unread { .. }
+            | ChatLine::SessionStart { .. }
+            | ChatLine::Spacer
+            | ChatLine::GiftBomb(_) => self.message_line().graphemes(true).count(),
+        }
+    }
 
-        message_sender.send(format!("NICK {}\r\n", nick))?;
+    fn visible_line(&self, scroll: usize, width: usize) -> Cow<'_, str> {
+        match self {
+            ChatLine::Privmsg(privmsg) => privmsg.visible_line(scroll, width),
+            ChatLine::System { .. }
+            | ChatLine::Unread { .. }
+            | ChatLine::SessionStart { .. }
+            | ChatLine::Spacer
+            | ChatLine::GiftBomb(_) => {
+                Cow::Owned(fit_line(&self.message_line(), scroll, width).into_owned())
+            }
+        }
+    }
 
-        message_sender.send(format!("JOIN #{channel}\r\n"))?;
+    /// Whether `draw` leaves the line out
+    fn hidden(&self) -> bool {
+        matches!(self, ChatLine::Privmsg(privmsg) if privmsg.deleted && privmsg.render.deleted == DeletedMessages::Hide)
+    }
 
-        Ok(Self {
-            irc_message_receiver,
-            auth_token: auth_token.to_string(),
-            message_sender,
-            channel: channel.to_string(),
-            nick: nick.to_string(),
-        })
+    /// The text that gets yanked
+    fn message(&self) -> &str {
+        match self {
+            ChatLine::Privmsg(privmsg) => privmsg.text(),
+            ChatLine::System { text, .. } => text,
+            ChatLine::GiftBomb(bomb) => &bomb.text,
+            ChatLine::Unread { .. } | ChatLine::SessionStart { .. } | ChatLine::Spacer => "",
+        }
     }
 
-    fn send_message(&mut self, message: &str) -> anyhow::Result<()> {
-        let privmsg = format!("PRIVMSG #{} :{message}\r\n", self.channel);
-        self.message_sender.send(privmsg)?;
+    /// Whether a `/` search for `query` finds the line, only chat messages have a sender
+    fn matches(&self, query: &Query) -> bool {
+        let searched = match self {
+            ChatLine::Privmsg(privmsg) => Searched {
+                login: privmsg.login.as_deref(),
+                display_name: &privmsg.display_name,
+                badges: &privmsg.badges,
+                text: privmsg.text(),
+            },
+            ChatLine::System { .. } | ChatLine::GiftBomb(_) => Searched {
+                login: None,
+                display_name: "",
+                badges: &[],
+                text: self.message(),
+            },
+            ChatLine::Unread { .. } | ChatLine::SessionStart { .. } | ChatLine::Spacer => {
+                return false
+            }
+        };
 
-        Ok(())
+        query.matches(&searched)
     }
 
-    fn try_recv(&mut self) -> anyhow::Result<IRCMessage> {
-        Ok(self.irc_message_receiver.try_recv()?)
+    /// The text that gets yanked with `yY`
+    fn quote(&self, template: &str) -> String {
+        match self {
+            ChatLine::Privmsg(privmsg) => privmsg.quote(template),
+            ChatLine::System { .. }
+            | ChatLine::Unread { .. }
+            | ChatLine::SessionStart { .. }
+            | ChatLine::Spacer
+            | ChatLine::GiftBomb(_) => self.message_line().into_owned(),
+        }
     }
 }
 
 fn main() {
-    let args = std::env::args().collect::<Vec<String>>();
+    let args = Args::parse();
 
-    let (channel, auth_token) = match &args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
-        [_cmd, "--token", token, "--channel", channel] => (channel.to_string(), token.to_string()),
-        [_cmd, "--channel", channel] => (
-            channel.to_string(),
-            std::env::var("TWITCH_TOKEN").expect("should provide twitch auth token"),
-        ),
-        _ => {
-            panic!("Should provide a channel name")
+    let mut config = Config::load(args.config.as_deref()).expect("failed to load config");
+
+    let mut channels = args
+        .channels()
+        .unwrap_or_else(|e| Args::exit_with_usage(ErrorKind::ValueValidation, e));
+    // the ones from the config's `include` files after those from the command line
+    for entry in &config.channels {
+        if !channels.contains(&entry.channel) {
+            channels.push(entry.channel.clone());
         }
+    }
+    let mut channels = channels.into_iter();
+    let Some(channel) = channels.next() else {
+        Args::exit_with_usage(
+            ErrorKind::MissingRequiredArgument,
+            "a channel to join is required, on the command line or in an `include` file",
+        );
+    };
+    // opened before anything else so a wrong path is reported like other bad arguments
+    let replay_log = args.replay.as_ref().map(|path| {
+        std::fs::File::open(path).unwrap_or_else(|e| {
+            Args::exit_with_usage(ErrorKind::Io, format!("can't read {}: {e}", path.display()))
+        })
+    });
+    let replaying = replay_log.is_some();
+    // without a token we can still watch chat anonymously, a replay never logs in
+    let auth_token = match replaying {
+        true => None,
+        false => args
+            .token()
+            .unwrap_or_else(|e| Args::exit_with_usage(ErrorKind::Io, format!("{e:#}"))),
+    };
+    let mut anonymous = auth_token.is_none();
+    // --read-only, which stays on when switching accounts
+    let read_only_arg = args.read_only || replaying;
+    let mut read_only = read_only_arg || anonymous;
+    let nick = if anonymous {
+        IRC::anonymous_nick()
+    } else {
+        args.nick
+            .clone()
+            .unwrap_or_else(|| String::from("sadmadladsalman"))
     };
 
+    let proxy = args
+        .proxy
+        .clone()
+        .or_else(|| std::env::var("ALL_PROXY").ok())
+        .or_else(|| std::env::var("all_proxy").ok())
+        .map(|proxy| {
+            Proxy::parse(&proxy).unwrap_or_else(|e| {
+                Args::exit_with_usage(
+                    ErrorKind::ValueValidation,
+                    format!("invalid proxy url: {e}"),
+                )
+            })
+        });
+
+    if args.verbose || std::env::var_os("RUST_LOG").is_some() {
+        init_logging(
+            args.verbose,
+            args.log_file
+                .unwrap_or_else(|| std::env::temp_dir().join("twitcher.log")),
+        )
+        .unwrap();
+    }
+
     let mut stdout = std::io::stdout();
 
     disable_raw_mode().unwrap();
     enable_raw_mode().unwrap();
+    // for the "new messages" line, which goes where we were when the terminal lost focus
+    stdout.execute(event::EnableFocusChange).unwrap();
+    // pasted line breaks would be Enter presses otherwise, sending the first line on its own
+    stdout.execute(event::EnableBracketedPaste).unwrap();
 
     stdout
         .execute(terminal::Clear(terminal::ClearType::All))
         .unwrap();
 
-    let (mut total_columns, mut total_rows) = terminal::size().unwrap();
+    let (_, mut total_rows) = terminal::size().unwrap();
+    let mut total_columns;
 
+    // on the compose line, which is the bottom row
     let mut cursor_pos = CursorPos {
-        row: total_rows,
+        row: total_rows.saturating_sub(1),
         column: 0,
     };
 
-    let mut chat_messages: Vec<Privmsg> = Vec::new();
+    let mut recent_chatters = Chatters::new(config.chatters_limit);
+    let mut completion: Option<Completion> = None;
+    let mut mention_popup: Option<MentionPopup> = None;
+    let mut pins: Vec<Pin> = Vec::new();
+    // the row of the message scrolled with `zl` and by how many graphemes, moving to another
+    // line puts it back
+    let mut horizontal_scroll: Option<(u16, usize)> = None;
 
     let mut edit_mode = Mode::Normal;
-    stdout.execute(cursor::SetCursorStyle::SteadyBlock).unwrap();
+    // the mode the cursor was last styled for, see `Mode::cursor_style`
+    let mut cursor_mode = None;
     stdout
         .execute(event::PushKeyboardEnhancementFlags(
             event::KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES,
@@ -319,367 +1074,3320 @@ fn main() {
 
     let mut send_message = String::new();
 
-    let mut irc = IRC::new(
-        "irc.chat.twitch.tv:6667",
-        &auth_token,
-        "sadmadladsalman",
-        &channel,
-    )
-    .unwrap();
+    let mut command_line = String::new();
+    let Rules {
+        mut highlights,
+        mut hooks,
+        mut auto_replies,
+        mut timestamp_format,
+        errors: config_errors,
+    } = Rules::compile(&config);
+    let mut show_timestamps = !timestamp_format.is_empty();
 
-    let mut user_tags = None;
+    // feedback from the last `:` command, shown in place of the compose line
+    let mut command_output: Option<String> =
+        (!config_errors.is_empty()).then(|| config_errors.join(", "));
 
-    let mut clipboard = Clipboard::new().unwrap();
+    let mut theme = Theme::Default;
 
-    loop {
-        while let Ok(irc_message) = irc.try_recv() {
-            match irc_message.command {
-                IRCCommand::Privmsg { channel, message } => {
-                    chat_messages.push(Privmsg {
-                        tags: irc_message.tags,
-                        prefix: irc_message.prefix,
-                        channel,
-                        message,
-                    });
-                }
-                IRCCommand::GlobalUserState => {
-                    user_tags = Some(irc_message.tags);
-                }
-                _ => {}
-            }
-        }
+    let mut show_sidebar = false;
 
-        (total_columns, total_rows) = terminal::size().unwrap();
+    // only messages matching this are shown while it's set
+    let mut filter: Option<Regex> = None;
+    // the last `/` search, which n and N go to the next match of
+    let mut search: Option<Query> = None;
 
-        draw(
-            &mut stdout,
-            &cursor_pos,
-            &edit_mode,
-            &chat_messages,
-            &send_message,
-            total_rows,
-        )
-        .unwrap();
+    let irc = match replay_log {
+        Some(log) => Ok(IRC::replay(BufReader::new(log), &nick, &channel)),
+        None => IRC::new(
+            if args.tls {
+                "irc.chat.twitch.tv:6697"
+            } else {
+                "irc.chat.twitch.tv:6667"
+            },
+            proxy,
+            args.tls,
+            auth_token.as_deref(),
+            &nick,
+            &config.capabilities,
+            &channel,
+        ),
+    };
+    let mut irc = match irc {
+        Ok(irc) => irc,
+        Err(e) => {
+            stdout.execute(event::DisableBracketedPaste).unwrap();
+            stdout.execute(event::DisableFocusChange).unwrap();
+            disable_raw_mode().unwrap();
+            eprintln!("failed to connect to twitch: {e}");
+            std::process::exit(1);
+        }
+    };
 
-        if event::poll(Duration::from_millis(16)).unwrap() {
-            let messages_lines_start_pos = total_rows
-                .saturating_sub(chat_messages.len() as u16)
-                .saturating_sub(1);
+    for channel in channels {
+        irc.join(&channel).unwrap();
+    }
 
-            let current_message_index =
-                cursor_pos.row.saturating_sub(messages_lines_start_pos) as usize;
+    let mut tabs = irc
+        .channels()
+        .iter()
+        .map(|channel| Tab::new(channel.clone(), total_rows - 1))
+        .collect::<Vec<_>>();
+    // the tab we send to, and the one that's shown unless all channels are merged into one view
+    let mut active_tab = 0;
+    let mut merged_view = false;
 
-            match event::read().expect("failed to read event") {
-                Event::Key(key_event) => match key_event.code {
-                    event::KeyCode::Esc => {
-                        edit_mode = Mode::Normal;
-                        stdout.execute(cursor::SetCursorStyle::SteadyBlock).unwrap();
-                    }
+    // tags from GLOBALUSERSTATE, used when there is no USERSTATE for the channel
+    let mut user_tags = None;
+    let mut channel_user_tags: HashMap<String, Tags> = HashMap::new();
 
-                    event::KeyCode::Enter if matches!(edit_mode, Mode::Insert) => {
-                        if !send_message.is_empty() {
-                            if irc.send_message(&send_message).is_ok() {
-                                chat_messages.push(Privmsg {
-                                    tags: user_tags.as_ref().cloned().unwrap_or_default(),
-                                    prefix: Prefix {
-                                        nick: Some(irc.nick.clone()),
-                                        user: Some(irc.nick.clone()),
-                                        host: String::from("idk"),
-                                    },
-                                    channel: irc.channel.clone(),
-                                    message: send_message.clone(),
-                                });
+    let mut registers = Registers::new(Clipboard::new().ok());
+    // set with `"x`, used by the next yank, delete or paste
+    let mut register = None;
+    let mut last_change = None;
+    // what was typed since entering insert mode, becomes `last_change` when leaving it
+    let mut inserted = String::new();
+    // typed before a command, only `@` uses it
+    let mut count: Option<usize> = None;
+    // the register and the keys pressed since `q<register>`
+    let mut recording: Option<(char, Vec<KeyEvent>)> = None;
+    let mut macros: HashMap<char, Vec<KeyEvent>> = HashMap::new();
+    let mut last_macro = None;
+    // keys of a macro being replayed, handled before anything else is read
+    let mut replay: VecDeque<KeyEvent> = VecDeque::new();
+    let mut replayed_keys = 0;
+    // `y` was pressed to send the message that's waiting for confirmation
+    let mut confirmed = false;
+    // what was already said yes to for the message, so answering one question doesn't skip the
+    // others
+    let mut confirmations: Vec<Confirmation> = Vec::new();
 
-                                if !key_event.modifiers.contains(KeyModifiers::CONTROL) {
-                                    send_message.clear();
-                                    cursor_pos.column = 0;
-                                }
-                            }
-                        }
-                    }
+    let mut render_options = RenderOptions::from_config(&config);
 
-                    event::KeyCode::Backspace if matches!(edit_mode, Mode::Insert) => {
-                        if (cursor_pos.column as usize) <= send_message.len()
-                            && send_message.len() > 0
-                        {
-                            send_message.remove(cursor_pos.column.saturating_sub(1) as usize);
-                            cursor_pos.column = cursor_pos.column.saturating_sub(1);
-                        }
-                    }
+    let mut next_reconnect = Instant::now();
+    // set by `:reconnect`, Ctrl-r and twitch's RECONNECT, done after the next frame so it shows `[reconnecting]`
+    // while the handshake blocks
+    let mut reconnect_requested = false;
+    let mut last_ping: Option<Instant> = None;
+    // twitch's RPL_WELCOME came in, the connection being up doesn't mean the login went through
+    let mut logged_in = replaying;
 
-                    event::KeyCode::Right if matches!(edit_mode, Mode::Insert) => {
-                        cursor_pos.column = (cursor_pos.column + 1)
-                            .min(send_message.len() as u16)
-                            .min(total_columns);
-                    }
+    // the view that was scrolled up (a tab, or `None` for the merged view) and how many of its
+    // messages end at the bottom of the screen, the newest ones are shown if this isn't set
+    let mut scroll: Option<(Option<usize>, usize)> = None;
+    let mut last_key_press = Instant::now();
+    // while the terminal isn't focused every message counts as unread, even in the active tab
+    let mut focused = true;
+    let mut notifier = Notifier::default();
+    let mut sound_player = sound::Player::default();
+    let mut emote_images = config
+        .emote_images
+        .then(Protocol::detect)
+        .flatten()
+        .map(EmoteImages::new);
+    // what's drawn in the chat rows, for `scroll_region`
+    let mut chat_rows = ChatRows::default();
 
-                    event::KeyCode::Left if matches!(edit_mode, Mode::Insert) => {
-                        cursor_pos.column = cursor_pos.column.saturating_sub(1);
-                    }
+    // put between messages in the list that's drawn, see `with_spacers`
+    let spacer = ChatLine::Spacer;
+    // `msg-param-community-gift-id`s of the gift bombs opened with `za`
+    let mut expanded_gifts = HashSet::new();
 
-                    event::KeyCode::End if matches!(edit_mode, Mode::Insert) => {
-                        cursor_pos.column = send_message.len() as u16;
-                    }
+    // what was last sent to each channel and when, for the duplicate check
+    let mut last_sent: HashMap<String, (String, Instant)> = HashMap::new();
 
-                    event::KeyCode::Char(c) => match c {
-                        'q' if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
-                            break;
+    loop {
+        let pong_timed_out = irc
+            .unanswered_ping()
+            .is_some_and(|waiting| waiting > PONG_TIMEOUT);
+        // a replay has nothing to lose, or to reconnect to
+        let mut connected = replaying
+            || (!irc.is_closed()
+                && !pong_timed_out
+                && irc.last_received().elapsed() <= HEARTBEAT_TIMEOUT);
+
+        if !connected && Instant::now() >= next_reconnect {
+            tracing::warn!(
+                closed = irc.is_closed(),
+                pong_timed_out,
+                "connection lost, reconnecting"
+            );
+
+            command_output = Some(match irc.reconnect() {
+                Ok(()) => {
+                    // the latency of the new connection
+                    last_ping = None;
+                    logged_in = false;
+                    connected = true;
+                    String::from("connection lost, reconnected")
+                }
+                Err(e) => {
+                    next_reconnect = Instant::now() + RECONNECT_DELAY;
+                    format!("connection lost, failed to reconnect: {e}")
+                }
+            });
+        }
+
+        // messages still waiting for the rate limiter weren't sent yet
+        for tab in &mut tabs {
+            if !irc
+                .queued()
+                .iter()
+                .any(|queued| queued.channel == tab.channel)
+            {
+                tab.expire_pending(Instant::now(), DELIVERY_TIMEOUT);
+            }
+        }
+
+        if let Err(e) = irc.flush() {
+            tracing::error!(%e, "failed to send queued messages");
+        }
+
+        if !replaying
+            && !irc.is_closed()
+            && last_ping.is_none_or(|last_ping| last_ping.elapsed() >= PING_INTERVAL)
+        {
+            if let Err(e) = irc.ping() {
+                tracing::error!(%e, "failed to send PING");
+            }
+            last_ping = Some(Instant::now());
+        }
+
+        let on_screen = |index: usize| focused && (merged_view || index == active_tab);
+
+        while let Ok(irc_message) = irc.try_recv() {
+            let mut suppressed = false;
+            let mut annotations = Vec::new();
+            let mut replies = Vec::new();
+            for action in hooks.run(&irc_message) {
+                match action {
+                    HookAction::Suppress => suppressed = true,
+                    HookAction::Send { channel, message } => replies.push((channel, message)),
+                    HookAction::Annotate(annotation) => annotations.push(annotation),
+                }
+            }
+
+            if suppressed {
+                continue;
+            }
+
+            match irc_message.command {
+                IRCCommand::Privmsg { channel, message } => {
+                    let Some(index) = tabs.iter().position(|tab| tab.channel == channel) else {
+                        continue;
+                    };
+
+                    // our own message coming back, e.g. when the same account is logged in
+                    // somewhere else too, it's already shown
+                    if irc_message.tags.get("client-nonce").is_some() {
+                        if let Some(privmsg) = tabs[index].answered(&irc_message.tags) {
+                            privmsg.delivery = Delivery::Delivered;
+                            continue;
                         }
-                        'c' if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
-                            break;
+                    }
+
+                    if irc_message.tags.get("bits").is_some() {
+                        sound_player.play(sound::Event::Bits, &config.sounds, Instant::now());
+                    }
+
+                    let mut privmsg =
+                        Privmsg::new(irc_message.tags, irc_message.prefix, channel, message);
+                    privmsg.set_annotations(annotations);
+                    privmsg.set_render(render_options);
+
+                    tracing::trace!(id = privmsg.id(), "{privmsg}");
+                    recent_chatters.seen(privmsg.display_name(), privmsg.timestamp);
+
+                    let mentioned = !anonymous && notify::mentions(privmsg.text(), irc.nick());
+                    let notify = !focused
+                        && match config.notifications {
+                            Notifications::Off => false,
+                            Notifications::Mentions => mentioned,
+                            Notifications::Highlights => {
+                                mentioned
+                                    || highlights.iter().any(|highlight| {
+                                        highlight.matches(&privmsg.channel, privmsg.text())
+                                    })
+                            }
+                        };
+                    if notify {
+                        notifier.queue(&privmsg.channel, privmsg.display_name(), privmsg.text());
+                    }
+
+                    tabs[index].push(ChatLine::Privmsg(privmsg), on_screen(index));
+                }
+                IRCCommand::HostTarget {
+                    channel,
+                    target,
+                    viewers,
+                } => {
+                    if target.is_some() {
+                        sound_player.play(sound::Event::Host, &config.sounds, Instant::now());
+                    }
+
+                    let text = match (target, viewers) {
+                        (Some(target), Some(viewers)) => {
+                            format!("#{channel} is now hosting {target} for {viewers} viewers")
                         }
+                        (Some(target), None) => format!("#{channel} is now hosting {target}"),
+                        (None, _) => format!("#{channel} exited host mode"),
+                    };
 
-                        'i' if matches!(edit_mode, Mode::Normal) => {
-                            edit_mode = Mode::Insert;
-                            stdout.execute(cursor::SetCursorStyle::SteadyBar).unwrap();
-                            if cursor_pos.row < total_rows - 1 {
-                                cursor_pos.row = total_rows.saturating_sub(1);
-                                cursor_pos.column = send_message.graphemes(true).count() as u16;
+                    if let Some(index) = tabs.iter().position(|tab| tab.channel == channel) {
+                        tabs[index].notice(text, on_screen(index));
+                    }
+                }
+                IRCCommand::ClearMsg { channel, .. } => {
+                    let Some(tab) = tabs.iter_mut().find(|tab| tab.channel == channel) else {
+                        continue;
+                    };
+                    let Some(target) = irc_message.tags.get("target-msg-id") else {
+                        continue;
+                    };
+
+                    for line in &mut tab.messages {
+                        if let ChatLine::Privmsg(privmsg) = line {
+                            if privmsg.id() == Some(target) {
+                                privmsg.set_deleted();
                             }
                         }
+                    }
+                }
+                IRCCommand::ClearChat { channel, user } => {
+                    let Some(index) = tabs.iter().position(|tab| tab.channel == channel) else {
+                        continue;
+                    };
 
-                        'h' if matches!(edit_mode, Mode::Normal) => {
-                            if cursor_pos.row >= total_rows - 1 {
-                                cursor_pos.column = cursor_pos.column.saturating_sub(1);
-                            } else {
-                                if let Some(new_pos) = cursor_pos.column.checked_sub(1) {
-                                    cursor_pos.column = new_pos;
-                                } else {
-                                    if messages_lines_start_pos > cursor_pos.row {
-                                        // TODO: Handle going to previous line
-                                        cursor_pos.row = cursor_pos.row.saturating_sub(1);
-                                        // cursor_pos.column = cursor_pos.column.max(
-                                        //     chat_lines[chat_lines.len() - cursor_pos.row as usize]
-                                        //         .message
-                                        //         .len() as u16,
-                                        // );
-                                    }
-                                }
+                    let tab = &mut tabs[index];
+                    for line in &mut tab.messages {
+                        if let ChatLine::Privmsg(privmsg) = line {
+                            if user.is_none() || privmsg.login == user {
+                                privmsg.set_deleted();
                             }
                         }
-                        'j' if matches!(edit_mode, Mode::Normal) => {
-                            cursor_pos.row = (total_rows - 1).min(cursor_pos.row + 1);
+                    }
 
-                            if cursor_pos.row >= total_rows - 1 {
-                                cursor_pos.column = cursor_pos
-                                    .column
-                                    .min(send_message.graphemes(true).count() as u16);
-                            } else {
-                                let current_message = chat_messages
-                                    .get((cursor_pos.row - messages_lines_start_pos) as usize);
+                    let text = match (user, irc_message.tags.get("ban-duration")) {
+                        (Some(user), Some(duration)) => {
+                            format!("{user} was timed out for {duration}s")
+                        }
+                        (Some(user), None) => format!("{user} was banned"),
+                        (None, _) => String::from("chat was cleared by a moderator"),
+                    };
+                    tab.notice(text, on_screen(index));
+                }
+                IRCCommand::UserNotice { channel, message } => {
+                    let Some(index) = tabs.iter().position(|tab| tab.channel == channel) else {
+                        continue;
+                    };
 
-                                let Some(current_message) = current_message else {
-                                    continue;
-                                };
+                    if let Some(event) = irc_message
+                        .tags
+                        .get("msg-id")
+                        .and_then(|msg_id| sound::Event::from_user_notice(msg_id))
+                    {
+                        sound_player.play(event, &config.sounds, Instant::now());
+                    }
+                    tabs[index].user_notice(
+                        &irc_message.tags,
+                        message.as_deref(),
+                        on_screen(index),
+                    );
+                }
+                IRCCommand::Notice { channel, message } => {
+                    // notices that aren't about a channel go where we're looking
+                    let index = channel
+                        .and_then(|channel| tabs.iter().position(|tab| tab.channel == channel))
+                        .unwrap_or(active_tab);
 
-                                cursor_pos.column = cursor_pos
-                                    .column
-                                    .min(current_message.message_line_len() as u16);
-                            }
+                    let msg_id = irc_message.tags.get("msg-id").map_or("", String::as_str);
+                    // `msg_duplicate`, `msg_slowmode` and the like say a message was dropped
+                    if msg_id.starts_with("msg_") {
+                        if let Some(privmsg) = tabs[index].answered(&irc_message.tags) {
+                            privmsg.delivery = Delivery::Failed;
                         }
-                        'k' if matches!(edit_mode, Mode::Normal) => {
-                            if messages_lines_start_pos < cursor_pos.row && chat_messages.len() > 0
-                            {
-                                if let Some(new_pos) = cursor_pos.row.checked_sub(1) {
-                                    cursor_pos.row = new_pos;
-
-                                    // cursor_pos.column = cursor_pos.column.min(
-                                    //     chat_lines[chat_lines.len() - cursor_pos.row as usize]
-                                    //         .message
-                                    //         .len() as u16,
-                                    // )
-                                }
-                                // println!("k: {messages_lines_start_pos}: {}", cursor_pos.row);
-                            }
+                    }
+                    tabs[index].colored_notice(
+                        format!("twitch: {message}"),
+                        notice_color(msg_id),
+                        on_screen(index),
+                    );
+                }
+                IRCCommand::Welcome { .. } => {
+                    logged_in = true;
+                }
+                // handled after the next frame like `:reconnect`, rejoining every tab's channel
+                IRCCommand::Reconnect => {
+                    tracing::info!("twitch asked to reconnect");
+                    reconnect_requested = true;
+                }
+                IRCCommand::GlobalUserState => {
+                    user_tags = Some(irc_message.tags);
+                }
+                IRCCommand::UserState { channel } => {
+                    // also how twitch says it got a message of ours
+                    if let Some(tab) = tabs.iter_mut().find(|tab| tab.channel == channel) {
+                        if let Some(privmsg) = tab.answered(&irc_message.tags) {
+                            privmsg.delivery = Delivery::Delivered;
                         }
-                        'l' if matches!(edit_mode, Mode::Normal) => {
-                            if cursor_pos.row >= total_rows - 1 {
-                                if send_message.len() > cursor_pos.column as usize {
-                                    cursor_pos.column += 1;
-                                }
-                            } else {
-                                let Some(current_message) =
-                                    chat_messages.get(current_message_index)
-                                else {
-                                    continue;
-                                };
+                    }
+                    channel_user_tags.insert(channel, irc_message.tags);
+                }
+                IRCCommand::RoomState { channel } => {
+                    let Some(index) = tabs.iter().position(|tab| tab.channel == channel) else {
+                        continue;
+                    };
 
-                                if current_message.message_line_len() <= cursor_pos.column as usize
-                                    && chat_messages.len() <= cursor_pos.row as usize
-                                {
-                                    cursor_pos.row += 1;
-                                    cursor_pos.column = 0;
-                                } else {
-                                    cursor_pos.column += 1;
-                                }
-                            }
+                    let tab = &mut tabs[index];
+                    // later ROOMSTATEs only have the tags that changed
+                    if let Some(slow) = irc_message.tags.get("slow") {
+                        tab.slow_mode = slow
+                            .parse()
+                            .ok()
+                            .filter(|seconds| *seconds > 0)
+                            .map(Duration::from_secs);
+                    }
+                    if let Some(emote_only) = irc_message.tags.get("emote-only") {
+                        tab.emote_only = emote_only == "1";
+                    }
+
+                    if !matches!(tab.join_state, JoinState::Joined) {
+                        tab.join_state = JoinState::Joined;
+
+                        let no_messages = tab
+                            .messages
+                            .iter()
+                            .all(|line| matches!(line, ChatLine::SessionStart { .. }));
+                        if no_messages {
+                            tab.notice(
+                                format!("joined #{channel}, no messages yet"),
+                                on_screen(index),
+                            );
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            // after the message itself, so the reply shows up below it
+            let mut sent_replies = Vec::new();
+            for (channel, message) in replies {
+                if read_only {
+                    tracing::info!("not sending hook reply to #{channel} in read-only mode");
+                    continue;
+                }
+
+                let Some(index) = tabs.iter().position(|tab| tab.channel == channel) else {
+                    tracing::warn!("hook tried to send to #{channel}, which we're not in");
+                    continue;
+                };
+
+                if let Some(wait) = slow_mode_wait(
+                    &tabs[index],
+                    channel_user_tags.get(&channel),
+                    last_sent.get(&channel),
+                    Instant::now(),
+                ) {
+                    tracing::warn!(?wait, "not sending hook reply to #{channel} in slow mode");
+                    continue;
+                }
+
+                let Some(outgoing) = dedup_message(
+                    &message,
+                    last_sent.get(&channel),
+                    Instant::now(),
+                    config.bypass_duplicates,
+                ) else {
+                    tracing::warn!("not sending duplicate hook reply to #{channel}");
+                    continue;
+                };
+
+                let nonce = match irc.send_message(&channel, &outgoing) {
+                    Ok(nonce) => nonce,
+                    Err(e) => {
+                        tracing::error!(%e, "failed to send hook reply");
+                        continue;
+                    }
+                };
+                last_sent.insert(channel.clone(), (outgoing, Instant::now()));
+                sent_replies.push((channel.clone(), message.clone()));
+
+                let privmsg = own_message(
+                    &irc,
+                    channel_user_tags.get(&channel).or(user_tags.as_ref()),
+                    channel,
+                    message,
+                    nonce,
+                    config.confirm_sent,
+                );
+                tabs[index].push(ChatLine::Privmsg(privmsg), on_screen(index));
+            }
+            for auto_reply in &auto_replies {
+                auto_reply.settle(&sent_replies, Instant::now());
+            }
+        }
+
+        notifier.flush(Instant::now());
+
+        for (index, tab) in tabs.iter_mut().enumerate() {
+            if let JoinState::Pending(joined_at) = tab.join_state {
+                if joined_at.elapsed() > JOIN_TIMEOUT {
+                    tracing::warn!("no ROOMSTATE for #{}", tab.channel);
+                    tab.join_state = JoinState::Unconfirmed;
+
+                    let text = format!(
+                        "twitch didn't confirm joining #{}, check that the channel exists",
+                        tab.channel
+                    );
+                    tab.notice(text, on_screen(index));
+                }
+            }
+        }
+
+        (total_columns, total_rows) = terminal::size().unwrap();
+
+        // what's actually on screen, navigation works on this rather than all of chat_messages
+        let mut visible_messages = if merged_view {
+            tabs.iter()
+                .flat_map(|tab| &tab.messages)
+                .collect::<Vec<_>>()
+        } else {
+            tabs[active_tab].messages.iter().collect()
+        };
+        visible_messages.retain(|message| {
+            !message.hidden()
+                && filter
+                    .as_ref()
+                    .is_none_or(|filter| filter.is_match(&message.message_line()))
+        });
+        if merged_view {
+            // stable, so lines with the same timestamp stay in the order they came in
+            visible_messages.sort_by_key(|message| message.timestamp());
+        }
+        if config.density == Density::Cozy {
+            visible_messages = with_spacers(visible_messages, &spacer, config.group_messages);
+        }
+        visible_messages = with_gift_recipients(visible_messages, &expanded_gifts);
+
+        let scroll_resume = Duration::from_secs(config.scroll_resume);
+        if config.scroll_resume > 0 && last_key_press.elapsed() >= scroll_resume {
+            scroll = None;
+        }
+
+        if let Some(emote_images) = &mut emote_images {
+            for message in &visible_messages {
+                if let ChatLine::Privmsg(privmsg) = message {
+                    for (id, _) in &privmsg.emotes {
+                        emote_images.fetch(id);
+                    }
+                }
+            }
+            emote_images.poll(&mut stdout).unwrap();
+        }
+
+        // closed once the cursor leaves the mention, e.g. when a space is typed
+        let cursor = byte_offset(&send_message, cursor_pos.column as usize);
+        let mention_query = mention_popup
+            .as_ref()
+            .filter(|_| matches!(edit_mode, Mode::Insert) && cursor_pos.row >= total_rows - 1)
+            .and_then(|popup| popup.query(&send_message, cursor));
+        let mention_candidates = match mention_query {
+            Some(query) => mention_popup::candidates(query, recent_chatters.names())
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>(),
+            None => {
+                mention_popup = None;
+                Vec::new()
+            }
+        };
+
+        let shown_pins = pins
+            .iter()
+            .filter(|pin| merged_view || pin.channel == tabs[active_tab].channel)
+            .map(|pin| pin.line.as_str())
+            .collect::<Vec<_>>();
+        let messages_top = tab_bar_rows(&tabs) + shown_pins.len() as u16;
+
+        let prompt = fill_channel(
+            &config.prompt,
+            &tabs[active_tab].channel,
+            config.channel_hash,
+        )
+        .replace("{nick}", irc.nick());
+        // a long message wraps upwards into the chat, out of the way for `:` and its output
+        let compose_rows =
+            if command_output.is_none() && !matches!(edit_mode, Mode::Command | Mode::Search) {
+                compose_rows(&prompt, &send_message, total_columns)
+                    .min(total_rows.saturating_sub(messages_top).max(1))
+            } else {
+                1
+            };
+        let layout = Layout::new(messages_top, compose_rows, config.max_messages, total_rows);
+        let compose_top = layout.compose_top;
+
+        let view = (!merged_view).then_some(active_tab);
+        let message_rows = layout.message_rows as usize;
+        let total_messages = visible_messages.len();
+        let scroll_end = scroll
+            .filter(|(scrolled_view, _)| *scrolled_view == view)
+            .map(|(_, end)| end.clamp(message_rows.min(total_messages), total_messages));
+        let messages_below = scroll_end.map_or(0, |end| {
+            visible_messages[end..]
+                .iter()
+                .filter(|message| !matches!(message, ChatLine::Spacer))
+                .count()
+        });
+        let unread_index = visible_messages
+            .iter()
+            .position(|message| matches!(message, ChatLine::Unread { .. }));
+        let search_matches = search.as_ref().map_or_else(Vec::new, |query| {
+            visible_messages
+                .iter()
+                .enumerate()
+                .filter(|(_, message)| message.matches(query))
+                .map(|(index, _)| index)
+                .collect::<Vec<_>>()
+        });
+        let view_end = scroll_end.unwrap_or(total_messages);
+        visible_messages.truncate(view_end);
+        visible_messages.drain(..visible_messages.len().saturating_sub(message_rows));
+        // the index of the first message on screen
+        let view_start = view_end - visible_messages.len();
+
+        if cursor_mode != Some(edit_mode) {
+            stdout.execute(edit_mode.cursor_style()).unwrap();
+            cursor_mode = Some(edit_mode);
+        }
+
+        draw(
+            &mut stdout,
+            &cursor_pos,
+            &edit_mode,
+            &visible_messages,
+            &send_message,
+            &command_line,
+            command_output.as_deref(),
+            theme,
+            &highlights,
+            &tabs,
+            active_tab,
+            merged_view,
+            show_sidebar.then(|| Sidebar {
+                channel: channel_label(&tabs[active_tab].channel, config.channel_hash),
+                chatters: &recent_chatters,
+                width: config.sidebar_width,
+            }),
+            config.group_messages,
+            // with a single channel it's obvious where messages are from
+            (tabs.len() > 1).then_some(config.channel_prefix.as_str()),
+            config.channel_hash,
+            show_timestamps.then_some(timestamp_format.as_str()),
+            (config.fade_after > 0).then(|| TimeDelta::seconds(config.fade_after as i64)),
+            &prompt,
+            layout,
+            emote_images.as_ref(),
+            &shown_pins,
+            mention_popup
+                .as_ref()
+                .filter(|_| !mention_candidates.is_empty())
+                .map(|popup| Popup {
+                    names: &mention_candidates,
+                    selected: popup.selected,
+                    column: send_message[..popup.start()].graphemes(true).count() as u16,
+                }),
+            scroll_columns(horizontal_scroll, cursor_pos.row),
+            match edit_mode {
+                Mode::Visual { anchor } => {
+                    let first_row = layout.first_message_row(visible_messages.len());
+                    visual_selection(
+                        anchor,
+                        cursor_pos.row,
+                        first_row,
+                        view_start,
+                        visible_messages.len(),
+                    )
+                    .map(|selection| {
+                        first_row + *selection.start() as u16..=first_row + *selection.end() as u16
+                    })
+                }
+                _ => None,
+            },
+            status(
+                read_only,
+                anonymous,
+                tabs[active_tab].emote_only,
+                slow_mode_wait(
+                    &tabs[active_tab],
+                    channel_user_tags.get(&tabs[active_tab].channel),
+                    last_sent.get(&tabs[active_tab].channel),
+                    Instant::now(),
+                ),
+                irc.queued().len(),
+                recording.as_ref().map(|(register, _)| *register),
+                messages_below,
+                connected && !reconnect_requested,
+                logged_in,
+                irc.latency(),
+            )
+            .as_str(),
+            &mut chat_rows,
+            config.scroll_region,
+            total_columns,
+            total_rows,
+        )
+        .unwrap();
+
+        if std::mem::take(&mut reconnect_requested) {
+            command_output = Some(match irc.reconnect() {
+                Ok(()) => {
+                    last_ping = None;
+                    logged_in = false;
+                    String::from("reconnected")
+                }
+                Err(e) => format!("failed to reconnect: {e}"),
+            });
+            continue;
+        }
+
+        if !replay.is_empty() || event::poll(Duration::from_millis(16)).unwrap() {
+            let messages_lines_start_pos = layout.first_message_row(visible_messages.len());
+
+            let current_message_index =
+                cursor_pos.row.saturating_sub(messages_lines_start_pos) as usize;
+
+            // the line under the cursor, either the compose line or a chat message
+            let current_line = if cursor_pos.row >= total_rows - 1 {
+                Some(send_message.clone())
+            } else {
+                visible_messages
+                    .get(current_message_index)
+                    .map(|message| message.message_line().into_owned())
+            };
+
+            let (event, replayed) = match replay.pop_front() {
+                Some(key_event) => {
+                    replayed_keys += 1;
+                    (Event::Key(key_event), true)
+                }
+                None => {
+                    replayed_keys = 0;
+                    (event::read().expect("failed to read event"), false)
+                }
+            };
+
+            match event {
+                Event::FocusLost => {
+                    focused = false;
+                    tabs.iter_mut().for_each(Tab::mark_read);
+                    continue;
+                }
+                Event::FocusGained => {
+                    focused = true;
+                    for (index, tab) in tabs.iter_mut().enumerate() {
+                        if merged_view || index == active_tab {
+                            tab.unread = 0;
                         }
+                        tab.drop_unread_line();
+                    }
+                    continue;
+                }
+                Event::Paste(text) if matches!(edit_mode, Mode::Insert) => {
+                    let text = pasted_text(&text);
+                    cursor_pos.column = insert_text(&mut send_message, cursor_pos.column, &text);
+                    inserted.push_str(&text);
+                    continue;
+                }
+                Event::Paste(text) if matches!(edit_mode, Mode::Command | Mode::Search) => {
+                    command_line.push_str(&text.replace(['\r', '\n'], " "));
+                    continue;
+                }
+                _ => {}
+            }
 
-                        'b' if matches!(edit_mode, Mode::Normal) => {
-                            if cursor_pos.row >= total_rows - 1 {
-                                cursor_pos.column =
-                                    send_message[..cursor_pos.column.saturating_sub(1) as usize]
-                                        .rfind(' ')
-                                        .map(|i| i + 1)
-                                        .unwrap_or(0) as u16;
-                            } else {
-                                let Some(current_message) =
-                                    chat_messages.get(current_message_index)
-                                else {
-                                    continue;
-                                };
+            if let Event::Key(key_event) = event {
+                command_output = None;
+                last_key_press = Instant::now();
+                let given_count = count.take();
 
-                                cursor_pos.column = current_message.message_line()
-                                    [..cursor_pos.column.saturating_sub(1) as usize]
-                                    .rfind(' ')
-                                    .map(|i| i + 1)
-                                    .unwrap_or(0)
-                                    as u16;
+                // a macro replayed while recording is recorded as the `@` keys, not what they did
+                if let Some((_, keys)) = &mut recording {
+                    if !replayed {
+                        keys.push(key_event);
+                    }
+                }
+
+                if !matches!(
+                    key_event.code,
+                    event::KeyCode::Tab | event::KeyCode::BackTab
+                ) {
+                    completion = None;
+                }
+
+                match key_event.code {
+                    // Esc only closes the popup, insert mode is left with the next one
+                    event::KeyCode::Esc if mention_popup.is_some() => {
+                        mention_popup = None;
+                    }
+
+                    event::KeyCode::Up | event::KeyCode::Down
+                        if mention_popup.is_some() && !mention_candidates.is_empty() =>
+                    {
+                        if let Some(popup) = &mut mention_popup {
+                            popup.select(
+                                key_event.code == event::KeyCode::Down,
+                                mention_candidates.len(),
+                            );
+                        }
+                    }
+
+                    event::KeyCode::Enter
+                        if mention_popup.is_some() && !mention_candidates.is_empty() =>
+                    {
+                        if let Some(popup) = mention_popup.take() {
+                            let name = &mention_candidates[popup.selected];
+                            let end = popup.insert(&mut send_message, cursor, name);
+                            cursor_pos.column = send_message[..end].graphemes(true).count() as u16;
+                            inserted.push_str(&format!("@{name} "));
+                        }
+                    }
+
+                    event::KeyCode::Esc => {
+                        if matches!(edit_mode, Mode::Insert) && !inserted.is_empty() {
+                            last_change = Some(Change::Insert(std::mem::take(&mut inserted)));
+                        }
+
+                        edit_mode = Mode::Normal;
+                        register = None;
+                        command_line.clear();
+                    }
+
+                    event::KeyCode::Enter if matches!(edit_mode, Mode::Search) => {
+                        edit_mode = Mode::Normal;
+
+                        match Query::parse(&command_line) {
+                            Ok(query) => {
+                                search = Some(query);
+                                // once the next frame found what it matches
+                                replay.push_front(KeyEvent::from(event::KeyCode::Char('n')));
                             }
+                            Err(e) => command_output = Some(format!("invalid search: {e}")),
                         }
-                        'w' if matches!(edit_mode, Mode::Normal) => {
-                            if cursor_pos.row >= total_rows - 1 {
-                                if let Some(send_message) =
-                                    send_message.get((cursor_pos.column + 1) as usize..)
+
+                        command_line.clear();
+                    }
+
+                    event::KeyCode::Enter if matches!(edit_mode, Mode::Command) => {
+                        edit_mode = Mode::Normal;
+
+                        match Action::parse(&command_line) {
+                            Ok(Action::Quit) => break,
+                            Ok(Action::Clear) => {
+                                if merged_view {
+                                    tabs.iter_mut().for_each(|tab| tab.messages.clear());
+                                } else {
+                                    tabs[active_tab].messages.clear();
+                                }
+                                cursor_pos.row = total_rows - 1;
+                                cursor_pos.column = 0;
+                            }
+                            Ok(Action::Reconnect) => {
+                                reconnect_requested = true;
+                            }
+                            Ok(Action::Raw(_)) if !config.allow_raw => {
+                                command_output = Some(String::from(
+                                    "set allow_raw in the config to send raw lines",
+                                ));
+                            }
+                            Ok(Action::Raw(_)) if read_only => {
+                                command_output = Some(String::from("read-only, not sent"));
+                            }
+                            Ok(Action::Raw(line)) => {
+                                command_output = Some(match irc.send_raw(&line) {
+                                    Ok(()) => format!("sent {line}"),
+                                    Err(e) => format!("failed to send: {e}"),
+                                });
+                            }
+                            Ok(Action::Channel(channel)) => {
+                                if let Some(index) =
+                                    tabs.iter().position(|tab| tab.channel == channel)
+                                {
+                                    switch_tab(
+                                        &mut tabs,
+                                        &mut active_tab,
+                                        merged_view,
+                                        index,
+                                        &mut cursor_pos,
+                                    );
+                                } else if let Err(e) = irc
+                                    .part(&tabs[active_tab].channel)
+                                    .and_then(|()| irc.join(&channel))
                                 {
-                                    cursor_pos.column +=
-                                        send_message.find(' ').map(|i| i + 1).unwrap_or(
-                                            send_message
-                                                .graphemes(true)
-                                                .count()
-                                                .saturating_sub(cursor_pos.column as usize),
-                                        ) as u16;
+                                    command_output = Some(format!("failed to switch channel: {e}"));
+                                } else {
+                                    tabs[active_tab] = Tab::new(channel, total_rows - 1);
+                                    cursor_pos = CursorPos {
+                                        row: total_rows - 1,
+                                        column: 0,
+                                    };
                                 }
-                            } else {
-                                let Some(current_message) =
-                                    chat_messages.get(current_message_index)
-                                else {
+                            }
+                            Ok(Action::Join(channel)) => {
+                                if let Some(index) =
+                                    tabs.iter().position(|tab| tab.channel == channel)
+                                {
+                                    switch_tab(
+                                        &mut tabs,
+                                        &mut active_tab,
+                                        merged_view,
+                                        index,
+                                        &mut cursor_pos,
+                                    );
+                                } else if let Err(e) = irc.join(&channel) {
+                                    command_output = Some(format!("failed to join channel: {e}"));
+                                } else {
+                                    tabs.push(Tab::new(channel, total_rows - 1));
+                                    let index = tabs.len() - 1;
+                                    switch_tab(
+                                        &mut tabs,
+                                        &mut active_tab,
+                                        merged_view,
+                                        index,
+                                        &mut cursor_pos,
+                                    );
+                                }
+                            }
+                            Ok(Action::Part) => {
+                                if tabs.len() == 1 {
+                                    command_output = Some(String::from(
+                                        "can't leave the only channel, use :channel to switch",
+                                    ));
+                                } else if let Err(e) = irc.part(&tabs[active_tab].channel) {
+                                    command_output = Some(format!("failed to leave channel: {e}"));
+                                } else {
+                                    tabs.remove(active_tab);
+                                    active_tab = active_tab.min(tabs.len() - 1);
+                                    if !merged_view {
+                                        std::mem::swap(
+                                            &mut cursor_pos,
+                                            &mut tabs[active_tab].cursor_pos,
+                                        );
+                                    }
+                                    tabs[active_tab].unread = 0;
+                                }
+                            }
+                            Ok(Action::Theme(new_theme)) => {
+                                theme = new_theme;
+                            }
+                            Ok(Action::Chatters) => {
+                                let now = Local::now();
+                                let chatters = recent_chatters
+                                    .iter()
+                                    .map(|chatter| {
+                                        format!(
+                                            "{} ({})",
+                                            chatter.name,
+                                            format_ago(now - chatter.last_seen)
+                                        )
+                                    })
+                                    .collect::<Vec<_>>();
+
+                                command_output = Some(if chatters.is_empty() {
+                                    String::from("nobody talked yet")
+                                } else {
+                                    chatters.join(", ")
+                                });
+                            }
+                            Ok(Action::ToggleMerged) => {
+                                toggle_merged_view(
+                                    &mut tabs,
+                                    active_tab,
+                                    &mut merged_view,
+                                    &mut cursor_pos,
+                                    total_rows - 1,
+                                );
+                            }
+                            Ok(Action::Queue) => {
+                                let queued = irc
+                                    .queued()
+                                    .iter()
+                                    .map(|outgoing| {
+                                        format!("#{}: {}", outgoing.channel, outgoing.message)
+                                    })
+                                    .collect::<Vec<_>>();
+
+                                command_output = Some(if queued.is_empty() {
+                                    String::from("nothing queued")
+                                } else {
+                                    queued.join(", ")
+                                });
+                            }
+                            Ok(Action::Cancel) => {
+                                let cancelled = irc.cancel_queued();
+                                command_output =
+                                    Some(format!("cancelled {} queued messages", cancelled.len()));
+
+                                for outgoing in cancelled {
+                                    if let Some(tab) =
+                                        tabs.iter_mut().find(|tab| tab.channel == outgoing.channel)
+                                    {
+                                        tab.notice(format!("not sent: {}", outgoing.message), true);
+                                    }
+                                }
+                            }
+                            Ok(Action::ToggleSidebar) => {
+                                show_sidebar = !show_sidebar;
+                            }
+                            Ok(Action::Account(None)) => {
+                                let accounts = config
+                                    .accounts
+                                    .iter()
+                                    .map(|account| account.nick.as_str())
+                                    .collect::<Vec<_>>();
+                                command_output = Some(if accounts.is_empty() {
+                                    String::from("no accounts in the config")
+                                } else {
+                                    format!(
+                                        "sending as {}, accounts: {}",
+                                        irc.nick(),
+                                        accounts.join(", ")
+                                    )
+                                });
+                            }
+                            Ok(Action::Account(Some(nick))) => {
+                                let Some(account) = config
+                                    .accounts
+                                    .iter()
+                                    .find(|account| account.nick.eq_ignore_ascii_case(&nick))
+                                else {
+                                    command_output = Some(format!("no account named {nick}"));
+                                    continue;
+                                };
+
+                                match irc.switch_account(Some(&account.token), &account.nick) {
+                                    Ok(cancelled) => {
+                                        anonymous = false;
+                                        read_only = read_only_arg;
+                                        // USERSTATEs for the new account come in after joining
+                                        user_tags = None;
+                                        channel_user_tags.clear();
+                                        command_output =
+                                            Some(format!("sending as {}", account.nick));
+
+                                        for outgoing in cancelled {
+                                            if let Some(tab) = tabs
+                                                .iter_mut()
+                                                .find(|tab| tab.channel == outgoing.channel)
+                                            {
+                                                tab.notice(
+                                                    format!("not sent: {}", outgoing.message),
+                                                    true,
+                                                );
+                                            }
+                                        }
+                                    }
+                                    Err(e) => {
+                                        command_output =
+                                            Some(format!("failed to switch account: {e}"));
+                                    }
+                                }
+                            }
+                            Ok(Action::Reload) => {
+                                let reloaded = match Config::load(args.config.as_deref()) {
+                                    Ok(reloaded) => reloaded,
+                                    Err(e) => {
+                                        command_output = Some(format!("{e}, kept the old config"));
+                                        continue;
+                                    }
+                                };
+                                config = reloaded;
+
+                                let rules = Rules::compile(&config);
+                                highlights = rules.highlights;
+                                hooks = rules.hooks;
+                                auto_replies = rules.auto_replies;
+                                timestamp_format = rules.timestamp_format;
+                                show_timestamps = !timestamp_format.is_empty();
+
+                                render_options = RenderOptions::from_config(&config);
+                                for line in tabs.iter_mut().flat_map(|tab| &mut tab.messages) {
+                                    if let ChatLine::Privmsg(privmsg) = line {
+                                        privmsg.set_render(render_options);
+                                    }
+                                }
+
+                                recent_chatters.set_capacity(config.chatters_limit);
+                                if config.emote_images != emote_images.is_some() {
+                                    emote_images = config
+                                        .emote_images
+                                        .then(Protocol::detect)
+                                        .flatten()
+                                        .map(EmoteImages::new);
+                                }
+
+                                command_output = Some(if rules.errors.is_empty() {
+                                    String::from("reloaded the config")
+                                } else {
+                                    rules.errors.join(", ")
+                                });
+                            }
+                            Ok(Action::Timestamps(None)) => {
+                                show_timestamps = !show_timestamps;
+                                if timestamp_format.is_empty() {
+                                    timestamp_format = Config::default().timestamp_format;
+                                }
+                            }
+                            Ok(Action::Timestamps(Some(format))) => {
+                                show_timestamps = true;
+                                timestamp_format = format;
+                            }
+                            Ok(Action::Filter(pattern)) => {
+                                match pattern.as_deref().map(Regex::new).transpose() {
+                                    Ok(new_filter) => {
+                                        filter = new_filter;
+                                        // back to the live tail, the filtered view might be shorter
+                                        cursor_pos.row = total_rows - 1;
+                                        cursor_pos.column = 0;
+                                    }
+                                    Err(e) => command_output = Some(format!("invalid filter: {e}")),
+                                }
+                            }
+                            Err(e) => {
+                                command_output = Some(e.to_string());
+                            }
+                        }
+
+                        command_line.clear();
+                    }
+
+                    event::KeyCode::Backspace
+                        if matches!(edit_mode, Mode::Command | Mode::Search) =>
+                    {
+                        if command_line.is_empty() {
+                            edit_mode = Mode::Normal;
+                        } else {
+                            command_line.pop();
+                        }
+                    }
+
+                    event::KeyCode::Enter
+                        if matches!(edit_mode, Mode::Insert | Mode::Confirm(_))
+                            && !send_message.is_empty() =>
+                    {
+                        let parts = split_message(&send_message, config.max_message_length);
+
+                        if let Mode::Confirm(confirmation) = edit_mode {
+                            edit_mode = Mode::Insert;
+
+                            if !std::mem::take(&mut confirmed) {
+                                confirmations.clear();
+                                command_output = Some(String::from("not sent"));
+                                continue;
+                            }
+                            confirmations.push(confirmation);
+                        } else {
+                            confirmations.clear();
+                        }
+
+                        let tab = &mut tabs[active_tab];
+                        let channel_tags = channel_user_tags.get(&tab.channel);
+
+                        // every part after the first would come too soon and be dropped
+                        if parts.len() > 1 && slow_mode(tab, channel_tags).is_some() {
+                            confirmations.clear();
+                            command_output = Some(format!(
+                                "slow mode, twitch would drop all but the first of {} messages, not sent",
+                                parts.len()
+                            ));
+                            continue;
+                        }
+
+                        let questions = [
+                            (
+                                Confirmation::Command,
+                                confirmation_prompt(&send_message, &config.confirm_commands),
+                            ),
+                            (
+                                Confirmation::EmoteOnly,
+                                (tab.emote_only
+                                    && !is_moderator(channel_tags)
+                                    && !only_emotes(&send_message, tab, &config.emotes))
+                                .then(|| {
+                                    String::from(
+                                        "emote-only mode, twitch drops messages with other words, send anyway? y/n",
+                                    )
+                                }),
+                            ),
+                            (
+                                Confirmation::Split,
+                                (parts.len() > 1 && !config.auto_split)
+                                    .then(|| format!("send as {} messages? y/n", parts.len())),
+                            ),
+                        ];
+                        if let Some((confirmation, prompt)) =
+                            next_question(questions, &confirmations)
+                        {
+                            command_output = Some(prompt);
+                            edit_mode = Mode::Confirm(confirmation);
+                            continue;
+                        }
+                        confirmations.clear();
+
+                        // twitch drops it without telling us otherwise
+                        if let Some(wait) = slow_mode_wait(
+                            tab,
+                            channel_tags,
+                            last_sent.get(&tab.channel),
+                            Instant::now(),
+                        ) {
+                            command_output = Some(format!(
+                                "slow mode, wait {}s before sending again",
+                                wait.as_secs()
+                            ));
+                            continue;
+                        }
+
+                        // the queue in `irc` paces the parts to the rate limit
+                        let mut sent = 0;
+                        for part in &parts {
+                            let Some(outgoing) = dedup_message(
+                                part,
+                                last_sent.get(&tab.channel),
+                                Instant::now(),
+                                config.bypass_duplicates,
+                            ) else {
+                                command_output = Some(String::from(
+                                    "twitch drops the same message twice in 30 seconds, not sent",
+                                ));
+                                break;
+                            };
+
+                            let nonce = match irc.send_message(&tab.channel, &outgoing) {
+                                Ok(nonce) => nonce,
+                                Err(e) => {
+                                    command_output = Some(format!("failed to send message: {e}"));
+                                    break;
+                                }
+                            };
+
+                            last_sent.insert(tab.channel.clone(), (outgoing, Instant::now()));
+
+                            // twitch answers these with a NOTICE, not with a chat message
+                            if let Some(echo) = command_echo(part) {
+                                tab.colored_notice(echo, Some(COMMAND_COLOR), true);
+                            } else {
+                                let privmsg = own_message(
+                                    &irc,
+                                    channel_user_tags.get(&tab.channel).or(user_tags.as_ref()),
+                                    tab.channel.clone(),
+                                    part.clone(),
+                                    nonce,
+                                    config.confirm_sent,
+                                );
+                                tab.push(ChatLine::Privmsg(privmsg), true);
+                            }
+                            sent += 1;
+                        }
+
+                        if sent == parts.len() {
+                            if !key_event.modifiers.contains(KeyModifiers::CONTROL) {
+                                send_message.clear();
+                                cursor_pos.column = 0;
+                            }
+                        } else if sent > 0 {
+                            // only what's left, so Enter again doesn't send the rest twice
+                            send_message = parts[sent..].join(&PASTED_NEWLINE.to_string());
+                            cursor_pos.column = send_message.graphemes(true).count() as u16;
+                        }
+                    }
+
+                    event::KeyCode::Backspace
+                        if matches!(edit_mode, Mode::Insert) && cursor_pos.column > 0 =>
+                    {
+                        let start = byte_offset(&send_message, cursor_pos.column as usize - 1);
+                        let end = byte_offset(&send_message, cursor_pos.column as usize);
+                        send_message.replace_range(start..end, "");
+                        cursor_pos.column -= 1;
+
+                        if let Some((offset, _)) = inserted.grapheme_indices(true).next_back() {
+                            inserted.truncate(offset);
+                        }
+                    }
+
+                    event::KeyCode::Right if matches!(edit_mode, Mode::Insert) => {
+                        cursor_pos.column = (cursor_pos.column + 1)
+                            .min(send_message.graphemes(true).count() as u16);
+                    }
+
+                    event::KeyCode::Left if matches!(edit_mode, Mode::Insert) => {
+                        cursor_pos.column = cursor_pos.column.saturating_sub(1);
+                    }
+
+                    event::KeyCode::End if matches!(edit_mode, Mode::Insert) => {
+                        cursor_pos.column = send_message.graphemes(true).count() as u16;
+                    }
+
+                    event::KeyCode::Tab | event::KeyCode::BackTab
+                        if matches!(edit_mode, Mode::Insert) =>
+                    {
+                        let forward = key_event.code == event::KeyCode::Tab;
+
+                        let cursor = match &mut completion {
+                            Some(completion) => Some(completion.cycle(&mut send_message, forward)),
+                            None => {
+                                let cursor = byte_offset(&send_message, cursor_pos.column as usize);
+
+                                completion = Completion::new(
+                                    &send_message,
+                                    cursor,
+                                    recent_chatters.names(),
+                                    &config.emotes,
+                                );
+
+                                completion.as_mut().map(|completion| {
+                                    if forward {
+                                        completion.apply(&mut send_message)
+                                    } else {
+                                        completion.cycle(&mut send_message, false)
+                                    }
+                                })
+                            }
+                        };
+
+                        if let Some(cursor) = cursor {
+                            cursor_pos.column =
+                                send_message[..cursor].graphemes(true).count() as u16;
+                        }
+                    }
+
+                    event::KeyCode::Char(c) => match c {
+                        'q' if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            break;
+                        }
+                        'c' if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            break;
+                        }
+                        's' if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            show_sidebar = !show_sidebar;
+                        }
+                        'y' | 'e' | 'u' | 'd'
+                            if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+                        {
+                            let lines = match c {
+                                'y' => -1,
+                                'e' => 1,
+                                'u' => -(message_rows as isize / 2),
+                                _ => message_rows as isize / 2,
+                            };
+
+                            scroll = scroll_by(scroll_end, lines, total_messages, message_rows)
+                                .map(|end| (view, end));
+                        }
+                        'G' if matches!(edit_mode, Mode::Normal) => {
+                            scroll = None;
+                        }
+                        'a' if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            toggle_merged_view(
+                                &mut tabs,
+                                active_tab,
+                                &mut merged_view,
+                                &mut cursor_pos,
+                                total_rows - 1,
+                            );
+                        }
+                        'n' if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            let index = (active_tab + 1) % tabs.len();
+                            switch_tab(
+                                &mut tabs,
+                                &mut active_tab,
+                                merged_view,
+                                index,
+                                &mut cursor_pos,
+                            );
+                        }
+                        'p' if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            let index = (active_tab + tabs.len() - 1) % tabs.len();
+                            switch_tab(
+                                &mut tabs,
+                                &mut active_tab,
+                                merged_view,
+                                index,
+                                &mut cursor_pos,
+                            );
+                        }
+                        'r' if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            reconnect_requested = true;
+                        }
+                        'x' if key_event.modifiers.contains(KeyModifiers::CONTROL)
+                            && !read_only
+                            && matches!(edit_mode, Mode::Normal | Mode::Insert) =>
+                        {
+                            let edited = open_editor(&mut stdout, &send_message);
+                            chat_rows.invalidate();
+                            match edited {
+                                Ok(Some(edited)) => {
+                                    send_message = edited;
+                                    cursor_pos.row = total_rows - 1;
+                                    cursor_pos.column = send_message.graphemes(true).count() as u16;
+                                }
+                                Ok(None) => {
+                                    command_output = Some(String::from(
+                                        "the editor failed or left nothing, message unchanged",
+                                    ));
+                                }
+                                Err(e) => {
+                                    command_output = Some(format!("editor: {e:#}"));
+                                }
+                            }
+                            // the editor leaves its own cursor behind
+                            cursor_mode = None;
+                        }
+                        '1'..='9' if key_event.modifiers.contains(KeyModifiers::ALT) => {
+                            let index = c as usize - '1' as usize;
+                            switch_tab(
+                                &mut tabs,
+                                &mut active_tab,
+                                merged_view,
+                                index,
+                                &mut cursor_pos,
+                            );
+                        }
+
+                        'i' | 'P' | '.' | 'r' if matches!(edit_mode, Mode::Normal) && read_only => {
+                            command_output = Some(String::from(if anonymous {
+                                "connected anonymously, pass --token to send messages"
+                            } else {
+                                "read-only, can't send messages"
+                            }));
+                        }
+
+                        'i' if matches!(edit_mode, Mode::Normal) => {
+                            edit_mode = Mode::Insert;
+                            inserted.clear();
+                            if cursor_pos.row < total_rows - 1 {
+                                cursor_pos.row = total_rows.saturating_sub(1);
+                                cursor_pos.column = send_message.graphemes(true).count() as u16;
+                            }
+                        }
+
+                        'h' if matches!(edit_mode, Mode::Normal) => {
+                            if cursor_pos.row >= total_rows - 1 {
+                                cursor_pos.column = cursor_pos.column.saturating_sub(1);
+                            } else {
+                                if let Some(new_pos) = cursor_pos.column.checked_sub(1) {
+                                    cursor_pos.column = new_pos;
+                                } else {
+                                    if messages_lines_start_pos > cursor_pos.row {
+                                        // TODO: Handle going to previous line
+                                        cursor_pos.row = cursor_pos.row.saturating_sub(1);
+                                        // cursor_pos.column = cursor_pos.column.max(
+                                        //     chat_lines[chat_lines.len() - cursor_pos.row as usize]
+                                        //         .message
+                                        //         .len() as u16,
+                                        // );
+                                    }
+                                }
+                            }
+                        }
+                        'V' if matches!(edit_mode, Mode::Normal)
+                            && !visible_messages.is_empty() =>
+                        {
+                            // from the compose line it starts at the newest message
+                            if cursor_pos.row >= compose_top {
+                                cursor_pos.row = compose_top - 1;
+                                cursor_pos.column = 0;
+                            }
+                            edit_mode = Mode::Visual {
+                                anchor: view_start
+                                    + cursor_pos.row.saturating_sub(messages_lines_start_pos)
+                                        as usize,
+                            };
+                        }
+                        'V' if matches!(edit_mode, Mode::Visual { .. }) => {
+                            edit_mode = Mode::Normal;
+                        }
+                        'j' if matches!(edit_mode, Mode::Visual { .. })
+                            && cursor_pos.row + 1 < compose_top =>
+                        {
+                            cursor_pos.row += 1;
+                        }
+                        'k' if matches!(edit_mode, Mode::Visual { .. })
+                            && cursor_pos.row > messages_lines_start_pos =>
+                        {
+                            cursor_pos.row -= 1;
+                        }
+                        'y' if matches!(edit_mode, Mode::Visual { .. }) => {
+                            let Mode::Visual { anchor } = edit_mode else {
+                                unreachable!()
+                            };
+                            let register = register.take().unwrap_or(registers::UNNAMED);
+
+                            let selection = visual_selection(
+                                anchor,
+                                cursor_pos.row,
+                                messages_lines_start_pos,
+                                view_start,
+                                visible_messages.len(),
+                            );
+                            let lines = selection
+                                .clone()
+                                .and_then(|selection| visible_messages.get(selection))
+                                .unwrap_or_default()
+                                .iter()
+                                .filter(|message| !matches!(message, ChatLine::Spacer))
+                                .map(|message| message.message_line().trim_start().to_string())
+                                .collect::<Vec<_>>();
+                            registers.set(register, lines.join("\n"));
+
+                            command_output = Some(format!("yanked {} messages", lines.len()));
+                            if let Some(selection) = selection {
+                                cursor_pos.row =
+                                    messages_lines_start_pos + *selection.start() as u16;
+                            }
+                            edit_mode = Mode::Normal;
+                        }
+
+                        'j' if matches!(edit_mode, Mode::Normal)
+                            && cursor_pos.row >= total_rows - 1 =>
+                        {
+                            let (row, column) = compose_position(
+                                &prompt,
+                                &send_message,
+                                cursor_pos.column,
+                                total_columns,
+                            );
+                            if row + 1 < compose_rows {
+                                cursor_pos.column = compose_column(
+                                    &prompt,
+                                    &send_message,
+                                    (row + 1, column),
+                                    total_columns,
+                                );
+                            }
+                        }
+                        'j' if matches!(edit_mode, Mode::Normal) => {
+                            cursor_pos.row += 1;
+                            if cursor_pos.row >= compose_top {
+                                cursor_pos.row = total_rows - 1;
+                            }
+
+                            if cursor_pos.row >= total_rows - 1 {
+                                cursor_pos.column = cursor_pos
+                                    .column
+                                    .min(send_message.graphemes(true).count() as u16);
+                            } else {
+                                let current_message = visible_messages
+                                    .get((cursor_pos.row - messages_lines_start_pos) as usize);
+
+                                let Some(current_message) = current_message else {
+                                    continue;
+                                };
+
+                                cursor_pos.column = cursor_pos
+                                    .column
+                                    .min(current_message.message_line_len() as u16);
+                            }
+                        }
+                        'k' if matches!(edit_mode, Mode::Normal)
+                            && cursor_pos.row >= total_rows - 1
+                            && compose_position(
+                                &prompt,
+                                &send_message,
+                                cursor_pos.column,
+                                total_columns,
+                            )
+                            .0 > 0 =>
+                        {
+                            let (row, column) = compose_position(
+                                &prompt,
+                                &send_message,
+                                cursor_pos.column,
+                                total_columns,
+                            );
+                            cursor_pos.column = compose_column(
+                                &prompt,
+                                &send_message,
+                                (row - 1, column),
+                                total_columns,
+                            );
+                        }
+                        'k' if matches!(edit_mode, Mode::Normal)
+                            && messages_lines_start_pos < cursor_pos.row
+                            && !visible_messages.is_empty() =>
+                        {
+                            // from the compose line to the newest message above it
+                            if let Some(new_pos) = cursor_pos.row.min(compose_top).checked_sub(1) {
+                                cursor_pos.row = new_pos;
+
+                                // cursor_pos.column = cursor_pos.column.min(
+                                //     chat_lines[chat_lines.len() - cursor_pos.row as usize]
+                                //         .message
+                                //         .len() as u16,
+                                // )
+                            }
+                            // println!("k: {messages_lines_start_pos}: {}", cursor_pos.row);
+                        }
+                        'l' if matches!(edit_mode, Mode::Normal) => {
+                            if cursor_pos.row >= total_rows - 1 {
+                                if send_message.graphemes(true).count() > cursor_pos.column as usize
+                                {
+                                    cursor_pos.column += 1;
+                                }
+                            } else {
+                                let Some(current_message) =
+                                    visible_messages.get(current_message_index)
+                                else {
                                     continue;
                                 };
 
-                                if let Some(message) = current_message
-                                    .message_line()
-                                    .get((cursor_pos.column + 1) as usize..)
-                                {
-                                    cursor_pos.column += message.find(' ').map(|i| i + 1).unwrap_or(
-                                        current_message
-                                            .message_line_len()
-                                            .saturating_sub(cursor_pos.column as usize),
-                                    )
-                                        as u16;
-                                }
-                            }
-                        }
+                                if current_message.message_line_len() <= cursor_pos.column as usize
+                                    && visible_messages.len() <= cursor_pos.row as usize
+                                {
+                                    cursor_pos.row += 1;
+                                    cursor_pos.column = 0;
+                                } else {
+                                    cursor_pos.column += 1;
+                                }
+                            }
+                        }
+
+                        'b' if matches!(edit_mode, Mode::Normal) => {
+                            let Some(line) = &current_line else {
+                                continue;
+                            };
+
+                            cursor_pos.column =
+                                motion::previous_word_start(line, cursor_pos.column as usize)
+                                    .unwrap_or(0) as u16;
+                        }
+                        'w' if matches!(edit_mode, Mode::Normal) => {
+                            let Some(line) = &current_line else {
+                                continue;
+                            };
+
+                            cursor_pos.column =
+                                motion::next_word_start(line, cursor_pos.column as usize)
+                                    .unwrap_or(line.graphemes(true).count())
+                                    as u16;
+                        }
+
+                        '$' if matches!(edit_mode, Mode::Normal) => {
+                            let Some(current_message) = visible_messages.get(current_message_index)
+                            else {
+                                continue;
+                            };
+
+                            cursor_pos.column = current_message.message_line_len() as u16;
+                        }
+
+                        '^' if matches!(edit_mode, Mode::Normal) => {
+                            cursor_pos.column = 0;
+                        }
+
+                        'e' if matches!(edit_mode, Mode::Normal) => {
+                            if let Some(column) = current_line
+                                .as_deref()
+                                .and_then(|line| motion::word_end(line, cursor_pos.column as usize))
+                            {
+                                cursor_pos.column = column as u16;
+                            }
+                        }
+
+                        'g' if matches!(edit_mode, Mode::Normal) => {
+                            edit_mode = Mode::G;
+                        }
+
+                        'z' if matches!(edit_mode, Mode::Normal)
+                            && cursor_pos.row < total_rows - 1 =>
+                        {
+                            edit_mode = Mode::Z;
+                        }
+
+                        // za shows or hides who got the subs of a gift bomb
+                        'a' if matches!(edit_mode, Mode::Z) => {
+                            if let Some(ChatLine::GiftBomb(bomb)) =
+                                visible_messages.get(current_message_index)
+                            {
+                                if !expanded_gifts.remove(&bomb.id) {
+                                    expanded_gifts.insert(bomb.id.clone());
+                                }
+                            }
+
+                            edit_mode = Mode::Normal;
+                        }
+
+                        // zl and zh scroll a column, zL and zH half the screen
+                        c if matches!(edit_mode, Mode::Z) => {
+                            let columns = match c {
+                                'l' | 'h' => 1,
+                                'L' | 'H' => (total_columns / 2).max(1) as usize,
+                                _ => 0,
+                            };
+                            let line_len = current_line
+                                .as_deref()
+                                .map_or(0, |line| line.graphemes(true).count());
+                            let scroll = scroll_columns(horizontal_scroll, cursor_pos.row);
+
+                            let scroll = match c {
+                                'l' | 'L' => (scroll + columns).min(line_len.saturating_sub(1)),
+                                _ => scroll.saturating_sub(columns),
+                            };
+                            horizontal_scroll = Some((cursor_pos.row, scroll));
+                            // the cursor stays on what's shown
+                            cursor_pos.column = cursor_pos.column.max(scroll as u16);
+
+                            edit_mode = Mode::Normal;
+                        }
+
+                        c if matches!(edit_mode, Mode::G) => {
+                            if c == 'e' {
+                                if let Some(column) = current_line.as_deref().and_then(|line| {
+                                    motion::previous_word_end(line, cursor_pos.column as usize)
+                                }) {
+                                    cursor_pos.column = column as u16;
+                                }
+                            } else if let Some(index) = unread_index.filter(|_| c == 'u') {
+                                // the "new messages" line at the top, or as close as it gets
+                                let end = (index + message_rows).min(total_messages);
+                                scroll = (end < total_messages).then_some((view, end));
+
+                                let shown = message_rows.min(end);
+                                cursor_pos.row = layout.first_message_row(shown)
+                                    + (index - (end - shown)) as u16;
+                                cursor_pos.column = 0;
+                            }
+
+                            edit_mode = Mode::Normal;
+                        }
+
+                        'y' if matches!(edit_mode, Mode::Normal) => {
+                            edit_mode = Mode::Y;
+                        }
+
+                        ':' if matches!(edit_mode, Mode::Normal) => {
+                            edit_mode = Mode::Command;
+                        }
+
+                        '/' if matches!(edit_mode, Mode::Normal) => {
+                            edit_mode = Mode::Search;
+                        }
+
+                        // n goes to older matches, N to newer ones
+                        'n' | 'N' if matches!(edit_mode, Mode::Normal) => {
+                            // everything shown is older than the compose line
+                            let current = if cursor_pos.row >= total_rows - 1 {
+                                view_end
+                            } else {
+                                view_start + current_message_index
+                            };
+                            let next = match c {
+                                'n' => search_matches.iter().rev().find(|index| **index < current),
+                                _ => search_matches.iter().find(|index| **index > current),
+                            };
+
+                            match next {
+                                None if search.is_none() => {
+                                    command_output =
+                                        Some(String::from("no search yet, / starts one"));
+                                }
+                                None => command_output = Some(String::from("no more matches")),
+                                Some(&index) if (view_start..view_end).contains(&index) => {
+                                    cursor_pos.row =
+                                        messages_lines_start_pos + (index - view_start) as u16;
+                                    cursor_pos.column = 0;
+                                }
+                                Some(&index) => {
+                                    // at the top, like gu
+                                    let end = (index + message_rows).min(total_messages);
+                                    scroll = (end < total_messages).then_some((view, end));
+
+                                    let shown = message_rows.min(end);
+                                    cursor_pos.row = layout.first_message_row(shown)
+                                        + (index - (end - shown)) as u16;
+                                    cursor_pos.column = 0;
+                                }
+                            }
+                        }
+
+                        'd' if matches!(edit_mode, Mode::Normal) => {
+                            edit_mode = Mode::D;
+                        }
+
+                        // pinning the same message again unpins it
+                        'm' if matches!(edit_mode, Mode::Normal) => {
+                            let Some(message) = visible_messages
+                                .get(current_message_index)
+                                .filter(|_| cursor_pos.row < total_rows - 1)
+                            else {
+                                continue;
+                            };
+
+                            let pin = Pin {
+                                channel: message.channel().to_string(),
+                                line: message.message_line().into_owned(),
+                            };
+                            let pinned = pins.iter().position(|existing| {
+                                existing.channel == pin.channel && existing.line == pin.line
+                            });
+
+                            if let Some(index) = pinned {
+                                pins.remove(index);
+                            } else {
+                                if pins.len() >= MAX_PINS {
+                                    pins.remove(0);
+                                }
+                                pins.push(pin);
+                            }
+                        }
+
+                        'M' if matches!(edit_mode, Mode::Normal) => {
+                            pins.pop();
+                        }
+
+                        // chat messages can't be edited
+                        'r' if matches!(edit_mode, Mode::Normal)
+                            && cursor_pos.row == total_rows - 1 =>
+                        {
+                            edit_mode = Mode::Replace;
+                        }
+
+                        c if matches!(edit_mode, Mode::Replace) => {
+                            if replace_grapheme(&mut send_message, cursor_pos.column, c) {
+                                last_change = Some(Change::Replace(c));
+                            }
+
+                            edit_mode = Mode::Normal;
+                        }
+
+                        '1'..='9' if matches!(edit_mode, Mode::Normal) => {
+                            count = Some(given_count.unwrap_or(0) * 10 + c as usize - '0' as usize);
+                        }
+                        '0' if matches!(edit_mode, Mode::Normal) && given_count.is_some() => {
+                            count = given_count.map(|count| count * 10);
+                        }
+
+                        'q' if matches!(edit_mode, Mode::Normal) => {
+                            if let Some((register, mut keys)) = recording.take() {
+                                // the `q` that stopped it
+                                keys.pop();
+                                macros.insert(register, keys);
+                            } else {
+                                edit_mode = Mode::Record;
+                            }
+                        }
+
+                        c if matches!(edit_mode, Mode::Record) => {
+                            if c.is_ascii_lowercase() {
+                                recording = Some((c, Vec::new()));
+                            } else {
+                                command_output = Some(format!("can't record into {c}"));
+                            }
+
+                            edit_mode = Mode::Normal;
+                        }
+
+                        '@' if matches!(edit_mode, Mode::Normal) => {
+                            edit_mode = Mode::Replay(given_count.unwrap_or(1));
+                        }
+
+                        c if matches!(edit_mode, Mode::Replay(_)) => {
+                            let Mode::Replay(times) = edit_mode else {
+                                unreachable!()
+                            };
+                            edit_mode = Mode::Normal;
+
+                            // `@@` replays the last one again
+                            let register = if c == '@' { last_macro } else { Some(c) };
+                            let Some(keys) = register.and_then(|register| macros.get(&register))
+                            else {
+                                command_output = Some(format!("no macro recorded in {c}"));
+                                continue;
+                            };
+
+                            if replayed_keys + replay.len() + keys.len() * times > MAX_REPLAY_KEYS {
+                                replay.clear();
+                                command_output = Some(String::from("macro replays too many keys"));
+                                continue;
+                            }
+
+                            last_macro = register;
+                            // in front of whatever is left, so a macro can replay another one
+                            for _ in 0..times {
+                                for key_event in keys.iter().rev() {
+                                    replay.push_front(*key_event);
+                                }
+                            }
+                        }
+
+                        // anything but `y` cancels, both go through the Enter handling above
+                        c if matches!(edit_mode, Mode::Confirm(_)) => {
+                            confirmed = c == 'y';
+                            replay.push_front(KeyEvent::from(event::KeyCode::Enter));
+                        }
+
+                        '"' if matches!(edit_mode, Mode::Normal) => {
+                            edit_mode = Mode::Register;
+                        }
+
+                        c if matches!(edit_mode, Mode::Register) => {
+                            if Registers::is_register(c) {
+                                register = Some(c);
+                            } else {
+                                command_output = Some(format!("no register named {c}"));
+                            }
+
+                            edit_mode = Mode::Normal;
+                        }
+
+                        c if matches!(edit_mode, Mode::Y) => {
+                            let register = register.take().unwrap_or(registers::UNNAMED);
+
+                            if let Some(current_message) =
+                                visible_messages.get(current_message_index)
+                            {
+                                match c {
+                                    'y' => registers
+                                        .set(register, current_message.message().to_string()),
+                                    'Y' => registers
+                                        .set(register, current_message.quote(&config.quote_format)),
+                                    _ => {}
+                                }
+                            }
+
+                            edit_mode = Mode::Normal;
+                        }
+
+                        c if matches!(edit_mode, Mode::D) => {
+                            let register = register.take().unwrap_or(registers::UNNAMED);
+
+                            if c == 'd' && cursor_pos.row == total_rows - 1 {
+                                registers.set(register, std::mem::take(&mut send_message));
+                                cursor_pos.column = 0;
+                                last_change = Some(Change::DeleteLine);
+                            }
+
+                            edit_mode = Mode::Normal;
+                        }
+
+                        'P' if matches!(edit_mode, Mode::Normal) => {
+                            let register = register.take().unwrap_or(registers::UNNAMED);
+
+                            if let Some(clipboard_text) = registers.get(register) {
+                                if cursor_pos.row != total_rows - 1 {
+                                    cursor_pos.row = total_rows - 1;
+                                    cursor_pos.column = send_message.graphemes(true).count() as u16;
+                                }
+
+                                cursor_pos.column = insert_text(
+                                    &mut send_message,
+                                    cursor_pos.column,
+                                    &clipboard_text,
+                                );
+                                last_change = Some(Change::Insert(clipboard_text));
+                            }
+                        }
+
+                        '.' if matches!(edit_mode, Mode::Normal) => {
+                            let Some(change) = &last_change else {
+                                continue;
+                            };
+
+                            if cursor_pos.row != total_rows - 1 {
+                                cursor_pos.row = total_rows - 1;
+                                cursor_pos.column = send_message.graphemes(true).count() as u16;
+                            }
+
+                            match change {
+                                Change::Insert(text) => {
+                                    cursor_pos.column =
+                                        insert_text(&mut send_message, cursor_pos.column, text);
+                                }
+                                Change::DeleteLine => {
+                                    registers
+                                        .set(registers::UNNAMED, std::mem::take(&mut send_message));
+                                    cursor_pos.column = 0;
+                                }
+                                Change::Replace(c) => {
+                                    replace_grapheme(&mut send_message, cursor_pos.column, *c);
+                                }
+                            }
+                        }
+
+                        c if matches!(edit_mode, Mode::Insert) => {
+                            // a mention starts a word
+                            if c == '@'
+                                && send_message[..cursor]
+                                    .chars()
+                                    .next_back()
+                                    .is_none_or(char::is_whitespace)
+                            {
+                                mention_popup = Some(MentionPopup::new(cursor));
+                            }
+
+                            cursor_pos.column = insert_text(
+                                &mut send_message,
+                                cursor_pos.column,
+                                c.encode_utf8(&mut [0; 4]),
+                            );
+                            inserted.push(c);
+                        }
+
+                        c if matches!(edit_mode, Mode::Command | Mode::Search) => {
+                            command_line.push(c);
+                        }
+
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+
+            stdout.flush().unwrap();
+        }
+    }
+
+    stdout.execute(style::ResetColor).unwrap();
+    stdout.execute(event::DisableBracketedPaste).unwrap();
+    stdout.execute(event::DisableFocusChange).unwrap();
+    disable_raw_mode().unwrap();
+}
+
+/// Lets `$VISUAL` or `$EDITOR` edit `message` with the terminal handed over to it, `None` if
+/// the editor failed or left the file empty
+fn open_editor(stdout: &mut Stdout, message: &str) -> anyhow::Result<Option<String>> {
+    let path = std::env::temp_dir().join(format!("twitcher-{}.txt", std::process::id()));
+    std::fs::write(&path, message)?;
+
+    stdout.execute(event::PopKeyboardEnhancementFlags)?;
+    stdout.execute(event::DisableBracketedPaste)?;
+    stdout.execute(event::DisableFocusChange)?;
+    stdout.execute(terminal::Clear(terminal::ClearType::All))?;
+    disable_raw_mode()?;
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    // through the shell, so an editor like `code --wait` works
+    let status = std::process::Command::new("sh")
+        .args(["-c", &format!("{editor} \"$1\""), "sh"])
+        .arg(&path)
+        .status();
+
+    enable_raw_mode()?;
+    stdout.execute(event::EnableFocusChange)?;
+    stdout.execute(event::EnableBracketedPaste)?;
+    stdout.execute(event::PushKeyboardEnhancementFlags(
+        event::KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES,
+    ))?;
+
+    let edited = match status {
+        Ok(status) if status.success() => {
+            std::fs::read_to_string(&path).map(|text| edited_message(&text))
+        }
+        Ok(_) => Ok(None),
+        Err(e) => Err(e),
+    };
+    let _ = std::fs::remove_file(&path);
+
+    edited.map_err(|e| anyhow::anyhow!("failed to run {editor}: {e}"))
+}
+
+/// Chat messages are a single line, so the lines of the edited file are joined with spaces
+fn edited_message(text: &str) -> Option<String> {
+    let message = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    (!message.is_empty()).then_some(message)
+}
+
+/// Pasted text for the compose line, with its line breaks as [`PASTED_NEWLINE`]
+fn pasted_text(text: &str) -> String {
+    text.trim_end_matches(['\r', '\n'])
+        .replace("\r\n", "\n")
+        .replace(['\r', '\n'], &PASTED_NEWLINE.to_string())
+}
+
+/// The messages to send for `message`, one per pasted line and each line split between words
+/// so no message is longer than `max_length` characters. A message that fits is sent as it is
+fn split_message(message: &str, max_length: usize) -> Vec<String> {
+    // cutting words into pieces of nothing would never end
+    let max_length = max_length.max(1);
+    if !message.contains(PASTED_NEWLINE) && message.chars().count() <= max_length {
+        return vec![message.to_string()];
+    }
+
+    let mut parts = Vec::new();
+    for line in message.split(PASTED_NEWLINE) {
+        let mut part = String::new();
+        let mut part_length = 0;
+
+        for word in line.split_whitespace() {
+            let mut word = word;
+            let mut word_length = word.chars().count();
+
+            if part_length > 0 && part_length + 1 + word_length > max_length {
+                parts.push(std::mem::take(&mut part));
+                part_length = 0;
+            }
+
+            // a word that doesn't fit in a message on its own is cut wherever it has to be
+            while word_length > max_length {
+                let end = word
+                    .char_indices()
+                    .nth(max_length)
+                    .map_or(word.len(), |(offset, _)| offset);
+                parts.push(word[..end].to_string());
+                word = &word[end..];
+                word_length -= max_length;
+            }
+
+            if part_length > 0 {
+                part.push(' ');
+                part_length += 1;
+            }
+            part.push_str(word);
+            part_length += word_length;
+        }
+
+        if !part.is_empty() {
+            parts.push(part);
+        }
+    }
+
+    parts
+}
+
+/// What we sent, as it shows up locally since twitch doesn't echo our messages back
+///
+/// `tags` are our USERSTATE or GLOBALUSERSTATE tags, for the display name and color
+fn own_message(
+    irc: &IRC,
+    tags: Option<&Tags>,
+    channel: String,
+    message: String,
+    nonce: String,
+    confirm_sent: bool,
+) -> Privmsg {
+    let mut privmsg = Privmsg::new(
+        tags.cloned().unwrap_or_default(),
+        Prefix {
+            nick: Some(irc.nick().to_string()),
+            user: Some(irc.nick().to_string()),
+            host: String::from("idk"),
+        },
+        channel,
+        message,
+    );
+    privmsg.nonce = Some(nonce);
+    if confirm_sent {
+        privmsg.delivery = Delivery::Pending(Instant::now());
+    }
+
+    privmsg
+}
+
+/// What to send so twitch doesn't drop `message` as a repeat of `last_sent`
+///
+/// That's `message` itself unless it's a repeat, then it gets [`DUPLICATE_SUFFIX`] if `bypass`
+/// is on, or `None` so it isn't sent at all
+fn dedup_message(
+    message: &str,
+    last_sent: Option<&(String, Instant)>,
+    now: Instant,
+    bypass: bool,
+) -> Option<String> {
+    let duplicate = last_sent.is_some_and(|(last_message, sent_at)| {
+        last_message == message && now.duration_since(*sent_at) < DUPLICATE_WINDOW
+    });
+
+    match (duplicate, bypass) {
+        (false, _) => Some(message.to_string()),
+        (true, true) => Some(format!("{message}{DUPLICATE_SUFFIX}")),
+        (true, false) => None,
+    }
+}
+
+/// How long until slow mode lets us send in `tab` again, `None` if it does now
+///
+/// Moderators, VIPs and the broadcaster aren't slowed down, which `user_tags` from the channel's
+/// USERSTATE tells
+fn slow_mode_wait(
+    tab: &Tab,
+    user_tags: Option<&Tags>,
+    last_sent: Option<&(String, Instant)>,
+    now: Instant,
+) -> Option<Duration> {
+    let (_, sent_at) = last_sent?;
+    let wait = (*sent_at + slow_mode(tab, user_tags)?).checked_duration_since(now)?;
+    // rounded up, so it never says 0s while still waiting
+    Some(Duration::from_secs(
+        wait.as_secs() + u64::from(wait.subsec_nanos() > 0),
+    ))
+    .filter(|wait| !wait.is_zero())
+}
+
+/// The time slow mode has us wait between messages in `tab`, `None` if it's off or doesn't
+/// apply to us, see [`slow_mode_wait`]
+fn slow_mode(tab: &Tab, user_tags: Option<&Tags>) -> Option<Duration> {
+    if is_moderator(user_tags) || has_badge(user_tags, "vip") {
+        return None;
+    }
+
+    tab.slow_mode
+}
+
+fn has_badge(user_tags: Option<&Tags>, badge: &str) -> bool {
+    user_tags
+        .and_then(|tags| tags.get("badges"))
+        .is_some_and(|badges| {
+            badges
+                .split(',')
+                .any(|b| b.split_once('/').is_some_and(|(name, _)| name == badge))
+        })
+}
+
+/// Whether `user_tags` from a channel's USERSTATE are the broadcaster's or a moderator's, they
+/// aren't held to slow or emote-only mode
+fn is_moderator(user_tags: Option<&Tags>) -> bool {
+    user_tags
+        .and_then(|tags| tags.get("mod"))
+        .is_some_and(|m| m == "1")
+        || has_badge(user_tags, "broadcaster")
+}
+
+/// Whether every word of `message` is an emote, as far as we know them: the configured ones and
+/// those seen in `tab`'s chat. Commands aren't chat messages so they always pass
+fn only_emotes(message: &str, tab: &Tab, emotes: &[String]) -> bool {
+    if message.starts_with('/') {
+        return true;
+    }
+
+    let seen = tab
+        .messages
+        .iter()
+        .filter_map(|line| match line {
+            ChatLine::Privmsg(privmsg) => Some(&privmsg.emotes),
+            _ => None,
+        })
+        .flatten()
+        .map(|(_, name)| name)
+        .collect::<Vec<_>>();
+
+    message.split_whitespace().all(|word| {
+        emotes
+            .iter()
+            .chain(seen.iter().copied())
+            .any(|emote| emote == word)
+    })
+}
+
+/// Indicators shown at the end of the compose line
+#[allow(clippy::too_many_arguments)]
+fn status(
+    read_only: bool,
+    anonymous: bool,
+    emote_only: bool,
+    slow_mode_wait: Option<Duration>,
+    queued: usize,
+    recording: Option<char>,
+    messages_below: usize,
+    connected: bool,
+    logged_in: bool,
+    latency: Option<Duration>,
+) -> String {
+    let mut status = Vec::new();
+    if messages_below > 0 {
+        status.push(format!("[{messages_below} messages below]"));
+    }
+
+    if let Some(register) = recording {
+        status.push(format!("[recording @{register}]"));
+    }
+
+    if emote_only {
+        status.push(String::from("[emote-only]"));
+    }
+
+    if let Some(wait) = slow_mode_wait {
+        status.push(format!("[wait {}s]", wait.as_secs()));
+    }
+
+    if queued > 0 {
+        status.push(format!("[{queued} queued]"));
+    }
+
+    // anonymous connections are always read-only
+    if anonymous {
+        status.push(String::from("[anonymous]"));
+    } else if read_only {
+        status.push(String::from("[READ-ONLY]"));
+    }
+
+    if !connected {
+        status.push(String::from("[reconnecting]"));
+    } else if !logged_in {
+        status.push(String::from("[logging in]"));
+    } else if let Some(latency) = latency {
+        status.push(format!("[{}ms]", latency.as_millis()));
+    }
+
+    status.join(" ")
+}
+
+/// Where the bottom of a view with `total` messages ends up after scrolling `lines` from `end`,
+/// `None` once it's back at the newest message
+///
+/// The view never scrolls past the point where the oldest message is at the top
+fn scroll_by(end: Option<usize>, lines: isize, total: usize, rows: usize) -> Option<usize> {
+    let end = end
+        .unwrap_or(total)
+        .saturating_add_signed(lines)
+        .max(rows.min(total));
+
+    (end < total).then_some(end)
+}
+
+/// The messages a `V` selection from message `anchor` to the one on `cursor_row` covers, as
+/// indices into the `shown` messages that start at message `view_start` on `first_row`. What's
+/// scrolled out of view or was cleared meanwhile is left out, `None` if nothing is left
+fn visual_selection(
+    anchor: usize,
+    cursor_row: u16,
+    first_row: u16,
+    view_start: usize,
+    shown: usize,
+) -> Option<RangeInclusive<usize>> {
+    let anchor = anchor.saturating_sub(view_start);
+    let cursor = cursor_row.saturating_sub(first_row) as usize;
+
+    let first = anchor.min(cursor);
+    let last = anchor.max(cursor).min(shown.checked_sub(1)?);
+    (first <= last).then_some(first..=last)
+}
+
+/// The first of `questions` that has a prompt and wasn't said yes to yet
+fn next_question(
+    questions: impl IntoIterator<Item = (Confirmation, Option<String>)>,
+    confirmations: &[Confirmation],
+) -> Option<(Confirmation, String)> {
+    questions.into_iter().find_map(|(confirmation, prompt)| {
+        prompt
+            .filter(|_| !confirmations.contains(&confirmation))
+            .map(|prompt| (confirmation, prompt))
+    })
+}
+
+/// The question to ask before sending `message`, if it starts with one of `commands`
+fn confirmation_prompt(message: &str, commands: &[String]) -> Option<String> {
+    let (command, arguments) = message.split_once(' ').unwrap_or((message, ""));
+    if !commands
+        .iter()
+        .any(|danger| danger.eq_ignore_ascii_case(command))
+    {
+        return None;
+    }
+
+    let mut name = command.trim_start_matches('/').to_lowercase();
+    if let Some(first) = name.get_mut(..1) {
+        first.make_ascii_uppercase();
+    }
+
+    Some(match arguments.trim() {
+        "" => format!("{name}? y/n"),
+        arguments => format!("{name} {arguments}? y/n"),
+    })
+}
+
+/// Makes `tabs[index]` the active tab, the cursor position is kept per tab
+///
+/// In the merged view this only changes which channel messages are sent to
+fn switch_tab(
+    tabs: &mut [Tab],
+    active_tab: &mut usize,
+    merged_view: bool,
+    index: usize,
+    cursor_pos: &mut CursorPos,
+) {
+    if index >= tabs.len() || index == *active_tab {
+        return;
+    }
+
+    if merged_view {
+        *active_tab = index;
+        return;
+    }
+
+    tabs[*active_tab].mark_read();
+    std::mem::swap(cursor_pos, &mut tabs[*active_tab].cursor_pos);
+    *active_tab = index;
+    std::mem::swap(cursor_pos, &mut tabs[*active_tab].cursor_pos);
+    tabs[*active_tab].unread = 0;
+}
+
+/// The merged view starts at the compose line, the tab's cursor is put back when leaving it
+fn toggle_merged_view(
+    tabs: &mut [Tab],
+    active_tab: usize,
+    merged_view: &mut bool,
+    cursor_pos: &mut CursorPos,
+    compose_row: u16,
+) {
+    std::mem::swap(cursor_pos, &mut tabs[active_tab].cursor_pos);
+
+    if !*merged_view {
+        *cursor_pos = CursorPos {
+            row: compose_row,
+            column: 0,
+        };
+    }
+
+    *merged_view = !*merged_view;
+}
+
+/// How far the message at `row` is scrolled right
+fn scroll_columns(horizontal_scroll: Option<(u16, usize)>, row: u16) -> usize {
+    horizontal_scroll
+        .filter(|(scrolled_row, _)| *scrolled_row == row)
+        .map_or(0, |(_, scroll)| scroll)
+}
+
+/// The tab bar takes the top row, but only once there's more than one channel
+fn tab_bar_rows(tabs: &[Tab]) -> u16 {
+    u16::from(tabs.len() > 1)
+}
+
+/// Short relative time like `5s`, `3m` or `2h`
+fn format_ago(elapsed: TimeDelta) -> String {
+    match elapsed.num_seconds().max(0) {
+        seconds @ 0..60 => format!("{seconds}s"),
+        seconds @ 60..3600 => format!("{}m", seconds / 60),
+        seconds => format!("{}h", seconds / 3600),
+    }
+}
+
+/// Logs go to a file since anything printed to the terminal would mess up the TUI
+fn init_logging(verbose: bool, path: PathBuf) -> anyhow::Result<()> {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(directives) => EnvFilter::new(directives),
+        Err(_) if verbose => EnvFilter::new("twitcher=debug"),
+        Err(_) => EnvFilter::new("twitcher=info"),
+    };
+
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(Mutex::new(file))
+        .with_ansi(false)
+        .init();
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw(
+    stdout: &mut Stdout,
+    cursor_pos: &CursorPos,
+    edit_mode: &Mode,
+    chat_messages: &[&ChatLine],
+    send_message: &str,
+    command_line: &str,
+    command_output: Option<&str>,
+    theme: Theme,
+    highlights: &[Highlight],
+    tabs: &[Tab],
+    active_tab: usize,
+    merged_view: bool,
+    sidebar: Option<Sidebar>,
+    group_messages: bool,
+    channel_prefix: Option<&str>,
+    channel_hash: bool,
+    timestamp_format: Option<&str>,
+    fade_after: Option<TimeDelta>,
+    prompt: &str,
+    layout: Layout,
+    emote_images: Option<&EmoteImages>,
+    pins: &[&str],
+    popup: Option<Popup>,
+    horizontal_scroll: usize,
+    selection: Option<RangeInclusive<u16>>,
+    status: &str,
+    chat_rows: &mut ChatRows,
+    scroll_region: bool,
+    total_columns: u16,
+    total_rows: u16,
+) -> anyhow::Result<()> {
+    // the sidebar only shows up if there's still a reasonable amount of room for the chat
+    let sidebar = sidebar.filter(|sidebar| total_columns >= sidebar.width + MIN_CHAT_WIDTH);
+    let chat_width = match &sidebar {
+        Some(sidebar) => total_columns - sidebar.width,
+        None => total_columns,
+    };
+
+    let tab_bar_rows = tab_bar_rows(tabs);
+    let messages_top = tab_bar_rows + pins.len() as u16;
+
+    let compose_top = layout.compose_top;
+    // rows count from 0, `total_rows` itself is below the screen
+    let bottom_row = total_rows.saturating_sub(1);
+    let messages_start = chat_messages
+        .len()
+        .saturating_sub(layout.message_rows as usize);
+    let shown_messages = &chat_messages[messages_start..];
+    let first_message_pos = layout.first_message_row(shown_messages.len());
+    let now = Local::now();
+
+    // each chat row goes into its own buffer first, so the ones already on the screen can be
+    // left alone
+    let mut rows = vec![Vec::new(); compose_top.saturating_sub(messages_top) as usize];
+    for (i, message) in shown_messages.iter().enumerate() {
+        let highlight = highlights
+            .iter()
+            .find(|highlight| highlight.matches(message.channel(), message.message()));
+
+        let Some(row) = (first_message_pos + i as u16)
+            .checked_sub(messages_top)
+            .and_then(|row| rows.get_mut(row as usize))
+        else {
+            continue;
+        };
+
+        let selected = selection
+            .as_ref()
+            .is_some_and(|selection| selection.contains(&(first_message_pos + i as u16)));
+        if selected {
+            row.queue(style::SetAttribute(style::Attribute::Reverse))?;
+        }
+
+        // older messages are dimmed, so the ones that just came in stand out
+        let faded = match message {
+            ChatLine::Unread { .. } | ChatLine::Spacer => false,
+            ChatLine::SessionStart { .. } => true,
+            ChatLine::Privmsg(Privmsg {
+                delivery: Delivery::Pending(_),
+                ..
+            }) => true,
+            _ => fade_after.is_some_and(|fade_after| now - message.timestamp() > fade_after),
+        };
+        let failed = matches!(
+            message,
+            ChatLine::Privmsg(Privmsg {
+                delivery: Delivery::Failed,
+                ..
+            })
+        );
+        if faded {
+            row.queue(style::SetAttribute(style::Attribute::Dim))?;
+        }
+
+        let timestamp = timestamp_prefix(timestamp_format, message, now);
+        row.queue(style::Print(truncate(&timestamp, chat_width as usize)))?;
+
+        let prefix = line_prefix(channel_prefix, message, channel_hash);
+        let prefix_width = width::width(&timestamp) + width::width(&prefix);
+        if !prefix.is_empty() {
+            row.queue(style::SetForegroundColor(hash_color(message.channel())))?;
+            row.queue(style::Print(truncate(
+                &prefix,
+                (chat_width as usize).saturating_sub(width::width(&timestamp)),
+            )))?;
+            row.queue(style::SetForegroundColor(theme.foreground()))?;
+        }
+
+        if let Some(highlight) = highlight {
+            row.queue(style::SetForegroundColor(highlight.color))?;
+        } else if failed || matches!(message, ChatLine::Unread { .. }) {
+            row.queue(style::SetForegroundColor(style::Color::Red))?;
+        } else if let ChatLine::System {
+            color: Some(color), ..
+        } = message
+        {
+            row.queue(style::SetForegroundColor(*color))?;
+        }
+
+        let crossed_out = matches!(message, ChatLine::Privmsg(privmsg) if privmsg.deleted && privmsg.render.deleted == DeletedMessages::Strikethrough);
+        if crossed_out {
+            row.queue(style::SetAttribute(style::Attribute::CrossedOut))?;
+            row.queue(style::SetAttribute(style::Attribute::Dim))?;
+        }
+
+        let width = (chat_width as usize).saturating_sub(prefix_width);
+        // only the message under the cursor is scrolled
+        let scroll = match first_message_pos + i as u16 == cursor_pos.row {
+            true => horizontal_scroll,
+            false => 0,
+        };
+        let line = match i.checked_sub(1) {
+            Some(previous) if group_messages => {
+                let line = grouped_line(message, shown_messages[previous]);
+                Cow::Owned(fit_line(&line, scroll, width).into_owned())
+            }
+            _ => message.visible_line(scroll, width),
+        };
+
+        // the name in the sender's color, unless a highlight colors the whole line
+        let name_color = match message {
+            ChatLine::Privmsg(privmsg) if highlight.is_none() && !failed => {
+                let name = privmsg.shown_name();
+                line.starts_with(&*name)
+                    .then_some((privmsg.color, name.len()))
+            }
+            _ => None,
+        };
+
+        if let Some((color, name_len)) = name_color {
+            row.queue(style::SetForegroundColor(color))?;
+            row.queue(style::Print(&line[..name_len]))?;
+            row.queue(style::SetForegroundColor(theme.foreground()))?;
+            row.queue(style::Print(&line[name_len..]))?;
+        } else {
+            row.queue(style::Print(&line))?;
+        }
+
+        if highlight.is_some()
+            || failed
+            || matches!(
+                message,
+                ChatLine::Unread { .. } | ChatLine::System { color: Some(_), .. }
+            )
+        {
+            row.queue(style::SetForegroundColor(theme.foreground()))?;
+        }
+
+        if crossed_out {
+            row.queue(style::SetAttribute(style::Attribute::NotCrossedOut))?;
+        }
+        if crossed_out || faded {
+            row.queue(style::SetAttribute(style::Attribute::NormalIntensity))?;
+        }
+        if selected {
+            row.queue(style::SetAttribute(style::Attribute::NoReverse))?;
+        }
+
+        // drawn over the emote's text, which is blanked since the image is narrower
+        if let (Some(emote_images), ChatLine::Privmsg(privmsg)) = (emote_images, message) {
+            for (column, width, id) in emote_columns(&line, &privmsg.emotes) {
+                let Some(image) = emote_images.image(id) else {
+                    continue;
+                };
+                let column = (prefix_width + column) as u16;
+                let row_number = first_message_pos + i as u16;
+                row.queue(cursor::MoveTo(column, row_number))?;
+                row.queue(style::Print(" ".repeat(width)))?;
+                row.queue(cursor::MoveTo(column, row_number))?;
+                row.queue(style::Print(image))?;
+            }
+        }
+    }
+
+    let screen = frame::Screen {
+        top: messages_top,
+        bottom: compose_top,
+        columns: total_columns,
+        rows: total_rows,
+        foreground: theme.foreground(),
+        background: theme.background(),
+    };
+    // images and the popup go over the chat rows, which can't be kept then
+    let in_place =
+        scroll_region && emote_images.is_none() && popup.is_none() && chat_rows.can_update(&screen);
+
+    stdout.queue(style::SetForegroundColor(theme.foreground()))?;
+    stdout.queue(style::SetBackgroundColor(theme.background()))?;
+    if in_place {
+        for row in (0..messages_top).chain(compose_top..total_rows) {
+            stdout.queue(cursor::MoveTo(0, row))?;
+            stdout.queue(terminal::Clear(terminal::ClearType::CurrentLine))?;
+        }
+        chat_rows.update(stdout, screen, rows)?;
+
+        if sidebar.is_some() {
+            for row in messages_top..compose_top {
+                stdout.queue(cursor::MoveTo(chat_width, row))?;
+                stdout.queue(terminal::Clear(terminal::ClearType::UntilNewLine))?;
+            }
+        }
+    } else {
+        stdout.queue(terminal::Clear(terminal::ClearType::All))?;
+        if let Some(emote_images) = emote_images {
+            stdout.queue(style::Print(emote_images.clear()))?;
+        }
+        chat_rows.replace(stdout, screen, rows)?;
+    }
+    if emote_images.is_some() || popup.is_some() {
+        chat_rows.invalidate();
+    }
+
+    if tab_bar_rows > 0 {
+        draw_tab_bar(stdout, tabs, active_tab, merged_view, channel_hash)?;
+    }
+
+    // between the tab bar and chat, bold so they stand out from the messages scrolling by
+    stdout.queue(style::SetAttribute(style::Attribute::Bold))?;
+    for (i, pin) in pins.iter().enumerate() {
+        stdout.queue(cursor::MoveTo(0, tab_bar_rows + i as u16))?;
+        stdout.queue(style::Print(truncate(
+            &format!("[pinned] {pin}"),
+            chat_width as usize,
+        )))?;
+    }
+    stdout.queue(style::SetAttribute(style::Attribute::NormalIntensity))?;
+
+    if let Some(sidebar) = &sidebar {
+        draw_sidebar(
+            stdout,
+            sidebar,
+            chat_width,
+            messages_top,
+            layout.compose_top,
+        )?;
+    }
+
+    if let Some(popup) = &popup {
+        let (row, column) = compose_position(prompt, send_message, popup.column, total_columns);
+        draw_popup(stdout, popup, column, compose_top + row, total_columns)?;
+    }
+
+    // right-aligned on the last compose row, anything typed there goes over it
+    if !status.is_empty() {
+        let width = width::width(status) as u16;
+        stdout.queue(cursor::MoveTo(
+            total_columns.saturating_sub(width),
+            bottom_row,
+        ))?;
+        stdout.queue(style::Print(status))?;
+    }
+
+    if let Mode::Command | Mode::Search = edit_mode {
+        let prefix = if matches!(edit_mode, Mode::Search) {
+            '/'
+        } else {
+            ':'
+        };
+        stdout.queue(cursor::MoveTo(0, bottom_row))?;
+        stdout.queue(style::Print(format!("{prefix}{command_line}")))?;
+        stdout.queue(cursor::MoveTo(
+            width::width(command_line) as u16 + 1,
+            bottom_row,
+        ))?;
+    } else {
+        match command_output {
+            Some(command_output) => {
+                stdout.queue(cursor::MoveTo(0, bottom_row))?;
+                stdout.queue(style::Print(command_output))?;
+            }
+            None => {
+                let compose_line = format!("{prompt}{send_message}");
+                let mut rest = compose_line.as_str();
+                for row in compose_top..total_rows {
+                    let line = truncate(rest, total_columns as usize);
+                    stdout.queue(cursor::MoveTo(0, row))?;
+                    stdout.queue(style::Print(line))?;
+                    rest = &rest[line.len()..];
+                }
+            }
+        };
+
+        // cursor columns don't count the channel prefix or the prompt, and on the compose line
+        // they're a grapheme of the whole message which can wrap. Either way they're graphemes,
+        // which wide characters take two cells of the screen for
+        let (column, row) = if cursor_pos.row >= total_rows - 1 {
+            let (row, column) =
+                compose_position(prompt, send_message, cursor_pos.column, total_columns);
+            (column, compose_top + row)
+        } else {
+            let message = cursor_pos
+                .row
+                .checked_sub(first_message_pos)
+                .and_then(|index| shown_messages.get(index as usize));
+            let column = message.map_or(cursor_pos.column as usize, |message| {
+                let prefix_width = width::width(&timestamp_prefix(timestamp_format, message, now))
+                    + width::width(&line_prefix(channel_prefix, message, channel_hash));
+                let before_cursor = message
+                    .message_line()
+                    .graphemes(true)
+                    .skip(horizontal_scroll)
+                    .take((cursor_pos.column as usize).saturating_sub(horizontal_scroll))
+                    .map(width::grapheme_width)
+                    .sum::<usize>();
+                prefix_width + before_cursor
+            });
+            (column as u16, cursor_pos.row)
+        };
+
+        stdout.queue(cursor::MoveTo(column, row))?;
+    }
+
+    stdout.flush()?;
+
+    Ok(())
+}
+
+/// The column, width and id of each emote in `line` that's wide enough to put its image over
+fn emote_columns<'a>(line: &str, emotes: &'a [(String, String)]) -> Vec<(usize, usize, &'a str)> {
+    let mut columns = Vec::new();
+    let mut column = 0;
+
+    for word in line.split(' ') {
+        let width = width::width(word);
+        if width >= emote_images::IMAGE_WIDTH {
+            if let Some((id, _)) = emotes.iter().find(|(_, name)| name == word) {
+                columns.push((column, width, id.as_str()));
+            }
+        }
+        column += width + 1;
+    }
+
+    columns
+}
+
+/// A follow-up from the same sender as `previous` gets spaces in place of the name, as wide as
+/// it so the cells still line up with `message_line` for the cursor
+fn grouped_line<'a>(message: &'a ChatLine, previous: &ChatLine) -> Cow<'a, str> {
+    match (message, previous) {
+        (ChatLine::Privmsg(privmsg), previous) if same_group(message, previous) => {
+            let name_len = privmsg.shown_name().len() + ": ".len();
+            let indent = width::width(&privmsg.shown_name()) + ": ".len();
+            Cow::Owned(format!(
+                "{}{}",
+                " ".repeat(indent),
+                &privmsg.message_line()[name_len..]
+            ))
+        }
+        _ => message.message_line(),
+    }
+}
+
+/// Whether `message` goes with `previous` when grouping, `/me` messages don't have the `: `
+/// after the name and they stand out more with it
+fn same_group(message: &ChatLine, previous: &ChatLine) -> bool {
+    match (message, previous) {
+        (ChatLine::Privmsg(privmsg), ChatLine::Privmsg(previous)) => {
+            privmsg.display_name() == previous.display_name() && privmsg.action().is_none()
+        }
+        _ => false,
+    }
+}
+
+/// `messages` with a [`ChatLine::Spacer`] between every two of them for the cozy density, with
+/// `group_messages` only between groups
+fn with_spacers<'a>(
+    messages: Vec<&'a ChatLine>,
+    spacer: &'a ChatLine,
+    group_messages: bool,
+) -> Vec<&'a ChatLine> {
+    let mut spaced: Vec<&ChatLine> = Vec::with_capacity(messages.len() * 2);
+    for message in messages {
+        let grouped = spaced
+            .last()
+            .is_some_and(|previous| group_messages && same_group(message, previous));
+        if !spaced.is_empty() && !grouped {
+            spaced.push(spacer);
+        }
+        spaced.push(message);
+    }
+
+    spaced
+}
+
+/// `messages` with the recipients of the gift bombs in `expanded` right under them
+fn with_gift_recipients<'a>(
+    messages: Vec<&'a ChatLine>,
+    expanded: &HashSet<String>,
+) -> Vec<&'a ChatLine> {
+    if expanded.is_empty() {
+        return messages;
+    }
+
+    let mut lines = Vec::with_capacity(messages.len());
+    for message in messages {
+        lines.push(message);
+        if let ChatLine::GiftBomb(bomb) = message {
+            if expanded.contains(&bomb.id) {
+                lines.extend(&bomb.recipients);
+            }
+        }
+    }
+
+    lines
+}
+
+/// `name` right-aligned in `width` columns so messages line up after it, cut with a `…` if it
+/// doesn't fit, 0 leaves it alone
+fn align_name(name: &str, width: usize) -> Cow<'_, str> {
+    let cells = width::width(name);
+    match width {
+        0 => Cow::Borrowed(name),
+        width if cells > width => {
+            // a wide character that doesn't fit whole leaves a cell to pad
+            let cut = truncate(name, width - 1);
+            let padding = " ".repeat(width - 1 - width::width(cut));
+            Cow::Owned(format!("{padding}{cut}…"))
+        }
+        width => Cow::Owned(format!("{}{name}", " ".repeat(width - cells))),
+    }
+}
+
+/// Twitch's `#RRGGBB` colors
+fn parse_color(color: &str) -> Option<style::Color> {
+    let hex = color.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let channel = |range| u8::from_str_radix(hex.get(range)?, 16).ok();
+
+    Some(style::Color::Rgb {
+        r: channel(0..2)?,
+        g: channel(2..4)?,
+        b: channel(4..6)?,
+    })
+}
+
+/// The line shown in place of our own message for broadcaster commands, until twitch's NOTICE
+/// says how it went
+fn command_echo(message: &str) -> Option<String> {
+    let (command, target) = message.split_once(' ').unwrap_or((message, ""));
+    let target = target.trim().trim_start_matches(['@', '#']);
+
+    match (command.to_lowercase().as_str(), target) {
+        ("/raid", target) if !target.is_empty() => Some(format!("[raid] raiding {target}…")),
+        ("/unraid", _) => Some(String::from("[raid] cancelling the raid…")),
+        ("/shoutout", target) if !target.is_empty() => {
+            Some(format!("[shoutout] shouting out {target}…"))
+        }
+        _ => None,
+    }
+}
+
+/// Red for the NOTICEs that say something didn't work, going by their `msg-id`
+fn notice_color(msg_id: &str) -> Option<style::Color> {
+    let failed = msg_id.contains("error")
+        || ["bad_", "no_", "msg_", "invalid_"]
+            .iter()
+            .any(|prefix| msg_id.starts_with(prefix));
+    failed.then_some(style::Color::Red)
+}
+
+/// What a USERNOTICE shows in chat and its color, `None` if twitch didn't describe it
+fn user_notice_line(tags: &Tags, message: Option<&str>) -> Option<(String, Option<style::Color>)> {
+    if tags.get("msg-id").map(String::as_str) == Some("announcement") {
+        let name = tags
+            .get("display-name")
+            .or(tags.get("login"))
+            .map_or("someone", String::as_str);
+        let color = tags
+            .get("msg-param-color")
+            .map_or("PRIMARY", String::as_str);
+
+        return Some((
+            format!("[announcement] {name}: {}", message.unwrap_or_default()),
+            Some(announcement_color(color)),
+        ));
+    }
+
+    if let Some(sub) = tags.sub() {
+        let name = tags
+            .get("display-name")
+            .or(tags.get("login"))
+            .map_or("someone", String::as_str);
+        let line = sub_line(name, &sub);
+
+        return Some(match message {
+            Some(message) => (format!("{line} {message}"), None),
+            None => (line, None),
+        });
+    }
+
+    let system_msg = tags.get("system-msg").filter(|text| !text.is_empty())?;
+    Some(match message {
+        Some(message) => (format!("{system_msg} {message}"), None),
+        None => (system_msg.clone(), None),
+    })
+}
+
+/// Like "Foo gifted 50 Tier 1 subs", `None` without the count
+fn gift_bomb_text(tags: &Tags) -> Option<String> {
+    let count = tags.mass_gift_count()?;
+    let name = tags
+        .get("display-name")
+        .or(tags.get("login"))
+        .map_or("someone", String::as_str);
+    let plan = tags
+        .sub_plan()
+        .map(|plan| format!(" {plan}"))
+        .unwrap_or_default();
+
+    Some(match count {
+        1 => format!("{name} gifted a{plan} sub"),
+        count => format!("{name} gifted {count}{plan} subs"),
+    })
+}
+
+/// Like "Foo resubscribed (Tier 2) for 14 months, 5-month streak!"
+fn sub_line(name: &str, sub: &Sub) -> String {
+    let mut line = match sub.resub {
+        true => format!("{name} resubscribed ({})", sub.plan),
+        false => format!("{name} subscribed ({})", sub.plan),
+    };
+
+    if let Some(months) = sub.cumulative_months.filter(|months| *months > 1) {
+        line.push_str(&format!(" for {months} months"));
+    }
+    if let Some(streak) = sub.streak_months.filter(|streak| *streak > 1) {
+        line.push_str(&format!(", {streak}-month streak"));
+    }
+    line.push('!');
+
+    line
+}
+
+/// The colors the web client draws announcement boxes in, `PRIMARY` is the channel's accent
+/// color which we don't know, so it's twitch purple
+fn announcement_color(color: &str) -> style::Color {
+    match color {
+        "BLUE" => style::Color::Blue,
+        "GREEN" => style::Color::Green,
+        "ORANGE" => style::Color::Rgb {
+            r: 0xff,
+            g: 0x8c,
+            b: 0x00,
+        },
+        _ => style::Color::Rgb {
+            r: 0x91,
+            g: 0x46,
+            b: 0xff,
+        },
+    }
+}
+
+/// When `message` was sent in `format`, `None` when timestamps are turned off
+fn timestamp_prefix(format: Option<&str>, message: &ChatLine, now: DateTime<Local>) -> String {
+    match format {
+        _ if matches!(message, ChatLine::Spacer) => String::new(),
+        None | Some("") => String::new(),
+        Some(config::RELATIVE_TIMESTAMPS) => {
+            format!("{} ago ", format_ago(now - message.timestamp()))
+        }
+        Some(format) => format!("{} ", message.timestamp().format(format)),
+    }
+}
+
+/// The `channel_prefix` template filled in for `message`
+fn line_prefix(format: Option<&str>, message: &ChatLine, channel_hash: bool) -> String {
+    format
+        .filter(|_| !matches!(message, ChatLine::Spacer))
+        .map(|format| fill_channel(format, message.channel(), channel_hash))
+        .unwrap_or_default()
+}
+
+/// How every part of the UI shows a channel, with the `#` IRC puts in front unless it's turned
+/// off with `channel_hash`
+fn channel_label(channel: &str, channel_hash: bool) -> String {
+    match channel_hash {
+        true => format!("#{channel}"),
+        false => channel.to_string(),
+    }
+}
+
+/// Fills in `{channel}` in a template from the config, a `#` in front of it comes and goes with
+/// `channel_hash` like everywhere else
+fn fill_channel(template: &str, channel: &str, channel_hash: bool) -> String {
+    template
+        .replace("#{channel}", &channel_label(channel, channel_hash))
+        .replace("{channel}", channel)
+}
+
+/// The `color` tag, or one picked from `login` for people who never chose a color, twitch sends
+/// an empty tag for them. Our own messages go through this with our USERSTATE tags too
+///
+/// It's worked out once when the message comes in and kept on it, the login is lowercased so
+/// someone gets the same color whether it came from the prefix, `--nick` or a display name
+fn name_color(tags: &Tags, login: &str) -> style::Color {
+    tags.get("color")
+        .and_then(|color| parse_color(color))
+        .unwrap_or_else(|| hash_color(&login.to_lowercase()))
+}
+
+/// Picks a color from the name, so the same name always gets the same color
+fn hash_color(name: &str) -> style::Color {
+    const COLORS: [style::Color; 12] = [
+        style::Color::Red,
+        style::Color::Green,
+        style::Color::Yellow,
+        style::Color::Blue,
+        style::Color::Magenta,
+        style::Color::Cyan,
+        style::Color::DarkRed,
+        style::Color::DarkGreen,
+        style::Color::DarkYellow,
+        style::Color::DarkBlue,
+        style::Color::DarkMagenta,
+        style::Color::DarkCyan,
+    ];
+
+    // FNV-1a, std's hasher isn't guaranteed to stay the same between releases
+    let hash = name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+
+    COLORS[(hash % COLORS.len() as u64) as usize]
+}
+
+/// Inserts `text` before the grapheme at `column`, returns the column right after it
+fn insert_text(line: &mut String, column: u16, text: &str) -> u16 {
+    let offset = byte_offset(line, column as usize);
+    line.insert_str(offset, text);
+    // a combining character joins the grapheme before it
+    line[..offset + text.len()].graphemes(true).count() as u16
+}
+
+/// Overwrites the grapheme at `column` with `c`, returns false if the line is shorter than that
+fn replace_grapheme(line: &mut String, column: u16, c: char) -> bool {
+    let Some((start, grapheme)) = line.grapheme_indices(true).nth(column as usize) else {
+        return false;
+    };
+
+    let end = start + grapheme.len();
+    line.replace_range(start..end, c.encode_utf8(&mut [0; 4]));
+    true
+}
+
+/// The row and cell every grapheme of the prompt and the compose line starts at when they're
+/// wrapped to `width` cells, and last where the cursor goes after them. A wide character that
+/// doesn't fit at the end of a row starts the next one, like `truncate` cuts it
+fn compose_cells(prompt: &str, send_message: &str, width: u16) -> Vec<(u16, u16)> {
+    let width = width.max(1) as usize;
+    let mut cells = Vec::new();
+    let (mut row, mut cell) = (0, 0);
 
-                        '$' if matches!(edit_mode, Mode::Normal) => {
-                            let Some(current_message) = chat_messages.get(current_message_index)
-                            else {
-                                continue;
-                            };
+    for grapheme in prompt.graphemes(true).chain(send_message.graphemes(true)) {
+        let grapheme_width = width::grapheme_width(grapheme);
+        if cell > 0 && cell + grapheme_width > width {
+            row += 1;
+            cell = 0;
+        }
+        cells.push((row, cell as u16));
+        cell += grapheme_width;
+    }
+    if cell >= width {
+        row += 1;
+        cell = 0;
+    }
+    cells.push((row, cell as u16));
 
-                            cursor_pos.column = current_message.message_line_len() as u16;
-                        }
+    cells
+}
 
-                        '^' if matches!(edit_mode, Mode::Normal) => {
-                            cursor_pos.column = 0;
-                        }
+/// Rows the compose line wraps to, with room for the cursor after the last grapheme
+fn compose_rows(prompt: &str, send_message: &str, width: u16) -> u16 {
+    compose_cells(prompt, send_message, width)
+        .last()
+        .map_or(0, |(row, _)| *row)
+        + 1
+}
 
-                        'y' if matches!(edit_mode, Mode::Normal) => {
-                            edit_mode = Mode::Y;
-                        }
+/// The row within the wrapped compose line and the screen column of the grapheme at `column`
+fn compose_position(prompt: &str, send_message: &str, column: u16, width: u16) -> (u16, u16) {
+    let cells = compose_cells(prompt, send_message, width);
+    let index = prompt.graphemes(true).count() + column as usize;
+    cells[index.min(cells.len() - 1)]
+}
 
-                        'd' if matches!(edit_mode, Mode::Normal) => {
-                            edit_mode = Mode::D;
-                        }
+/// The grapheme shown at `position` in the wrapped compose line, the closest one if the row is
+/// shorter than that or it's where the prompt is
+fn compose_column(prompt: &str, send_message: &str, position: (u16, u16), width: u16) -> u16 {
+    let cells = compose_cells(prompt, send_message, width);
+    let prompt_graphemes = prompt.graphemes(true).count();
 
-                        c if matches!(edit_mode, Mode::Y) => {
-                            if c == 'y' {
-                                if let Some(current_message) =
-                                    chat_messages.get(current_message_index)
-                                {
-                                    clipboard.set_text(&current_message.message).unwrap();
-                                };
-                            }
+    cells[prompt_graphemes..]
+        .iter()
+        .rposition(|cell| *cell <= position)
+        .unwrap_or(0) as u16
+}
 
-                            edit_mode = Mode::Normal;
-                        }
+/// Byte offset of the grapheme at `column`, or the end of `line` if it's shorter than that
+fn byte_offset(line: &str, column: usize) -> usize {
+    line.grapheme_indices(true)
+        .nth(column)
+        .map_or(line.len(), |(offset, _)| offset)
+}
 
-                        c if matches!(edit_mode, Mode::D) => {
-                            if c == 'd' {
-                                if cursor_pos.row == total_rows - 1 {
-                                    clipboard.set_text(&send_message).unwrap();
-                                    send_message.clear();
-                                    cursor_pos.column = 0;
-                                }
-                            }
+/// What's shown of `line` in `width` cells when it's scrolled right by `scroll` graphemes, a
+/// `…` takes the place of the last one shown if there's more and of the first one if the start
+/// is scrolled out, so every grapheme stays in its column
+///
+/// A cut in the middle of a word moves back to the space before it, unless that would leave
+/// less than half the row, the half word at the end only gets read wrong otherwise
+fn fit_line(line: &str, scroll: usize, width: usize) -> Cow<'_, str> {
+    let line = &line[byte_offset(line, scroll)..];
+    let visible = truncate(line, width);
+    let cut = visible.len() < line.len();
+    if !cut && scroll == 0 {
+        return Cow::Borrowed(visible);
+    }
 
-                            edit_mode = Mode::Normal;
-                        }
+    let mut graphemes = visible.graphemes(true).collect::<Vec<_>>();
+    if cut && width > 0 {
+        // room for the `…`
+        let mut cells = width::width(visible);
+        while cells >= width {
+            let Some(last) = graphemes.pop() else {
+                break;
+            };
+            cells -= width::grapheme_width(last);
+        }
 
-                        'P' if matches!(edit_mode, Mode::Normal) => {
-                            if let Ok(clipboard_text) = clipboard.get_text() {
-                                if cursor_pos.row != total_rows - 1 {
-                                    cursor_pos.row = total_rows - 1;
-                                    cursor_pos.column = send_message.graphemes(true).count() as u16;
-                                }
+        let kept = graphemes
+            .iter()
+            .map(|grapheme| grapheme.len())
+            .sum::<usize>();
+        let mid_word = !line[kept..].starts_with(char::is_whitespace)
+            && graphemes.last().is_some_and(|last| !last.trim().is_empty());
+        let space = graphemes
+            .iter()
+            .rposition(|grapheme| grapheme.trim().is_empty());
+        let space_cell = |space: usize| {
+            graphemes[..space]
+                .iter()
+                .map(|grapheme| width::grapheme_width(grapheme))
+                .sum::<usize>()
+        };
+        if let Some(space) = space.filter(|space| mid_word && space_cell(*space) >= width / 2) {
+            graphemes.truncate(space + 1);
+        }
+        graphemes.push("…");
+    }
+    if let Some(first) = graphemes.first_mut().filter(|_| scroll > 0) {
+        // a wide one keeps its second cell so the rest doesn't move
+        *first = match width::grapheme_width(first) {
+            2 => "… ",
+            _ => "…",
+        };
+    }
 
-                                send_message
-                                    .insert_str(cursor_pos.column as usize, &clipboard_text);
-                                cursor_pos.column += clipboard_text.graphemes(true).count() as u16;
-                            }
-                        }
+    Cow::Owned(graphemes.concat())
+}
 
-                        c if matches!(edit_mode, Mode::Insert) => {
-                            send_message.insert(cursor_pos.column as usize, c);
-                            cursor_pos.column += 1;
-                        }
+/// The graphemes of `line` that fit in `width` cells
+fn truncate(line: &str, width: usize) -> &str {
+    let mut cells = 0;
+    for (offset, grapheme) in line.grapheme_indices(true) {
+        cells += width::grapheme_width(grapheme);
+        if cells > width {
+            return &line[..offset];
+        }
+    }
 
-                        _ => {}
-                    },
-                    _ => {}
-                },
-                _ => {}
-            }
+    line
+}
 
-            stdout.flush().unwrap();
+/// One row with every channel and how many messages were missed in it
+///
+/// In the merged view `all` is highlighted and the channel messages are sent to is underlined
+fn draw_tab_bar(
+    stdout: &mut Stdout,
+    tabs: &[Tab],
+    active_tab: usize,
+    merged_view: bool,
+    channel_hash: bool,
+) -> anyhow::Result<()> {
+    stdout.queue(cursor::MoveTo(0, 0))?;
+
+    if merged_view {
+        stdout.queue(style::SetAttribute(style::Attribute::Reverse))?;
+        stdout.queue(style::Print(" all "))?;
+        stdout.queue(style::SetAttribute(style::Attribute::NoReverse))?;
+    } else {
+        stdout.queue(style::Print(" all "))?;
+    }
+
+    for (i, tab) in tabs.iter().enumerate() {
+        let channel = channel_label(&tab.channel, channel_hash);
+        let label = match tab.unread {
+            0 => format!(" {channel} "),
+            unread => format!(" {channel} ({unread}) "),
+        };
+
+        if i == active_tab && merged_view {
+            stdout.queue(style::SetAttribute(style::Attribute::Underlined))?;
+            stdout.queue(style::Print(label))?;
+            stdout.queue(style::SetAttribute(style::Attribute::NoUnderline))?;
+        } else if i == active_tab {
+            stdout.queue(style::SetAttribute(style::Attribute::Reverse))?;
+            stdout.queue(style::Print(label))?;
+            stdout.queue(style::SetAttribute(style::Attribute::NoReverse))?;
+        } else {
+            stdout.queue(style::Print(label))?;
         }
     }
 
-    disable_raw_mode().unwrap();
+    Ok(())
 }
 
-fn draw(
+fn draw_sidebar(
     stdout: &mut Stdout,
-    cursor_pos: &CursorPos,
-    edit_mode: &Mode,
-    chat_messages: &[Privmsg],
-    send_message: &str,
-    total_rows: u16,
+    sidebar: &Sidebar,
+    start_column: u16,
+    start_row: u16,
+    end_row: u16,
 ) -> anyhow::Result<()> {
-    stdout
-        .execute(terminal::Clear(terminal::ClearType::All))
-        .unwrap();
+    // leave room for the border
+    let width = sidebar.width.saturating_sub(2) as usize;
+
+    let lines = [sidebar.channel.clone(), String::from("chatters:")]
+        .into_iter()
+        .chain(sidebar.chatters.names().map(|name| format!(" {name}")));
 
-    let messages_start = chat_messages.len().saturating_sub(total_rows as usize);
-    let first_message_pos = total_rows
-        .saturating_sub(chat_messages.len() as u16)
-        .saturating_sub(1);
-    stdout.queue(cursor::MoveTo(0, first_message_pos))?;
-    for (i, message) in chat_messages[messages_start..].iter().enumerate() {
-        stdout.queue(style::Print(message.message_line()))?;
-        stdout.queue(cursor::MoveTo(0, first_message_pos + i as u16 + 1))?;
+    // the compose area below keeps the full width
+    let mut lines = lines;
+    for row in start_row..end_row {
+        stdout.queue(cursor::MoveTo(start_column, row))?;
+        stdout.queue(style::Print("│ "))?;
+
+        if let Some(line) = lines.next() {
+            stdout.queue(style::Print(truncate(&line, width)))?;
+        }
     }
 
-    stdout.queue(cursor::MoveTo(0, total_rows))?;
+    Ok(())
+}
 
-    stdout.queue(style::Print(send_message))?;
+/// The names in a box that ends right above `bottom`, starting at `column` unless that's too
+/// close to the right edge
+fn draw_popup(
+    stdout: &mut Stdout,
+    popup: &Popup,
+    column: u16,
+    bottom: u16,
+    total_columns: u16,
+) -> anyhow::Result<()> {
+    let width = popup
+        .names
+        .iter()
+        .map(|name| width::width(name) + 2)
+        .max()
+        .unwrap_or(0)
+        .min(total_columns as usize);
+    let column = column.min(total_columns - width as u16);
 
-    stdout.queue(cursor::MoveTo(
-        cursor_pos.column as u16,
-        cursor_pos.row as u16,
-    ))?;
+    for (i, name) in popup.names.iter().enumerate() {
+        let Some(row) = bottom.checked_sub((popup.names.len() - i) as u16) else {
+            continue;
+        };
 
-    stdout.flush()?;
+        stdout.queue(cursor::MoveTo(column, row))?;
+        if i == popup.selected {
+            stdout.queue(style::SetAttribute(style::Attribute::Reverse))?;
+        }
+        let padding = " ".repeat((width - 1).saturating_sub(width::width(name)));
+        let line = format!(" {name}{padding}");
+        stdout.queue(style::Print(truncate(&line, width)))?;
+        stdout.queue(style::SetAttribute(style::Attribute::Reset))?;
+    }
 
     Ok(())
 }
@@ -689,52 +4397,815 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_tags_parsing() {
-        let message = "@badge-info=;badges=moderator/1;color=;display-name=bar;emote-sets=0,300374282;mod=1;subscriber=0;user-type=mod :tmi.twitch.tv USERSTATE #foo";
-        let mut pos = 0;
-        let tags = Tags::parse(message, &mut pos).unwrap();
+    fn test_action_parsing() {
+        assert_eq!(Action::parse("quit").unwrap(), Action::Quit);
+        assert_eq!(Action::parse("reload").unwrap(), Action::Reload);
+        assert_eq!(
+            Action::parse("raw CAP LS 302").unwrap(),
+            Action::Raw(String::from("CAP LS 302"))
+        );
+        assert!(Action::parse("raw").is_err());
+        assert_eq!(Action::parse(" clear ").unwrap(), Action::Clear);
+        assert_eq!(
+            Action::parse("channel #Foo").unwrap(),
+            Action::Channel(String::from("foo"))
+        );
+        assert_eq!(
+            Action::parse("theme dark").unwrap(),
+            Action::Theme(Theme::Dark)
+        );
 
-        eprintln!("{tags:?}");
+        assert_eq!(
+            Action::parse("filter ^foo: ").unwrap(),
+            Action::Filter(Some(String::from("^foo:")))
+        );
+        assert_eq!(Action::parse("filter").unwrap(), Action::Filter(None));
 
-        assert_eq!(&message[pos - 1..pos], " ");
+        assert_eq!(
+            Action::parse("join #Bar").unwrap(),
+            Action::Join(String::from("bar"))
+        );
+        assert_eq!(
+            Action::parse("join https://twitch.tv/foo").unwrap(),
+            Action::Join(String::from("foo"))
+        );
+        assert!(Action::parse("join foo bar").is_err());
+        assert_eq!(Action::parse("part").unwrap(), Action::Part);
+        assert_eq!(Action::parse("all").unwrap(), Action::ToggleMerged);
+        assert_eq!(Action::parse("cancel").unwrap(), Action::Cancel);
 
-        assert_eq!(pos, 112);
+        assert!(Action::parse("channel").is_err());
+        assert!(Action::parse("join").is_err());
+        assert!(Action::parse("theme purple").is_err());
+        assert!(Action::parse("nope").is_err());
     }
 
     #[test]
-    fn test_prefix_parsing() {
-        let message = "@badge-info=;badges=moderator/1;color=;display-name=bar;emote-sets=0,300374282;mod=1;subscriber=0;user-type=mod :tmi.twitch.tv USERSTATE #foo";
-        let mut pos = 0;
-        let _ = Tags::parse(message, &mut pos).unwrap();
-        let prefix = Prefix::parse(message, &mut pos).unwrap();
+    fn test_cursor_style() {
+        let style = |mode: Mode| {
+            let mut escape = String::new();
+            crossterm::Command::write_ansi(&mode.cursor_style(), &mut escape).unwrap();
+            escape
+        };
+
+        assert_eq!(style(Mode::Insert), style(Mode::Search));
+        assert_eq!(style(Mode::Y), style(Mode::Replay(3)));
+        assert_eq!(style(Mode::Normal), style(Mode::Visual { anchor: 0 }));
+        assert_ne!(style(Mode::Normal), style(Mode::Insert));
+        assert_ne!(style(Mode::Normal), style(Mode::D));
+    }
+
+    #[test]
+    fn test_collapse_whitespace() {
+        let ChatLine::Privmsg(mut privmsg) = privmsg("Foo", "  look      at\tthis  ") else {
+            unreachable!()
+        };
+
+        assert_eq!(privmsg.message_line(), "Foo:   look      at this  ");
+
+        privmsg.set_render(RenderOptions {
+            collapse_whitespace: true,
+            ..privmsg.render
+        });
+        assert_eq!(privmsg.message_line(), "Foo: look at this");
+        assert_eq!(privmsg.message, "  look      at\tthis  ");
+    }
+
+    #[test]
+    fn test_command_echo() {
+        assert_eq!(
+            command_echo("/raid @Forsen").as_deref(),
+            Some("[raid] raiding Forsen…")
+        );
+        assert_eq!(
+            command_echo("/shoutout xqc").as_deref(),
+            Some("[shoutout] shouting out xqc…")
+        );
+        assert!(command_echo("/unraid").is_some());
+        assert_eq!(command_echo("/raid"), None);
+        assert_eq!(command_echo("raid forsen"), None);
+
+        assert_eq!(notice_color("raid_error_self"), Some(style::Color::Red));
+        assert_eq!(notice_color("no_permission"), Some(style::Color::Red));
+        assert_eq!(notice_color("raid_notice_mature"), None);
+    }
+
+    #[test]
+    fn test_delivery() {
+        let now = Instant::now();
+        let mut tab = Tab::new(String::from("bar"), 0);
+        for (text, delivery) in [
+            ("first", Delivery::Pending(now)),
+            ("second", Delivery::Pending(now + Duration::from_secs(5))),
+        ] {
+            let ChatLine::Privmsg(mut message) = privmsg("Foo", text) else {
+                unreachable!()
+            };
+            message.delivery = delivery;
+            tab.push(ChatLine::Privmsg(message), true);
+        }
+
+        let deliveries = |tab: &Tab| {
+            tab.messages
+                .iter()
+                .filter_map(|line| match line {
+                    ChatLine::Privmsg(privmsg) => Some(privmsg.delivery),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        tab.oldest_pending().unwrap().delivery = Delivery::Delivered;
+        assert_eq!(
+            deliveries(&tab),
+            [
+                Delivery::Delivered,
+                Delivery::Pending(now + Duration::from_secs(5))
+            ]
+        );
+
+        // an answer with a `client-nonce` is about that message, not the oldest one
+        if let Some(ChatLine::Privmsg(privmsg)) = tab.messages.last_mut() {
+            privmsg.nonce = Some(String::from("abc"));
+        }
+        let answer = Tags::parse("@client-nonce=abc ", &mut 0).unwrap();
+        assert!(tab
+            .answered(&answer)
+            .is_some_and(|privmsg| privmsg.text() == "second"));
+        let unknown = Tags::parse("@client-nonce=def ", &mut 0).unwrap();
+        assert!(tab.answered(&unknown).is_none());
+
+        tab.expire_pending(now + Duration::from_secs(12), DELIVERY_TIMEOUT);
+        assert_eq!(
+            deliveries(&tab)[1],
+            Delivery::Pending(now + Duration::from_secs(5))
+        );
+        tab.expire_pending(now + Duration::from_secs(20), DELIVERY_TIMEOUT);
+        assert_eq!(deliveries(&tab), [Delivery::Delivered, Delivery::Failed]);
+        assert!(tab.oldest_pending().is_none());
+    }
+
+    #[test]
+    fn test_with_spacers() {
+        let (a, b, c) = (
+            privmsg("Foo", "hi"),
+            privmsg("Foo", "again"),
+            privmsg("Bar", "yo"),
+        );
+        let spacer = ChatLine::Spacer;
+        let lines = |spaced: Vec<&ChatLine>| {
+            spaced
+                .iter()
+                .map(|line| line.message_line().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            lines(with_spacers(vec![&a, &b, &c], &spacer, false)),
+            ["Foo: hi", "", "Foo: again", "", "Bar: yo"]
+        );
+        assert_eq!(
+            lines(with_spacers(vec![&a, &b, &c], &spacer, true)),
+            ["Foo: hi", "Foo: again", "", "Bar: yo"]
+        );
+        assert!(with_spacers(Vec::new(), &spacer, true).is_empty());
+        assert_eq!(timestamp_prefix(Some("%H:%M"), &spacer, Local::now()), "");
+    }
+
+    #[test]
+    fn test_nick_width() {
+        assert_eq!(align_name("foo", 0), "foo");
+        assert_eq!(align_name("foo", 5), "  foo");
+        assert_eq!(align_name("forsen", 5), "fors…");
+
+        let ChatLine::Privmsg(mut privmsg) = privmsg("Foo", "hi") else {
+            unreachable!()
+        };
+        privmsg.set_render(RenderOptions {
+            nick_width: 6,
+            ..privmsg.render
+        });
+        assert_eq!(privmsg.message_line(), "   Foo: hi");
+        assert_eq!(privmsg.shown_name(), "   Foo");
+    }
+
+    #[test]
+    fn test_deleted_messages() {
+        let ChatLine::Privmsg(mut message) = privmsg("Foo", "something rude") else {
+            unreachable!()
+        };
+        assert_eq!(message.message_line(), "Foo: something rude");
 
-        eprintln!("{prefix:?}");
+        message.set_deleted();
+        assert_eq!(message.message_line(), "Foo: <message deleted>");
+        // still there for moderators who yank it
+        assert_eq!(message.text(), "something rude");
 
-        assert_eq!(&message[pos..pos + 1], "U");
+        message.set_render(RenderOptions {
+            deleted: DeletedMessages::Strikethrough,
+            ..message.render
+        });
+        assert_eq!(message.message_line(), "Foo: something rude");
+
+        message.set_render(RenderOptions {
+            deleted: DeletedMessages::Hide,
+            ..message.render
+        });
+        assert!(ChatLine::Privmsg(message).hidden());
+        assert!(!privmsg("Foo", "fine").hidden());
+    }
+
+    #[test]
+    fn test_action_messages() {
+        let ChatLine::Privmsg(action) = privmsg("Foo", "\u{1}ACTION waves\u{1}") else {
+            unreachable!()
+        };
+
+        assert_eq!(action.message_line(), "Foo waves");
+        assert_eq!(action.text(), "waves");
+
+        let line = privmsg("Foo", "hi \u{1b}[31mred");
+        assert_eq!(line.message_line(), "Foo: hi [31mred");
+        assert_eq!(line.message(), "hi \u{1b}[31mred");
+    }
+
+    #[test]
+    fn test_mark_read() {
+        let mut tab = Tab::new(String::from("bar"), 10);
+        tab.mark_read();
+        assert!(matches!(&tab.messages[..], [ChatLine::SessionStart { .. }]));
+        tab.messages.clear();
+
+        tab.notice(String::from("one"), true);
+        tab.mark_read();
+        tab.mark_read();
+        tab.notice(String::from("two"), true);
+        tab.mark_read();
+
+        let lines = tab
+            .messages
+            .iter()
+            .map(|line| line.message_line().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, ["* one", "* two", "── new messages ──"]);
+
+        tab.drop_unread_line();
+        assert_eq!(tab.messages.len(), 2);
+    }
+
+    #[test]
+    fn test_channel_prefix() {
+        let message = privmsg("Foo", "hello");
+
+        assert_eq!(
+            line_prefix(Some("[#{channel}] "), &message, true),
+            "[#bar] "
+        );
+        assert_eq!(line_prefix(None, &message, true), "");
+
+        assert_eq!(
+            line_prefix(Some("[#{channel}] "), &message, false),
+            "[bar] "
+        );
+        assert_eq!(fill_channel("{channel}> ", "bar", true), "bar> ");
+        assert_eq!(channel_label("bar", false), "bar");
+        assert_eq!(hash_color("bar"), hash_color("bar"));
+    }
+
+    #[test]
+    fn test_timestamp_prefix() {
+        let message = privmsg("Foo", "hello");
+        let sent = message.timestamp();
+
+        assert_eq!(
+            timestamp_prefix(Some("%H:%M"), &message, sent),
+            format!("{} ", sent.format("%H:%M"))
+        );
+        assert_eq!(
+            timestamp_prefix(Some("relative"), &message, sent + TimeDelta::minutes(2)),
+            "2m ago "
+        );
+        assert_eq!(timestamp_prefix(None, &message, sent), "");
+        assert_eq!(timestamp_prefix(Some(""), &message, sent), "");
+    }
+
+    #[test]
+    fn test_slow_mode_wait() {
+        let now = Instant::now();
+        let mut tab = Tab::new(String::from("foo"), 0);
+        let last_sent = (String::from("hi"), now);
+
+        assert_eq!(slow_mode_wait(&tab, None, Some(&last_sent), now), None);
+
+        tab.slow_mode = Some(Duration::from_secs(30));
+        assert_eq!(
+            slow_mode_wait(
+                &tab,
+                None,
+                Some(&last_sent),
+                now + Duration::from_millis(1500)
+            ),
+            Some(Duration::from_secs(29))
+        );
+        assert_eq!(
+            slow_mode_wait(&tab, None, Some(&last_sent), now + Duration::from_secs(30)),
+            None
+        );
+        assert_eq!(slow_mode_wait(&tab, None, None, now), None);
+
+        let tags = |line: &str| twitcher::IRCMessage::parse(line).unwrap().tags;
+        let moderator = tags("@badges=moderator/1;mod=1 :tmi.twitch.tv USERSTATE #foo\r\n");
+        assert_eq!(
+            slow_mode_wait(&tab, Some(&moderator), Some(&last_sent), now),
+            None
+        );
+        let viewer = tags("@badges=subscriber/12;mod=0 :tmi.twitch.tv USERSTATE #foo\r\n");
+        assert_eq!(
+            slow_mode_wait(&tab, Some(&viewer), Some(&last_sent), now),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn test_only_emotes() {
+        let mut tab = Tab::new(String::from("foo"), 0);
+        let emotes = [String::from("Kappa")];
+        assert!(only_emotes("Kappa Kappa", &tab, &emotes));
+        assert!(!only_emotes("Kappa hi", &tab, &emotes));
+        assert!(only_emotes("/me", &tab, &emotes));
+
+        let tags =
+            twitcher::IRCMessage::parse("@emotes=1902:0-4 :tmi.twitch.tv PRIVMSG #foo :x\r\n")
+                .unwrap()
+                .tags;
+        tab.messages.push(ChatLine::Privmsg(Privmsg::new(
+            tags,
+            Prefix::default(),
+            String::from("foo"),
+            String::from("Keepo"),
+        )));
+        assert!(only_emotes("Keepo  Kappa", &tab, &emotes));
+    }
+
+    #[test]
+    fn test_dedup_message() {
+        let now = Instant::now();
+        let last_sent = (String::from("hi"), now);
+
+        assert_eq!(
+            dedup_message("hello", Some(&last_sent), now, true).as_deref(),
+            Some("hello")
+        );
+        assert_eq!(
+            dedup_message("hi", Some(&last_sent), now, true),
+            Some(format!("hi{DUPLICATE_SUFFIX}"))
+        );
+        assert_eq!(dedup_message("hi", Some(&last_sent), now, false), None);
+        assert_eq!(
+            dedup_message("hi", Some(&last_sent), now + DUPLICATE_WINDOW, false).as_deref(),
+            Some("hi")
+        );
+
+        // the one with the suffix was sent last, so the plain one goes through again
+        let last_sent = (format!("hi{DUPLICATE_SUFFIX}"), now);
+        assert_eq!(
+            dedup_message("hi", Some(&last_sent), now, true).as_deref(),
+            Some("hi")
+        );
+    }
+
+    #[test]
+    fn test_insert_text() {
+        let mut line = String::from("hllo");
+        assert_eq!(insert_text(&mut line, 1, "e"), 2);
+        assert_eq!(line, "hello");
+
+        assert_eq!(insert_text(&mut line, 9, " there"), 11);
+        assert_eq!(line, "hello there");
+
+        let mut line = String::from("cafe");
+        assert_eq!(insert_text(&mut line, 4, "\u{301}"), 4);
+        assert_eq!(line, "cafe\u{301}");
+    }
+
+    #[test]
+    fn test_replace_grapheme() {
+        let mut line = String::from("cafe\u{301} time");
+        assert!(replace_grapheme(&mut line, 3, 'e'));
+        assert_eq!(line, "cafe time");
+
+        assert!(replace_grapheme(&mut line, 5, '👍'));
+        assert_eq!(line, "cafe 👍ime");
+        assert!(replace_grapheme(&mut line, 5, 't'));
+        assert_eq!(line, "cafe time");
+
+        assert!(!replace_grapheme(&mut line, 9, 'x'));
+        assert_eq!(line, "cafe time");
+    }
+
+    #[test]
+    fn test_confirmation_prompt() {
+        let commands = Config::default().confirm_commands;
+
+        assert_eq!(
+            confirmation_prompt("/ban someone", &commands).as_deref(),
+            Some("Ban someone? y/n")
+        );
+        assert_eq!(
+            confirmation_prompt("/TIMEOUT someone 600", &commands).as_deref(),
+            Some("Timeout someone 600? y/n")
+        );
+        assert_eq!(
+            confirmation_prompt("/clear", &commands).as_deref(),
+            Some("Clear? y/n")
+        );
+        assert_eq!(confirmation_prompt("/me waves", &commands), None);
+        assert_eq!(confirmation_prompt("ban me pls", &commands), None);
+        assert_eq!(confirmation_prompt("/ban someone", &[]), None);
+    }
+
+    #[test]
+    fn test_scroll_by() {
+        assert_eq!(scroll_by(None, -1, 100, 20), Some(99));
+        assert_eq!(scroll_by(Some(99), -10, 100, 20), Some(89));
+        assert_eq!(scroll_by(Some(89), -100, 100, 20), Some(20));
+        assert_eq!(scroll_by(Some(20), 5, 100, 20), Some(25));
+        assert_eq!(scroll_by(Some(95), 10, 100, 20), None);
+        assert_eq!(scroll_by(None, 1, 100, 20), None);
+
+        // everything already fits on screen
+        assert_eq!(scroll_by(None, -1, 10, 20), None);
+    }
+
+    #[test]
+    fn test_compose_wrapping() {
+        // "> " and 8 graphemes in 5 columns: "> abc", "defgh" and the cursor after them
+        assert_eq!(compose_rows("> ", "abcdefgh", 5), 3);
+        assert_eq!(compose_rows("> ", "ab", 5), 1);
+
+        // a tab bar and a pin above, a wrapped compose line below
+        let layout = Layout::new(2, 2, 0, 10);
+        assert_eq!(layout.compose_top, 8);
+        assert_eq!((layout.messages_top, layout.message_rows), (2, 6));
+        assert_eq!(layout.first_message_row(3), 5);
+        assert_eq!(layout.first_message_row(20), 2);
+
+        let layout = Layout::new(2, 1, 3, 10);
+        assert_eq!((layout.messages_top, layout.message_rows), (6, 3));
+        assert_eq!(layout.first_message_row(1), 8);
+
+        assert_eq!(compose_position("> ", "abcdefgh", 0, 5), (0, 2));
+        assert_eq!(compose_position("> ", "abcdefgh", 3, 5), (1, 0));
+        assert_eq!(compose_position("> ", "abcdefgh", 8, 5), (2, 0));
+
+        assert_eq!(compose_column("> ", "abcdefgh", (1, 2), 5), 5);
+        // the prompt isn't part of the message
+        assert_eq!(compose_column("> ", "abcdefgh", (0, 1), 5), 0);
+        assert_eq!(compose_column("> ", "abcdefgh", (2, 4), 5), 8);
+
+        // "> 日" and "本語", the second wide character doesn't fit in the last cell of the row
+        assert_eq!(compose_rows("> ", "日本語", 5), 2);
+        assert_eq!(compose_position("> ", "日本語", 1, 5), (1, 0));
+        assert_eq!(compose_position("> ", "日本語", 3, 5), (1, 4));
+        // the second cell of a wide character is still that character
+        assert_eq!(compose_column("> ", "日本語", (1, 3), 5), 2);
+        assert_eq!(compose_column("> ", "日本語", (0, 4), 5), 0);
+    }
+
+    #[test]
+    fn test_emote_columns() {
+        let emotes = [
+            (String::from("25"), String::from("Kappa")),
+            (String::from("1"), String::from(":)")),
+        ];
+        assert_eq!(
+            emote_columns("foo: Kappa hi Kappa :)", &emotes),
+            [(5, 5, "25"), (14, 5, "25"), (20, 2, "1")]
+        );
+        // cut off at the end of the screen
+        assert!(emote_columns("foo: Kap", &emotes).is_empty());
+    }
+
+    #[test]
+    fn test_edited_message() {
+        assert_eq!(edited_message("hi chat\n").as_deref(), Some("hi chat"));
+        assert_eq!(
+            edited_message("first line\n\n  second line  \n").as_deref(),
+            Some("first line second line")
+        );
+        assert_eq!(edited_message("\n \n"), None);
+    }
+
+    #[test]
+    fn test_visual_selection() {
+        // 5 messages from message 10 on, drawn from row 3
+        assert_eq!(visual_selection(11, 6, 3, 10, 5), Some(1..=3));
+        assert_eq!(visual_selection(13, 4, 3, 10, 5), Some(1..=3));
+        // the anchor scrolled out of view at the top
+        assert_eq!(visual_selection(8, 5, 3, 10, 5), Some(0..=2));
+        // the cursor above the first message after it moved down
+        assert_eq!(visual_selection(12, 1, 3, 10, 5), Some(0..=2));
+        // chat was cleared
+        assert_eq!(visual_selection(12, 5, 3, 0, 0), None);
+        assert_eq!(visual_selection(12, 20, 3, 0, 2), None);
+    }
+
+    #[test]
+    fn test_next_question() {
+        let questions = || {
+            [
+                (Confirmation::Command, Some(String::from("ban foo? y/n"))),
+                (Confirmation::EmoteOnly, None),
+                (
+                    Confirmation::Split,
+                    Some(String::from("send as 2 messages? y/n")),
+                ),
+            ]
+        };
+
+        assert_eq!(
+            next_question(questions(), &[]),
+            Some((Confirmation::Command, String::from("ban foo? y/n")))
+        );
+        // yes to the first one still asks about splitting
+        assert_eq!(
+            next_question(questions(), &[Confirmation::Command]),
+            Some((Confirmation::Split, String::from("send as 2 messages? y/n")))
+        );
+        assert_eq!(
+            next_question(questions(), &[Confirmation::Command, Confirmation::Split]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_split_message() {
+        assert_eq!(split_message("hi  chat ", 500), ["hi  chat "]);
+
+        let pasted = pasted_text("first line\r\n\nsecond  line\n");
+        assert_eq!(pasted, "first line\u{2424}\u{2424}second  line");
+        assert_eq!(split_message(&pasted, 500), ["first line", "second line"]);
+
+        assert_eq!(
+            split_message("one two three four", 9),
+            ["one two", "three", "four"]
+        );
+        // counted in characters, a word longer than a message is cut
+        assert_eq!(split_message("ééééé ab", 4), ["éééé", "é ab"]);
+        assert_eq!(split_message("abcdefgh", 4), ["abcd", "efgh"]);
+        assert_eq!(split_message("ab c", 0), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_gift_bomb() {
+        let tags = |line: &str| twitcher::IRCMessage::parse(line).unwrap().tags;
+        let mut tab = Tab::new(String::from("bar"), 10);
+        tab.messages.clear();
+
+        tab.user_notice(&tags("@display-name=Foo;msg-id=submysterygift;msg-param-community-gift-id=1;msg-param-mass-gift-count=2;msg-param-sub-plan=1000 :tmi.twitch.tv USERNOTICE #bar"), None, true);
+        for name in ["a", "b"] {
+            tab.user_notice(&tags(&format!("@msg-id=subgift;msg-param-community-gift-id=1;system-msg=Foo\\sgifted\\sa\\ssub\\sto\\s{name} :tmi.twitch.tv USERNOTICE #bar")), None, true);
+        }
+        // not part of the bomb
+        tab.user_notice(
+            &tags(
+                "@msg-id=subgift;system-msg=Baz\\sgifted\\sa\\ssub :tmi.twitch.tv USERNOTICE #bar",
+            ),
+            None,
+            true,
+        );
+
+        let lines = |messages: Vec<&ChatLine>| {
+            messages
+                .iter()
+                .map(|line| line.message_line().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            lines(tab.messages.iter().collect()),
+            ["* Foo gifted 2 Tier 1 subs (+2)", "* Baz gifted a sub"]
+        );
+
+        let expanded = HashSet::from([String::from("1")]);
+        assert_eq!(
+            lines(with_gift_recipients(
+                tab.messages.iter().collect(),
+                &expanded
+            )),
+            [
+                "* Foo gifted 2 Tier 1 subs (+2)",
+                "*   Foo gifted a sub to a",
+                "*   Foo gifted a sub to b",
+                "* Baz gifted a sub"
+            ]
+        );
     }
 
     #[test]
-    fn test_prefix_parsing_with_nick_and_user() {
-        let message = "@badge-info=;badges=broadcaster/1;client-nonce=28e05b1c83f1e916ca1710c44b014515;color=#0000FF;display-name=foofoo;emotes=62835:0-10;first-msg=0;flags=;id=f80a19d6-e35a-4273-82d0-cd87f614e767;mod=0;room-id=713936733;subscriber=0;tmi-sent-ts=1642696567751;turbo=0;user-id=713936733;user-type= :foofoo!foofoo@foofoo.tmi.twitch.tv PRIVMSG #bar :bleedPurple";
+    fn test_user_notice_line() {
+        let tags = |line: &str| twitcher::IRCMessage::parse(line).unwrap().tags;
+
+        let announcement = tags("@display-name=Foo;msg-id=announcement;msg-param-color=GREEN :tmi.twitch.tv USERNOTICE #bar :hi all");
+        assert_eq!(
+            user_notice_line(&announcement, Some("hi all")),
+            Some((
+                String::from("[announcement] Foo: hi all"),
+                Some(style::Color::Green)
+            ))
+        );
+
+        let resub = tags("@msg-id=resub;system-msg=Foo\\ssubscribed\\sfor\\s3\\smonths. :tmi.twitch.tv USERNOTICE #bar :hype");
+        assert_eq!(
+            user_notice_line(&resub, Some("hype")),
+            Some((String::from("Foo subscribed for 3 months. hype"), None))
+        );
+
+        let resub = tags("@display-name=Foo;msg-id=resub;msg-param-sub-plan=2000;msg-param-cumulative-months=14;msg-param-should-share-streak=1;msg-param-streak-months=5;system-msg=Foo\\ssubscribed :tmi.twitch.tv USERNOTICE #bar");
+        assert_eq!(
+            user_notice_line(&resub, None),
+            Some((
+                String::from("Foo resubscribed (Tier 2) for 14 months, 5-month streak!"),
+                None
+            ))
+        );
+        let sub = tags("@login=foo;msg-id=sub;msg-param-sub-plan=Prime;msg-param-cumulative-months=1 :tmi.twitch.tv USERNOTICE #bar");
+        assert_eq!(
+            user_notice_line(&sub, Some("hi")),
+            Some((String::from("foo subscribed (Prime)! hi"), None))
+        );
+
+        assert_eq!(user_notice_line(&Tags::default(), None), None);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(byte_offset("héllo", 2), 3);
+        assert_eq!(byte_offset("héllo", 9), "héllo".len());
+
+        let ChatLine::Privmsg(message) = privmsg("Foo", "héllo") else {
+            unreachable!()
+        };
+        assert_eq!(message.message_line_len(), 10);
+        assert_eq!(message.visible_line(0, 7), "Foo: …");
+        assert_eq!(message.visible_line(2, 8), "…: héllo");
+        assert_eq!(message.visible_line(0, 20), "Foo: héllo");
+
+        assert_eq!(fit_line("hello there", 0, 20), "hello there");
+        assert_eq!(fit_line("hello there", 0, 5), "hell…");
+        assert_eq!(fit_line("hello there", 3, 5), "…o …");
+        assert_eq!(fit_line("hello there", 0, 0), "");
+        // cut before the word that doesn't fit
+        assert_eq!(fit_line("hello there friend", 0, 16), "hello there …");
+        assert_eq!(fit_line("hello there friend", 0, 13), "hello there …");
+        // the word ends right where the row does
+        assert_eq!(fit_line("hello there friend", 0, 12), "hello there…");
+        // a long word that would leave most of the row empty is cut anyway
+        assert_eq!(fit_line("a verylongword", 0, 10), "a verylon…");
+
+        assert_eq!(truncate("hello", 10), "hello");
+        assert_eq!(truncate("hello", 3), "hel");
+        assert_eq!(truncate("héllo", 2), "hé");
+
+        // wide characters take two cells
+        assert_eq!(truncate("日本語", 5), "日本");
+        assert_eq!(fit_line("日本語です", 0, 7), "日本語…");
+        assert_eq!(fit_line("日本語です", 1, 7), "… 語で…");
+        assert_eq!(fit_line("hi 日本語", 0, 8), "hi 日本…");
+    }
+
+    fn privmsg(name: &str, message: &str) -> ChatLine {
         let mut pos = 0;
-        let _ = Tags::parse(message, &mut pos).unwrap();
-        let prefix = Prefix::parse(message, &mut pos).unwrap();
+        let tags = Tags::parse(&format!("@display-name={name} "), &mut pos).unwrap();
+
+        ChatLine::Privmsg(Privmsg::new(
+            tags,
+            Prefix {
+                nick: Some(name.to_lowercase()),
+                user: Some(name.to_lowercase()),
+                host: format!("{}.tmi.twitch.tv", name.to_lowercase()),
+            },
+            String::from("bar"),
+            message.to_string(),
+        ))
+    }
 
-        eprintln!("{prefix:?}");
+    #[test]
+    fn test_grouped_line() {
+        let first = privmsg("Foo", "hello");
+        let second = privmsg("Foo", "again");
+        let other = privmsg("Baz", "hi");
 
-        assert_eq!(&message[pos..pos + 1], "P");
+        assert_eq!(grouped_line(&second, &first), "     again");
+        assert_eq!(
+            grouped_line(&second, &first).len(),
+            second.message_line().len()
+        );
+        assert_eq!(grouped_line(&other, &second), "Baz: hi");
+        assert_eq!(
+            grouped_line(
+                &ChatLine::System {
+                    channel: String::from("bar"),
+                    text: String::from("x"),
+                    color: None,
+                    timestamp: Local::now(),
+                },
+                &first
+            ),
+            "* x"
+        );
     }
 
     #[test]
-    fn test_command_parsing() {
-        let message = "@badge-info=;badges=broadcaster/1;client-nonce=28e05b1c83f1e916ca1710c44b014515;color=#0000FF;display-name=foofoo;emotes=62835:0-10;first-msg=0;flags=;id=f80a19d6-e35a-4273-82d0-cd87f614e767;mod=0;room-id=713936733;subscriber=0;tmi-sent-ts=1642696567751;turbo=0;user-id=713936733;user-type= :foofoo!foofoo@foofoo.tmi.twitch.tv PRIVMSG #bar :bleedPurple";
+    fn test_privmsg_tags() {
         let mut pos = 0;
-        let _ = Tags::parse(message, &mut pos).unwrap();
-        let _ = Prefix::parse(message, &mut pos).unwrap();
-        let command = IRCCommand::parse(message, &mut pos).unwrap();
+        let tags = Tags::parse(
+            "@color=#0000FF;display-name=;id=f80a19d6-e35a-4273-82d0-cd87f614e767 ",
+            &mut pos,
+        )
+        .unwrap();
+        let privmsg = Privmsg::new(
+            tags,
+            Prefix {
+                nick: Some(String::from("foo")),
+                user: Some(String::from("foo")),
+                host: String::from("foo.tmi.twitch.tv"),
+            },
+            String::from("bar"),
+            String::from("hello"),
+        );
 
-        eprintln!("{command:?}");
+        assert_eq!(privmsg.id(), Some("f80a19d6-e35a-4273-82d0-cd87f614e767"));
+        assert_eq!(privmsg.display_name(), "foo");
+        assert_eq!(
+            privmsg.color,
+            style::Color::Rgb {
+                r: 0,
+                g: 0,
+                b: 0xff
+            }
+        );
+
+        // no color chosen, like GLOBALUSERSTATE often says about us
+        let tags = Tags::parse("@color=;display-name=Foo ", &mut 0).unwrap();
+        assert_eq!(name_color(&tags, "foo"), hash_color("foo"));
+        assert_eq!(name_color(&Tags::default(), "foo"), hash_color("foo"));
+        assert_eq!(name_color(&Tags::default(), "Foo"), hash_color("foo"));
+
+        assert_eq!(parse_color(""), None);
+        assert_eq!(parse_color("#12345"), None);
+    }
+
+    #[test]
+    fn test_search_lines() {
+        let tags = Tags::parse(
+            "@badges=moderator/1,subscriber/12;display-name=Foo ",
+            &mut 0,
+        )
+        .unwrap();
+        let prefix = Prefix {
+            nick: Some(String::from("foo")),
+            user: Some(String::from("foo")),
+            host: String::from("foo.tmi.twitch.tv"),
+        };
+        let privmsg = Privmsg::new(tags, prefix, String::from("bar"), String::from("hi chat"));
+        assert_eq!(privmsg.badges, ["moderator", "subscriber"]);
+
+        let line = ChatLine::Privmsg(privmsg);
+        let matches = |line: &ChatLine, query: &str| line.matches(&Query::parse(query).unwrap());
+        assert!(matches(&line, "from:foo badge:moderator"));
+        assert!(matches(&line, "CHAT"));
+        assert!(!matches(&line, "badge:vip"));
+
+        let notice = ChatLine::System {
+            channel: String::from("bar"),
+            text: String::from("raid incoming"),
+            color: None,
+            timestamp: Local::now(),
+        };
+        assert!(matches(&notice, "raid"));
+        assert!(!matches(&notice, "from:foo"));
+        assert!(!matches(&ChatLine::Spacer, "-raid"));
+    }
+
+    #[test]
+    fn test_quote_format() {
+        let mut pos = 0;
+        let tags = Tags::parse("@display-name=Foo;tmi-sent-ts=1642696567751 ", &mut pos).unwrap();
+        let privmsg = Privmsg::new(
+            tags,
+            Prefix {
+                nick: Some(String::from("foo")),
+                user: Some(String::from("foo")),
+                host: String::from("foo.tmi.twitch.tv"),
+            },
+            String::from("bar"),
+            String::from("hello"),
+        );
 
-        assert!(false);
+        assert_eq!(
+            privmsg.quote("<{name} in #{channel}> {message}"),
+            "<Foo in #bar> hello"
+        );
+        assert_eq!(privmsg.timestamp.timestamp_millis(), 1642696567751);
     }
 }
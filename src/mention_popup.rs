@@ -0,0 +1,119 @@
+//! The list of recent chatters that pops up while typing an `@mention`
+
+/// Names shown at once, the best matches
+pub const MAX_SHOWN: usize = 8;
+
+/// A popup that's open for the mention starting at `start`
+pub struct MentionPopup {
+    /// byte offset of the `@` in the compose line
+    start: usize,
+    pub selected: usize,
+}
+
+impl MentionPopup {
+    pub fn new(start: usize) -> Self {
+        Self { start, selected: 0 }
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// What's typed after the `@` up to byte `cursor`, `None` once the cursor isn't in the
+    /// mention anymore
+    pub fn query<'a>(&self, line: &'a str, cursor: usize) -> Option<&'a str> {
+        let query = line.get(self.start..cursor)?.strip_prefix('@')?;
+        (!query.contains(char::is_whitespace)).then_some(query)
+    }
+
+    pub fn select(&mut self, down: bool, candidates: usize) {
+        self.selected = match down {
+            true => (self.selected + 1).min(candidates.saturating_sub(1)),
+            false => self.selected.saturating_sub(1),
+        };
+    }
+
+    /// Replaces the mention up to byte `cursor` with `@name `, returns the byte offset after it
+    pub fn insert(&self, line: &mut String, cursor: usize, name: &str) -> usize {
+        let mention = format!("@{name} ");
+        line.replace_range(self.start..cursor, &mention);
+        self.start + mention.len()
+    }
+}
+
+/// The names `query` matches, best first, `names` is expected most recent first which is
+/// kept for names that match equally well
+pub fn candidates<'a>(query: &str, names: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut candidates = names
+        .into_iter()
+        .filter_map(|name| Some((fuzzy_score(query, name)?, name)))
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|(score, _)| *score);
+
+    candidates
+        .into_iter()
+        .take(MAX_SHOWN)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// Whether the letters of `query` appear in `name` in order, ignoring case, lower is a better
+/// match: names starting with the query come first, then the ones with the fewest letters
+/// between the matched ones
+fn fuzzy_score(query: &str, name: &str) -> Option<usize> {
+    let name = name.to_lowercase();
+    let mut chars = name.chars().enumerate();
+    let mut gaps = 0;
+    let mut first = None;
+
+    for q in query.to_lowercase().chars() {
+        let (skipped, (position, _)) = chars.by_ref().enumerate().find(|(_, (_, c))| *c == q)?;
+        first.get_or_insert(position);
+        if first != Some(position) {
+            gaps += skipped;
+        }
+    }
+
+    let prefix = first.is_none_or(|first| first == 0);
+    Some(if prefix { gaps } else { 1000 + gaps })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidates() {
+        let names = ["xqc", "forsen", "nymn", "fo_rsen_fan", "sodapoppin"];
+
+        assert_eq!(candidates("fo", names), ["forsen", "fo_rsen_fan"]);
+        assert_eq!(candidates("frsn", names), ["forsen", "fo_rsen_fan"]);
+        assert_eq!(candidates("SEN", names), ["forsen", "fo_rsen_fan"]);
+        assert_eq!(candidates("pop", names), ["sodapoppin"]);
+        assert!(candidates("zzz", names).is_empty());
+
+        // nothing typed yet, the most recent chatters
+        assert_eq!(candidates("", names), names);
+    }
+
+    #[test]
+    fn test_mention_popup() {
+        let mut line = String::from("hi @fors");
+        let popup = MentionPopup::new(3);
+        assert_eq!(popup.query(&line, line.len()), Some("fors"));
+        assert_eq!(popup.query(&line, 3), None);
+        assert_eq!(popup.query("hi @fo rs", 9), None);
+
+        let cursor = popup.insert(&mut line, 8, "forsen");
+        assert_eq!(line, "hi @forsen ");
+        assert_eq!(cursor, line.len());
+
+        let mut popup = MentionPopup::new(0);
+        popup.select(true, 2);
+        popup.select(true, 2);
+        assert_eq!(popup.selected, 1);
+        popup.select(false, 2);
+        popup.select(false, 2);
+        assert_eq!(popup.selected, 0);
+    }
+}
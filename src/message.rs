@@ -0,0 +1,1017 @@
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tags(HashMap<String, String>);
+
+impl Tags {
+    pub fn get(&self, tag: &str) -> Option<&String> {
+        self.0.get(tag)
+    }
+
+    /// Every tag and its unescaped value, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// `msg-param-<name>`, the details of a USERNOTICE
+    pub fn msg_param(&self, name: &str) -> Option<&str> {
+        self.get(&format!("msg-param-{name}")).map(String::as_str)
+    }
+
+    pub fn sub_plan(&self) -> Option<SubPlan> {
+        SubPlan::parse(self.msg_param("sub-plan")?)
+    }
+
+    /// Months subscribed in total, counting this one
+    pub fn cumulative_months(&self) -> Option<u32> {
+        self.msg_param("cumulative-months")?.parse().ok()
+    }
+
+    /// Months subscribed in a row, `None` if the subscriber doesn't share it
+    pub fn streak_months(&self) -> Option<u32> {
+        if self.msg_param("should-share-streak") == Some("0") {
+            return None;
+        }
+
+        self.msg_param("streak-months")?
+            .parse()
+            .ok()
+            .filter(|months| *months > 0)
+    }
+
+    /// Shared by a gift bomb's `submysterygift` and the `subgift` for each of its recipients
+    pub fn community_gift_id(&self) -> Option<&str> {
+        self.msg_param("community-gift-id")
+    }
+
+    /// How many subs a `submysterygift` gives away
+    pub fn mass_gift_count(&self) -> Option<u32> {
+        self.msg_param("mass-gift-count")?.parse().ok()
+    }
+
+    /// The sub a `sub` or `resub` USERNOTICE is about
+    pub fn sub(&self) -> Option<Sub> {
+        let resub = match self.get("msg-id")?.as_str() {
+            "sub" => false,
+            "resub" => true,
+            _ => return None,
+        };
+
+        Some(Sub {
+            resub,
+            plan: self.sub_plan()?,
+            cumulative_months: self.cumulative_months(),
+            streak_months: self.streak_months(),
+        })
+    }
+
+    pub fn parse(raw_message: &str, pos: &mut usize) -> Option<Self> {
+        if raw_message[*pos..].starts_with('@') {
+            if let Some(space_index) = raw_message[*pos..].find(' ') {
+                let mut map = HashMap::new();
+
+                // skip the leading `@`
+                let message = &raw_message[*pos + 1..*pos + space_index];
+                for tag in message.split(';').filter(|tag| !tag.is_empty()) {
+                    // IRCv3 allows a tag without a value, which means the same as an empty one
+                    let (key, value) = tag.split_once('=').unwrap_or((tag, ""));
+
+                    map.insert(key.to_string(), unescape_tag_value(value));
+                }
+
+                *pos += space_index + 1;
+
+                return Some(Self(map));
+            }
+        }
+
+        None
+    }
+
+    /// The tags as they're sent, `@key=value;...` with values escaped again, sorted by key so
+    /// the same tags always give the same line. Empty if there are none
+    pub fn to_irc_line(&self) -> String {
+        if self.0.is_empty() {
+            return String::new();
+        }
+
+        let mut tags = self.0.iter().collect::<Vec<_>>();
+        tags.sort();
+
+        let tags = tags
+            .into_iter()
+            .map(|(key, value)| format!("{key}={}", escape_tag_value(value)))
+            .collect::<Vec<_>>();
+        format!("@{}", tags.join(";"))
+    }
+}
+
+/// What a sub costs, from `msg-param-sub-plan`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubPlan {
+    /// free with amazon prime, the same as tier 1 otherwise
+    Prime,
+    Tier1,
+    Tier2,
+    Tier3,
+}
+
+impl SubPlan {
+    pub fn parse(plan: &str) -> Option<Self> {
+        match plan {
+            "Prime" => Some(Self::Prime),
+            "1000" => Some(Self::Tier1),
+            "2000" => Some(Self::Tier2),
+            "3000" => Some(Self::Tier3),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for SubPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Prime => "Prime",
+            Self::Tier1 => "Tier 1",
+            Self::Tier2 => "Tier 2",
+            Self::Tier3 => "Tier 3",
+        })
+    }
+}
+
+/// A sub or resub, see [`Tags::sub`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sub {
+    pub resub: bool,
+    pub plan: SubPlan,
+    pub cumulative_months: Option<u32>,
+    pub streak_months: Option<u32>,
+}
+
+/// The other way around from [`unescape_tag_value`]
+fn escape_tag_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ';' => escaped.push_str("\\:"),
+            ' ' => escaped.push_str("\\s"),
+            '\\' => escaped.push_str("\\\\"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Tag values can't contain spaces or `;`, so they're escaped, e.g. in `system-msg`
+fn unescape_tag_value(value: &str) -> String {
+    if !value.contains('\\') {
+        return value.to_string();
+    }
+
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            // a trailing backslash is dropped
+            None => {}
+        }
+    }
+
+    unescaped
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Prefix {
+    pub nick: Option<String>,
+    pub user: Option<String>,
+    pub host: String,
+}
+
+impl Prefix {
+    pub fn parse(raw_message: &str, pos: &mut usize) -> Option<Self> {
+        if raw_message[*pos..].starts_with(':') {
+            let end_index = raw_message[*pos..].find(' ')?;
+            // only look for `!` and `@` inside the prefix, the rest of the line can contain them too
+            let prefix = &raw_message[*pos + 1..*pos + end_index];
+
+            let prefix = if let Some((nick, user_and_host)) = prefix.split_once('!') {
+                let (user, host) = user_and_host.split_once('@')?;
+
+                Self {
+                    nick: Some(nick.to_string()),
+                    user: Some(user.to_string()),
+                    host: host.to_string(),
+                }
+            } else {
+                Self {
+                    nick: None,
+                    user: None,
+                    host: prefix.to_string(),
+                }
+            };
+
+            *pos += end_index + 1;
+
+            return Some(prefix);
+        }
+
+        None
+    }
+
+    /// `:nick!user@host` or `:host`, empty for messages without a prefix
+    pub fn to_irc_line(&self) -> String {
+        match (&self.nick, &self.user) {
+            (Some(nick), Some(user)) => format!(":{nick}!{user}@{}", self.host),
+            _ if self.host.is_empty() => String::new(),
+            _ => format!(":{}", self.host),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct IRCMessage {
+    pub tags: Tags,
+    pub prefix: Prefix,
+    pub command: IRCCommand,
+}
+
+impl IRCMessage {
+    pub fn parse(raw_message: &str) -> Option<Self> {
+        // lines come in with their `\r\n`, which shouldn't end up in the last parameter
+        let raw_message = raw_message.trim_end_matches(['\r', '\n']);
+        let mut pos = 0;
+
+        let tags = Tags::parse(raw_message, &mut pos).unwrap_or_default();
+        // server messages like PING don't have a prefix
+        let prefix = Prefix::parse(raw_message, &mut pos).unwrap_or_default();
+        let command = IRCCommand::parse(raw_message, &mut pos)?;
+
+        Some(Self {
+            tags,
+            prefix,
+            command,
+        })
+    }
+
+    pub fn tags(&self) -> &Tags {
+        &self.tags
+    }
+
+    /// The channel it's about, `None` for commands that aren't about one
+    pub fn channel(&self) -> Option<&str> {
+        match &self.command {
+            IRCCommand::Privmsg { channel, .. }
+            | IRCCommand::UserState { channel }
+            | IRCCommand::HostTarget { channel, .. }
+            | IRCCommand::Join { channel }
+            | IRCCommand::RoomState { channel }
+            | IRCCommand::ClearMsg { channel, .. }
+            | IRCCommand::ClearChat { channel, .. }
+            | IRCCommand::UserNotice { channel, .. } => Some(channel),
+            IRCCommand::Notice { channel, .. } => channel.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Who sent it as they want to be called, their `display-name` or else their login from the
+    /// prefix, `None` for what the server sends itself
+    pub fn sender(&self) -> Option<&str> {
+        self.tags
+            .get("display-name")
+            .map(String::as_str)
+            .filter(|name| !name.is_empty())
+            .or(self.prefix.nick.as_deref())
+    }
+
+    /// The text of a chat message, notice or what someone added to a sub, `None` for commands
+    /// without any
+    pub fn message(&self) -> Option<&str> {
+        match &self.command {
+            IRCCommand::Privmsg { message, .. }
+            | IRCCommand::ClearMsg { message, .. }
+            | IRCCommand::Notice { message, .. } => Some(message),
+            IRCCommand::UserNotice { message, .. } => message.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The message as an IRC line without the `\r\n`, [`IRCMessage::parse`] gives back the
+    /// same message from it
+    pub fn to_irc_line(&self) -> String {
+        [
+            self.tags.to_irc_line(),
+            self.prefix.to_irc_line(),
+            self.command.to_irc_line(),
+        ]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+    }
+}
+
+/// For logs, chat messages like `#channel <name> text` and everything else as its IRC line
+/// without the tags
+impl std::fmt::Display for IRCMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let IRCCommand::Privmsg { channel, message } = &self.command {
+            let sender = self.sender().unwrap_or(&self.prefix.host);
+            return write!(f, "#{channel} <{sender}> {message}");
+        }
+
+        match self.prefix.to_irc_line() {
+            prefix if prefix.is_empty() => write!(f, "{}", self.command.to_irc_line()),
+            prefix => write!(f, "{prefix} {}", self.command.to_irc_line()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum IRCCommand {
+    Privmsg {
+        channel: String,
+        message: String,
+    },
+    GlobalUserState,
+    /// our own state in a channel, sent on join and after every message we send
+    UserState {
+        channel: String,
+    },
+    /// `target` is `None` when the channel stops hosting
+    HostTarget {
+        channel: String,
+        target: Option<String>,
+        viewers: Option<u32>,
+    },
+    /// someone joined, with the membership capability we also see everyone else's joins
+    Join {
+        channel: String,
+    },
+    /// the channel's settings, sent when we join and when they change, twitch only sends it for
+    /// channels that exist
+    RoomState {
+        channel: String,
+    },
+    /// a moderator deleted a single message, its id is in the `target-msg-id` tag
+    ClearMsg {
+        channel: String,
+        message: String,
+    },
+    /// `user` was timed out or banned, or the whole chat was cleared if there's no user
+    ClearChat {
+        channel: String,
+        user: Option<String>,
+    },
+    /// subs, raids, announcements and other events, described by the `msg-id` and `system-msg`
+    /// tags, `message` is what the user added to it if anything
+    UserNotice {
+        channel: String,
+        message: Option<String>,
+    },
+    /// twitch telling us something, like that a command worked or why it didn't, described by
+    /// the `msg-id` tag. `channel` is `None` for notices that aren't about one, like a failed
+    /// login
+    Notice {
+        channel: Option<String>,
+        message: String,
+    },
+    /// RPL_WELCOME, the first thing twitch sends once it accepted the login, `nick` is who
+    /// we're logged in as
+    Welcome {
+        nick: String,
+        message: String,
+    },
+    /// the rest of what twitch sends after logging in, 002 to 004 and the message of the day
+    /// (375, 372 and 376), there's nothing in them worth showing
+    Numeric {
+        code: u16,
+        nick: String,
+        message: String,
+    },
+    /// twitch is about to close the connection for maintenance, connecting again gets another
+    /// server
+    Reconnect,
+    Unknown(String),
+    CapAck,
+    /// twitch refused the request, it's all or nothing so none of `capabilities` were enabled
+    CapNak {
+        capabilities: Vec<String>,
+    },
+    Ping,
+    /// the answer to a PING we sent, with the token it carried
+    Pong {
+        token: String,
+    },
+}
+
+impl IRCCommand {
+    pub fn parse(raw_message: &str, pos: &mut usize) -> Option<Self> {
+        if let Some(privmsg) = raw_message[*pos..].strip_prefix("PRIVMSG ") {
+            let (channel, message) = privmsg.split_once(' ')?;
+
+            return Some(IRCCommand::Privmsg {
+                channel: channel.trim_start_matches('#').to_string(),
+                message: message.strip_prefix(':').unwrap_or(message).to_string(),
+            });
+        }
+
+        if let Some(host_target) = raw_message[*pos..].strip_prefix("HOSTTARGET ") {
+            let (channel, parameters) = host_target.split_once(" :")?;
+            let mut parameters = parameters.split_whitespace();
+
+            return Some(IRCCommand::HostTarget {
+                channel: channel.trim_start_matches('#').to_string(),
+                target: parameters
+                    .next()
+                    .filter(|target| *target != "-")
+                    .map(str::to_string),
+                viewers: parameters.next().and_then(|viewers| viewers.parse().ok()),
+            });
+        }
+
+        if let Some(user_state) = raw_message[*pos..].strip_prefix("USERSTATE ") {
+            return Some(IRCCommand::UserState {
+                channel: user_state.trim_end().trim_start_matches('#').to_string(),
+            });
+        }
+
+        if let Some(join) = raw_message[*pos..].strip_prefix("JOIN ") {
+            return Some(IRCCommand::Join {
+                channel: join.trim_end().trim_start_matches('#').to_string(),
+            });
+        }
+
+        if let Some(room_state) = raw_message[*pos..].strip_prefix("ROOMSTATE ") {
+            return Some(IRCCommand::RoomState {
+                channel: room_state.trim_end().trim_start_matches('#').to_string(),
+            });
+        }
+
+        if let Some(clear_msg) = raw_message[*pos..].strip_prefix("CLEARMSG ") {
+            let (channel, message) = clear_msg.split_once(" :")?;
+
+            return Some(IRCCommand::ClearMsg {
+                channel: channel.trim_start_matches('#').to_string(),
+                message: message.to_string(),
+            });
+        }
+
+        if let Some(clear_chat) = raw_message[*pos..].strip_prefix("CLEARCHAT ") {
+            let (channel, user) = match clear_chat.split_once(" :") {
+                Some((channel, user)) => (channel, Some(user.to_string())),
+                None => (clear_chat.trim_end(), None),
+            };
+
+            return Some(IRCCommand::ClearChat {
+                channel: channel.trim_start_matches('#').to_string(),
+                user,
+            });
+        }
+
+        if let Some(user_notice) = raw_message[*pos..].strip_prefix("USERNOTICE ") {
+            let (channel, message) = match user_notice.split_once(" :") {
+                Some((channel, message)) => (channel, Some(message.to_string())),
+                None => (user_notice.trim_end(), None),
+            };
+
+            return Some(IRCCommand::UserNotice {
+                channel: channel.trim_start_matches('#').to_string(),
+                message,
+            });
+        }
+
+        if let Some(notice) = raw_message[*pos..].strip_prefix("NOTICE ") {
+            let (channel, message) = notice.split_once(" :").unwrap_or((notice, ""));
+            let channel = channel.trim_end();
+
+            return Some(IRCCommand::Notice {
+                channel: (channel != "*").then(|| channel.trim_start_matches('#').to_string()),
+                message: message.trim_end().to_string(),
+            });
+        }
+
+        if let Some((code, parameters)) = raw_message[*pos..].split_once(' ') {
+            if matches!(code, "001" | "002" | "003" | "004" | "372" | "375" | "376") {
+                let (nick, message) = parameters.split_once(" :").unwrap_or((parameters, ""));
+                let (nick, message) = (nick.to_string(), message.to_string());
+
+                return Some(match code.parse().ok()? {
+                    1 => IRCCommand::Welcome { nick, message },
+                    code => IRCCommand::Numeric {
+                        code,
+                        nick,
+                        message,
+                    },
+                });
+            }
+        }
+
+        if raw_message[*pos..].starts_with("RECONNECT") {
+            return Some(IRCCommand::Reconnect);
+        }
+
+        if raw_message[*pos..].starts_with("GLOBALUSERSTATE") {
+            return Some(IRCCommand::GlobalUserState);
+        }
+
+        if raw_message[*pos..].starts_with("CAP * ACK") {
+            return Some(IRCCommand::CapAck);
+        }
+
+        if let Some(nak) = raw_message[*pos..].strip_prefix("CAP * NAK") {
+            let capabilities = nak.trim_start().trim_start_matches(':');
+
+            return Some(IRCCommand::CapNak {
+                capabilities: capabilities.split_whitespace().map(String::from).collect(),
+            });
+        }
+
+        if raw_message[*pos..].starts_with("PING :tmi.twitch.tv") {
+            return Some(IRCCommand::Ping);
+        }
+
+        if let Some(pong) = raw_message[*pos..].strip_prefix("PONG ") {
+            // `PONG tmi.twitch.tv :<token>`
+            let token = pong.split_once(" :").map_or(pong, |(_, token)| token);
+
+            return Some(IRCCommand::Pong {
+                token: token.to_string(),
+            });
+        }
+
+        Some(IRCCommand::Unknown(
+            raw_message[*pos..raw_message.len()].to_string(),
+        ))
+    }
+
+    /// The command and its parameters as they're sent, an [`IRCCommand::Unknown`] is the raw
+    /// text it was parsed from
+    pub fn to_irc_line(&self) -> String {
+        match self {
+            Self::Privmsg { channel, message } => format!("PRIVMSG #{channel} :{message}"),
+            Self::GlobalUserState => String::from("GLOBALUSERSTATE"),
+            Self::UserState { channel } => format!("USERSTATE #{channel}"),
+            Self::HostTarget {
+                channel,
+                target,
+                viewers,
+            } => {
+                let target = target.as_deref().unwrap_or("-");
+                match viewers {
+                    Some(viewers) => format!("HOSTTARGET #{channel} :{target} {viewers}"),
+                    None => format!("HOSTTARGET #{channel} :{target}"),
+                }
+            }
+            Self::Join { channel } => format!("JOIN #{channel}"),
+            Self::RoomState { channel } => format!("ROOMSTATE #{channel}"),
+            Self::ClearMsg { channel, message } => format!("CLEARMSG #{channel} :{message}"),
+            Self::ClearChat { channel, user } => match user {
+                Some(user) => format!("CLEARCHAT #{channel} :{user}"),
+                None => format!("CLEARCHAT #{channel}"),
+            },
+            Self::UserNotice { channel, message } => match message {
+                Some(message) => format!("USERNOTICE #{channel} :{message}"),
+                None => format!("USERNOTICE #{channel}"),
+            },
+            Self::Notice { channel, message } => match channel {
+                Some(channel) => format!("NOTICE #{channel} :{message}"),
+                None => format!("NOTICE * :{message}"),
+            },
+            Self::Welcome { nick, message } => format!("001 {nick} :{message}"),
+            Self::Numeric {
+                code,
+                nick,
+                message,
+            } => format!("{code:03} {nick} :{message}"),
+            Self::Reconnect => String::from("RECONNECT"),
+            Self::Unknown(raw) => raw.clone(),
+            Self::CapAck => String::from("CAP * ACK"),
+            Self::CapNak { capabilities } => format!("CAP * NAK :{}", capabilities.join(" ")),
+            Self::Ping => String::from("PING :tmi.twitch.tv"),
+            Self::Pong { token } => format!("PONG tmi.twitch.tv :{token}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags_parsing() {
+        let message = "@badge-info=;badges=moderator/1;color=;display-name=bar;emote-sets=0,300374282;mod=1;subscriber=0;user-type=mod :tmi.twitch.tv USERSTATE #foo";
+        let mut pos = 0;
+        let tags = Tags::parse(message, &mut pos).unwrap();
+
+        eprintln!("{tags:?}");
+
+        assert_eq!(&message[pos - 1..pos], " ");
+
+        assert_eq!(pos, 112);
+    }
+
+    #[test]
+    fn test_prefix_parsing() {
+        let message = "@badge-info=;badges=moderator/1;color=;display-name=bar;emote-sets=0,300374282;mod=1;subscriber=0;user-type=mod :tmi.twitch.tv USERSTATE #foo";
+        let mut pos = 0;
+        let _ = Tags::parse(message, &mut pos).unwrap();
+        let prefix = Prefix::parse(message, &mut pos).unwrap();
+
+        eprintln!("{prefix:?}");
+
+        assert_eq!(&message[pos..pos + 1], "U");
+    }
+
+    #[test]
+    fn test_prefix_parsing_with_nick_and_user() {
+        let message = "@badge-info=;badges=broadcaster/1;client-nonce=28e05b1c83f1e916ca1710c44b014515;color=#0000FF;display-name=foofoo;emotes=62835:0-10;first-msg=0;flags=;id=f80a19d6-e35a-4273-82d0-cd87f614e767;mod=0;room-id=713936733;subscriber=0;tmi-sent-ts=1642696567751;turbo=0;user-id=713936733;user-type= :foofoo!foofoo@foofoo.tmi.twitch.tv PRIVMSG #bar :bleedPurple";
+        let mut pos = 0;
+        let _ = Tags::parse(message, &mut pos).unwrap();
+        let prefix = Prefix::parse(message, &mut pos).unwrap();
+
+        eprintln!("{prefix:?}");
+
+        assert_eq!(&message[pos..pos + 1], "P");
+    }
+
+    #[test]
+    fn test_command_parsing() {
+        // with the line ending, which the reader strips but other callers might not
+        let message = "@badge-info=;badges=broadcaster/1;client-nonce=28e05b1c83f1e916ca1710c44b014515;color=#0000FF;display-name=foofoo;emotes=62835:0-10;first-msg=0;flags=;id=f80a19d6-e35a-4273-82d0-cd87f614e767;mod=0;room-id=713936733;subscriber=0;tmi-sent-ts=1642696567751;turbo=0;user-id=713936733;user-type= :foofoo!foofoo@foofoo.tmi.twitch.tv PRIVMSG #bar :bleedPurple\r\n";
+        let command = IRCMessage::parse(message).unwrap().command;
+
+        let IRCCommand::Privmsg { channel, message } = command else {
+            panic!("expected PRIVMSG, got {command:?}");
+        };
+
+        assert_eq!(channel, "bar");
+        assert_eq!(message, "bleedPurple");
+
+        let message = ":foofoo!foofoo@foofoo.tmi.twitch.tv PRIVMSG #bar :time: 12:30 :)\r\n";
+        let command = IRCMessage::parse(message).unwrap().command;
+
+        let IRCCommand::Privmsg { channel, message } = command else {
+            panic!("expected PRIVMSG, got {command:?}");
+        };
+
+        assert_eq!(channel, "bar");
+        assert_eq!(message, "time: 12:30 :)");
+    }
+
+    #[test]
+    fn test_full_privmsg_parsing() {
+        let message = parse_line("@badge-info=;badges=broadcaster/1;client-nonce=28e05b1c83f1e916ca1710c44b014515;color=#0000FF;display-name=foofoo;emotes=62835:0-10;first-msg=0;flags=;id=f80a19d6-e35a-4273-82d0-cd87f614e767;mod=0;room-id=713936733;subscriber=0;tmi-sent-ts=1642696567751;turbo=0;user-id=713936733;user-type= :foofoo!foofoo@foofoo.tmi.twitch.tv PRIVMSG #bar :bleedPurple");
+
+        assert_eq!(message.tags.get("display-name").unwrap(), "foofoo");
+        assert_eq!(message.tags.get("color").unwrap(), "#0000FF");
+        assert_eq!(message.tags.get("badges").unwrap(), "broadcaster/1");
+        assert_eq!(message.tags.get("emotes").unwrap(), "62835:0-10");
+        assert_eq!(message.tags.get("badge-info").unwrap(), "");
+        assert_eq!(message.tags.get("tmi-sent-ts").unwrap(), "1642696567751");
+
+        assert_eq!(message.prefix.nick.as_deref(), Some("foofoo"));
+        assert_eq!(message.prefix.user.as_deref(), Some("foofoo"));
+        assert_eq!(message.prefix.host, "foofoo.tmi.twitch.tv");
+
+        let IRCCommand::Privmsg { channel, message } = message.command else {
+            panic!("expected PRIVMSG, got {:?}", message.command);
+        };
+
+        assert_eq!(channel, "bar");
+        assert_eq!(message, "bleedPurple");
+    }
+
+    #[test]
+    fn test_valueless_tags_parsing() {
+        let message =
+            parse_line("@foo;bar=1;;baz= :foofoo!foofoo@foofoo.tmi.twitch.tv PRIVMSG #bar :hi");
+
+        assert_eq!(message.tags.get("foo").unwrap(), "");
+        assert_eq!(message.tags.get("bar").unwrap(), "1");
+        assert_eq!(message.tags.get("baz").unwrap(), "");
+        assert_eq!(message.tags.iter().count(), 3);
+        assert_eq!(message.message(), Some("hi"));
+    }
+
+    #[test]
+    fn test_server_prefix_with_bang_in_message() {
+        let message = parse_line(":tmi.twitch.tv PRIVMSG #bar :hello there! me@example.com");
+
+        assert_eq!(message.prefix.nick, None);
+        assert_eq!(message.prefix.user, None);
+        assert_eq!(message.prefix.host, "tmi.twitch.tv");
+        assert!(matches!(
+            message.command,
+            IRCCommand::Privmsg { ref message, .. } if message == "hello there! me@example.com"
+        ));
+    }
+
+    #[test]
+    fn test_to_irc_line() {
+        let lines = [
+            "@badge-info=;badges=broadcaster/1;color=#0000FF;display-name=foofoo;emotes=62835:0-10 :foofoo!foofoo@foofoo.tmi.twitch.tv PRIVMSG #bar :bleedPurple :)",
+            ":tmi.twitch.tv PRIVMSG #bar :hello there! me@example.com",
+            "@emote-sets=0 :tmi.twitch.tv GLOBALUSERSTATE",
+            "@mod=1 :tmi.twitch.tv USERSTATE #bar",
+            ":tmi.twitch.tv HOSTTARGET #abc :xyz 10",
+            ":tmi.twitch.tv HOSTTARGET #abc :- 0",
+            ":tmi.twitch.tv HOSTTARGET #abc :xyz",
+            ":foo!foo@foo.tmi.twitch.tv JOIN #bar",
+            "@emote-only=0;slow=10 :tmi.twitch.tv ROOMSTATE #bar",
+            "@login=ronni;target-msg-id=abc-123 :tmi.twitch.tv CLEARMSG #dallas :HeyGuys",
+            "@ban-duration=350 :tmi.twitch.tv CLEARCHAT #dallas :ronni",
+            ":tmi.twitch.tv CLEARCHAT #dallas",
+            "@msg-id=resub;system-msg=ronni\\shas\\ssubscribed\\sfor\\s6\\smonths! :tmi.twitch.tv USERNOTICE #dallas :Great stream",
+            "@msg-id=raid :tmi.twitch.tv USERNOTICE #dallas",
+            "@msg-id=msg_ratelimit :tmi.twitch.tv NOTICE #dallas :You are sending messages too quickly.",
+            ":tmi.twitch.tv NOTICE * :Login authentication failed",
+            ":tmi.twitch.tv 001 justinfan123 :Welcome, GLHF!",
+            ":tmi.twitch.tv 372 justinfan123 :You are in a maze of twisty passages, all alike.",
+            ":tmi.twitch.tv 421 justinfan123 WHO :Unknown command",
+            ":tmi.twitch.tv CAP * ACK :twitch.tv/tags twitch.tv/commands",
+            ":tmi.twitch.tv CAP * NAK :twitch.tv/foo twitch.tv/bar",
+            "PING :tmi.twitch.tv",
+            ":tmi.twitch.tv RECONNECT",
+            ":tmi.twitch.tv PONG tmi.twitch.tv :twitcher-1",
+        ];
+
+        for line in lines {
+            let message = parse_line(line);
+            assert_eq!(parse_line(&message.to_irc_line()), message, "{line}");
+        }
+
+        // tag values are escaped again
+        let message = parse_line("@system-msg=a\\sb\\:c\\\\d\\r\\n :tmi.twitch.tv USERNOTICE #bar");
+        assert_eq!(message.tags.get("system-msg").unwrap(), "a b;c\\d\r\n");
+        assert_eq!(
+            message.to_irc_line(),
+            "@system-msg=a\\sb\\:c\\\\d\\r\\n :tmi.twitch.tv USERNOTICE #bar"
+        );
+
+        let message = IRCMessage {
+            tags: Tags::default(),
+            prefix: Prefix::default(),
+            command: IRCCommand::Privmsg {
+                channel: String::from("bar"),
+                message: String::from("hi chat"),
+            },
+        };
+        assert_eq!(message.to_irc_line(), "PRIVMSG #bar :hi chat");
+    }
+
+    #[test]
+    fn test_accessors_and_display() {
+        let message = parse_line("@color=#0000FF;display-name=FooFoo :foofoo!foofoo@foofoo.tmi.twitch.tv PRIVMSG #bar :bleedPurple");
+        assert_eq!(message.channel(), Some("bar"));
+        assert_eq!(message.sender(), Some("FooFoo"));
+        assert_eq!(message.message(), Some("bleedPurple"));
+        assert_eq!(message.tags().get("color").unwrap(), "#0000FF");
+        assert_eq!(message.to_string(), "#bar <FooFoo> bleedPurple");
+
+        let message = parse_line(":foo!foo@foo.tmi.twitch.tv JOIN #bar");
+        assert_eq!(message.sender(), Some("foo"));
+        assert_eq!(message.message(), None);
+        assert_eq!(message.to_string(), ":foo!foo@foo.tmi.twitch.tv JOIN #bar");
+
+        let message = parse_line("@msg-id=no_permission :tmi.twitch.tv NOTICE * :nope");
+        assert_eq!(message.channel(), None);
+        assert_eq!(message.sender(), None);
+        assert_eq!(message.message(), Some("nope"));
+        assert_eq!(message.to_string(), ":tmi.twitch.tv NOTICE * :nope");
+
+        assert_eq!(
+            parse_line("PING :tmi.twitch.tv").to_string(),
+            "PING :tmi.twitch.tv"
+        );
+
+        let mut tags = message.tags().iter().collect::<Vec<_>>();
+        tags.sort();
+        assert_eq!(tags, [("msg-id", "no_permission")]);
+    }
+
+    fn parse_line(line: &str) -> IRCMessage {
+        IRCMessage::parse(line).unwrap_or_else(|| panic!("failed to parse {line:?}"))
+    }
+
+    #[test]
+    fn test_host_target_parsing() {
+        let message = ":tmi.twitch.tv HOSTTARGET #abc :xyz 10\r\n";
+        let mut pos = 0;
+        let _ = Prefix::parse(message, &mut pos).unwrap();
+        let command = IRCCommand::parse(message, &mut pos).unwrap();
+
+        let IRCCommand::HostTarget {
+            channel,
+            target,
+            viewers,
+        } = command
+        else {
+            panic!("expected HOSTTARGET, got {command:?}");
+        };
+
+        assert_eq!(channel, "abc");
+        assert_eq!(target.as_deref(), Some("xyz"));
+        assert_eq!(viewers, Some(10));
+
+        let message = ":tmi.twitch.tv HOSTTARGET #abc :- 0\r\n";
+        let mut pos = 0;
+        let _ = Prefix::parse(message, &mut pos).unwrap();
+        let command = IRCCommand::parse(message, &mut pos).unwrap();
+
+        assert!(matches!(
+            command,
+            IRCCommand::HostTarget { target: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_join_and_room_state_parsing() {
+        let message = parse_line(":foo!foo@foo.tmi.twitch.tv JOIN #bar\r\n");
+        assert!(matches!(message.command, IRCCommand::Join { ref channel } if channel == "bar"));
+        assert_eq!(message.prefix.nick.as_deref(), Some("foo"));
+
+        let message =
+            parse_line("@emote-only=0;followers-only=-1;r9k=0;slow=0;subs-only=0 :tmi.twitch.tv ROOMSTATE #bar\r\n");
+        assert!(
+            matches!(message.command, IRCCommand::RoomState { ref channel } if channel == "bar")
+        );
+        assert_eq!(message.tags.get("slow").unwrap(), "0");
+    }
+
+    #[test]
+    fn test_clear_parsing() {
+        let message = parse_line(
+            "@login=ronni;target-msg-id=abc-123 :tmi.twitch.tv CLEARMSG #dallas :HeyGuys\r\n",
+        );
+        let IRCCommand::ClearMsg {
+            channel,
+            message: text,
+        } = message.command
+        else {
+            panic!("expected CLEARMSG, got {:?}", message.command);
+        };
+        assert_eq!(channel, "dallas");
+        assert_eq!(text, "HeyGuys");
+        assert_eq!(message.tags.get("target-msg-id").unwrap(), "abc-123");
+
+        let message = parse_line("@ban-duration=350 :tmi.twitch.tv CLEARCHAT #dallas :ronni\r\n");
+        let IRCCommand::ClearChat { channel, user } = message.command else {
+            panic!("expected CLEARCHAT, got {:?}", message.command);
+        };
+        assert_eq!(channel, "dallas");
+        assert_eq!(user.as_deref(), Some("ronni"));
+
+        let message = parse_line(":tmi.twitch.tv CLEARCHAT #dallas\r\n");
+        assert!(matches!(
+            message.command,
+            IRCCommand::ClearChat { user: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_user_notice_parsing() {
+        let message = parse_line("@msg-id=announcement;msg-param-color=BLUE;system-msg=a\\sb\\:c\\\\ :tmi.twitch.tv USERNOTICE #dallas :hello everyone\r\n");
+        let IRCCommand::UserNotice {
+            channel,
+            message: text,
+        } = message.command
+        else {
+            panic!("expected USERNOTICE, got {:?}", message.command);
+        };
+        assert_eq!(channel, "dallas");
+        assert_eq!(text.as_deref(), Some("hello everyone"));
+        assert_eq!(message.tags.get("system-msg").unwrap(), "a b;c\\");
+
+        let message = parse_line("@msg-id=raid :tmi.twitch.tv USERNOTICE #dallas\r\n");
+        assert!(matches!(
+            message.command,
+            IRCCommand::UserNotice { message: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_notice_parsing() {
+        let message = parse_line(
+            "@msg-id=raid_error_self :tmi.twitch.tv NOTICE #bar :You cannot raid yourself.\r\n",
+        );
+        let IRCCommand::Notice { channel, message } = message.command else {
+            panic!("expected NOTICE, got {:?}", message.command);
+        };
+        assert_eq!(channel.as_deref(), Some("bar"));
+        assert_eq!(message, "You cannot raid yourself.");
+
+        let message = parse_line(":tmi.twitch.tv NOTICE * :Login authentication failed\r\n");
+        assert!(matches!(
+            message.command,
+            IRCCommand::Notice { channel: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_sub_tags() {
+        let message = parse_line("@msg-id=resub;msg-param-sub-plan=2000;msg-param-cumulative-months=14;msg-param-should-share-streak=1;msg-param-streak-months=5 :tmi.twitch.tv USERNOTICE #bar :hype\r\n");
+        assert_eq!(
+            message.tags.sub(),
+            Some(Sub {
+                resub: true,
+                plan: SubPlan::Tier2,
+                cumulative_months: Some(14),
+                streak_months: Some(5),
+            })
+        );
+        assert_eq!(message.tags.msg_param("sub-plan"), Some("2000"));
+
+        let message = parse_line("@msg-id=sub;msg-param-sub-plan=Prime;msg-param-cumulative-months=1;msg-param-should-share-streak=0;msg-param-streak-months=0 :tmi.twitch.tv USERNOTICE #bar\r\n");
+        let sub = message.tags.sub().unwrap();
+        assert_eq!((sub.resub, sub.plan), (false, SubPlan::Prime));
+        assert_eq!(sub.streak_months, None);
+
+        let message = parse_line("@msg-id=raid :tmi.twitch.tv USERNOTICE #bar\r\n");
+        assert_eq!(message.tags.sub(), None);
+
+        let message = parse_line("@msg-id=submysterygift;msg-param-community-gift-id=123;msg-param-mass-gift-count=50 :tmi.twitch.tv USERNOTICE #bar\r\n");
+        assert_eq!(message.tags.community_gift_id(), Some("123"));
+        assert_eq!(message.tags.mass_gift_count(), Some(50));
+    }
+
+    #[test]
+    fn test_ping_parsing() {
+        let message = parse_line("PING :tmi.twitch.tv\r\n");
+
+        assert!(matches!(message.command, IRCCommand::Ping));
+        assert_eq!(message.prefix.host, "");
+
+        let message = parse_line(":tmi.twitch.tv PONG tmi.twitch.tv :twitcher-1\r\n");
+        let IRCCommand::Pong { token } = message.command else {
+            panic!("expected PONG, got {:?}", message.command);
+        };
+        assert_eq!(token, "twitcher-1");
+    }
+
+    #[test]
+    fn test_numeric_parsing() {
+        let message = parse_line(":tmi.twitch.tv 001 foo :Welcome, GLHF!\r\n");
+        let IRCCommand::Welcome { nick, message } = message.command else {
+            panic!("expected RPL_WELCOME, got {:?}", message.command);
+        };
+        assert_eq!(nick, "foo");
+        assert_eq!(message, "Welcome, GLHF!");
+
+        let message = parse_line(":tmi.twitch.tv 004 foo :-\r\n");
+        assert!(matches!(
+            message.command,
+            IRCCommand::Numeric { code: 4, .. }
+        ));
+        let message = parse_line(":tmi.twitch.tv 376 foo :>\r\n");
+        assert!(matches!(
+            message.command,
+            IRCCommand::Numeric { code: 376, .. }
+        ));
+
+        // not part of logging in
+        let message = parse_line(":tmi.twitch.tv 421 foo WHO :Unknown command\r\n");
+        assert!(matches!(message.command, IRCCommand::Unknown(_)));
+    }
+
+    #[test]
+    fn test_cap_parsing() {
+        assert!(matches!(
+            parse_line(":tmi.twitch.tv CAP * ACK :twitch.tv/tags\r\n").command,
+            IRCCommand::CapAck
+        ));
+
+        let message = parse_line(":tmi.twitch.tv CAP * NAK :twitch.tv/tags twitch.tv/foo\r\n");
+        let IRCCommand::CapNak { capabilities } = message.command else {
+            panic!("expected CAP NAK, got {:?}", message.command);
+        };
+        assert_eq!(capabilities, ["twitch.tv/tags", "twitch.tv/foo"]);
+    }
+
+    #[test]
+    fn test_user_state_parsing() {
+        let message = "@badge-info=;badges=moderator/1;color=;display-name=bar;emote-sets=0,300374282;mod=1;subscriber=0;user-type=mod :tmi.twitch.tv USERSTATE #foo\r\n";
+        let irc_message = IRCMessage::parse(message).unwrap();
+
+        let IRCCommand::UserState { channel } = irc_message.command else {
+            panic!("expected USERSTATE, got {:?}", irc_message.command);
+        };
+
+        assert_eq!(channel, "foo");
+        assert_eq!(irc_message.tags.get("badges").unwrap(), "moderator/1");
+    }
+}
@@ -0,0 +1,110 @@
+//! Vim word motions over a single line, columns are counted in graphemes
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The first and last grapheme column of every word in the line
+fn words(line: &str) -> Vec<(usize, usize)> {
+    let offsets = line
+        .grapheme_indices(true)
+        .map(|(offset, _)| offset)
+        .collect::<Vec<_>>();
+    let column_of = |offset: usize| offsets.partition_point(|&start| start < offset);
+
+    line.unicode_word_indices()
+        .map(|(start, word)| (column_of(start), column_of(start + word.len()) - 1))
+        .collect()
+}
+
+/// `w`, the start of the next word
+pub fn next_word_start(line: &str, column: usize) -> Option<usize> {
+    words(line)
+        .into_iter()
+        .map(|(start, _)| start)
+        .find(|&start| start > column)
+}
+
+/// `b`, the start of the current word, or of the previous one if already at the start
+pub fn previous_word_start(line: &str, column: usize) -> Option<usize> {
+    words(line)
+        .into_iter()
+        .map(|(start, _)| start)
+        .rev()
+        .find(|&start| start < column)
+}
+
+/// `e`, the end of the current word, or of the next one if already at the end
+pub fn word_end(line: &str, column: usize) -> Option<usize> {
+    words(line)
+        .into_iter()
+        .map(|(_, end)| end)
+        .find(|&end| end > column)
+}
+
+/// `ge`, the end of the previous word
+pub fn previous_word_end(line: &str, column: usize) -> Option<usize> {
+    words(line)
+        .into_iter()
+        .map(|(_, end)| end)
+        .rev()
+        .find(|&end| end < column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_word_start() {
+        let line = "foo: hello, world!";
+
+        // punctuation isn't part of a word
+        assert_eq!(next_word_start(line, 0), Some(5));
+        assert_eq!(next_word_start(line, 5), Some(12));
+        assert_eq!(next_word_start(line, 10), Some(12));
+        assert_eq!(next_word_start(line, 12), None);
+    }
+
+    #[test]
+    fn test_previous_word_start() {
+        let line = "foo: hello, world!";
+
+        assert_eq!(previous_word_start(line, 17), Some(12));
+        assert_eq!(previous_word_start(line, 12), Some(5));
+        assert_eq!(previous_word_start(line, 7), Some(5));
+        assert_eq!(previous_word_start(line, 0), None);
+    }
+
+    #[test]
+    fn test_multibyte_words() {
+        // every one of these is more than one byte, columns are still graphemes
+        let line = "héllo wörld 日本語 👋🏽 ok";
+
+        assert_eq!(next_word_start(line, 0), Some(6));
+        assert_eq!(next_word_start(line, 6), Some(12));
+        assert_eq!(previous_word_start(line, 20), Some(18));
+        assert_eq!(word_end(line, 6), Some(10));
+    }
+
+    #[test]
+    fn test_word_end() {
+        let line = "foo: hello, world";
+
+        assert_eq!(word_end(line, 0), Some(2));
+        assert_eq!(word_end(line, 2), Some(9));
+        assert_eq!(word_end(line, 9), Some(16));
+        assert_eq!(word_end(line, 16), None);
+
+        // "é" is two bytes but one column
+        assert_eq!(word_end("café au lait", 0), Some(3));
+    }
+
+    #[test]
+    fn test_previous_word_end() {
+        let line = "foo: hello, world";
+
+        assert_eq!(previous_word_end(line, 16), Some(9));
+        assert_eq!(previous_word_end(line, 12), Some(9));
+        assert_eq!(previous_word_end(line, 9), Some(2));
+        assert_eq!(previous_word_end(line, 2), None);
+    }
+}
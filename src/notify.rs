@@ -0,0 +1,130 @@
+//! Desktop notifications for messages that come in while the terminal isn't focused
+
+use std::time::{Duration, Instant};
+
+/// Shortest time between two notifications, whatever comes in meanwhile is shown together
+const COOLDOWN: Duration = Duration::from_secs(10);
+
+/// Messages listed in a notification for a burst, the rest are only counted
+const MAX_LISTED: usize = 5;
+
+struct Pending {
+    channel: String,
+    sender: String,
+    text: String,
+}
+
+#[derive(Default)]
+pub struct Notifier {
+    pending: Vec<Pending>,
+    last_shown: Option<Instant>,
+}
+
+impl Notifier {
+    pub fn queue(&mut self, channel: &str, sender: &str, text: &str) {
+        self.pending.push(Pending {
+            channel: channel.to_string(),
+            sender: sender.to_string(),
+            text: text.to_string(),
+        });
+    }
+
+    /// Shows what's queued, unless the last notification was too recent
+    pub fn flush(&mut self, now: Instant) {
+        let Some((summary, body)) = self.take_notification(now) else {
+            return;
+        };
+
+        if let Err(e) = notify_rust::Notification::new()
+            .summary(&summary)
+            .body(&body)
+            .appname("twitcher")
+            .show()
+        {
+            tracing::warn!(%e, "failed to show a notification");
+        }
+    }
+
+    /// The summary and body of the next notification
+    fn take_notification(&mut self, now: Instant) -> Option<(String, String)> {
+        if self.pending.is_empty()
+            || self
+                .last_shown
+                .is_some_and(|last_shown| now.duration_since(last_shown) < COOLDOWN)
+        {
+            return None;
+        }
+
+        self.last_shown = Some(now);
+
+        if let [message] = self.pending.as_slice() {
+            let notification = (
+                format!("{} in #{}", message.sender, message.channel),
+                message.text.clone(),
+            );
+            self.pending.clear();
+            return Some(notification);
+        }
+
+        let mut body = self
+            .pending
+            .iter()
+            .take(MAX_LISTED)
+            .map(|message| format!("#{} {}: {}", message.channel, message.sender, message.text))
+            .collect::<Vec<_>>();
+        if self.pending.len() > MAX_LISTED {
+            body.push(format!("and {} more", self.pending.len() - MAX_LISTED));
+        }
+
+        let summary = format!("{} new messages", self.pending.len());
+        self.pending.clear();
+        Some((summary, body.join("\n")))
+    }
+}
+
+/// Whether `text` mentions `nick`, with or without the `@`
+pub fn mentions(text: &str, nick: &str) -> bool {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .any(|word| word.eq_ignore_ascii_case(nick))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mentions() {
+        assert!(mentions("hey @Foo_Bar how are you", "foo_bar"));
+        assert!(mentions("foo_bar: hi", "foo_bar"));
+        assert!(!mentions("foo_barbaz", "foo_bar"));
+        assert!(!mentions("hello", "foo_bar"));
+    }
+
+    #[test]
+    fn test_notifications_are_coalesced() {
+        let mut notifier = Notifier::default();
+        let start = Instant::now();
+        assert_eq!(notifier.take_notification(start), None);
+
+        notifier.queue("bar", "Foo", "@me hi");
+        assert_eq!(
+            notifier.take_notification(start),
+            Some((String::from("Foo in #bar"), String::from("@me hi")))
+        );
+
+        notifier.queue("bar", "Foo", "@me are you there");
+        notifier.queue("baz", "Qux", "@me hello");
+        assert_eq!(
+            notifier.take_notification(start + Duration::from_secs(5)),
+            None
+        );
+        assert_eq!(
+            notifier.take_notification(start + COOLDOWN),
+            Some((
+                String::from("2 new messages"),
+                String::from("#bar Foo: @me are you there\n#baz Qux: @me hello")
+            ))
+        );
+        assert_eq!(notifier.take_notification(start + COOLDOWN * 2), None);
+    }
+}
@@ -0,0 +1,69 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Keeps track of how many messages were sent in a sliding window
+///
+/// Twitch silently drops messages past its limit, and can lock the account out of chat for
+/// a while, so it's better to refuse them here
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: usize,
+    window: Duration,
+    sent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            sent: VecDeque::with_capacity(limit),
+        }
+    }
+
+    /// Records a message sent at `now` if it still fits in the window
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= self.window)
+        {
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() >= self.limit {
+            return false;
+        }
+
+        self.sent.push_back(now);
+        true
+    }
+}
+
+impl Default for RateLimiter {
+    /// The limit for regular users, 20 messages every 30 seconds
+    fn default() -> Self {
+        Self::new(20, Duration::from_secs(30))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut rate_limiter = RateLimiter::new(2, Duration::from_secs(30));
+        let start = Instant::now();
+
+        assert!(rate_limiter.try_acquire(start));
+        assert!(rate_limiter.try_acquire(start + Duration::from_secs(1)));
+        assert!(!rate_limiter.try_acquire(start + Duration::from_secs(2)));
+
+        // the first one left the window
+        assert!(rate_limiter.try_acquire(start + Duration::from_secs(30)));
+        assert!(!rate_limiter.try_acquire(start + Duration::from_secs(30)));
+    }
+}
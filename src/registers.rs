@@ -0,0 +1,83 @@
+//! Vim style registers for yanking, deleting and pasting
+
+use std::collections::HashMap;
+
+use arboard::Clipboard;
+
+/// Where yanks and deletes go when no register is given, and what `P` pastes from
+pub const UNNAMED: char = '"';
+
+/// Named registers `a` to `z` and the unnamed one are kept in memory, `+` and `*` are the
+/// system clipboard
+pub struct Registers {
+    clipboard: Option<Clipboard>,
+    saved: HashMap<char, String>,
+}
+
+impl Registers {
+    /// `clipboard` is `None` when there's no system clipboard, e.g. without a display
+    pub fn new(clipboard: Option<Clipboard>) -> Self {
+        Self {
+            clipboard,
+            saved: HashMap::new(),
+        }
+    }
+
+    /// Whether `c` can follow a `"`
+    pub fn is_register(c: char) -> bool {
+        c.is_ascii_lowercase() || matches!(c, UNNAMED | '+' | '*')
+    }
+
+    /// Stores `text` in `register`, the unnamed register always gets a copy like in vim
+    pub fn set(&mut self, register: char, text: String) {
+        if matches!(register, '+' | '*') {
+            if let Some(clipboard) = &mut self.clipboard {
+                if let Err(e) = clipboard.set_text(&text) {
+                    tracing::warn!(%e, "failed to set the clipboard");
+                }
+            }
+        } else if register != UNNAMED {
+            self.saved.insert(register, text.clone());
+        }
+
+        self.saved.insert(UNNAMED, text);
+    }
+
+    pub fn get(&mut self, register: char) -> Option<String> {
+        if matches!(register, '+' | '*') {
+            return self.clipboard.as_mut()?.get_text().ok();
+        }
+
+        self.saved.get(&register).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registers() {
+        let mut registers = Registers::new(None);
+        assert_eq!(registers.get(UNNAMED), None);
+
+        registers.set('a', String::from("first"));
+        registers.set(UNNAMED, String::from("second"));
+        assert_eq!(registers.get('a').as_deref(), Some("first"));
+        assert_eq!(registers.get(UNNAMED).as_deref(), Some("second"));
+
+        registers.set('b', String::from("third"));
+        assert_eq!(registers.get(UNNAMED).as_deref(), Some("third"));
+        assert_eq!(registers.get('a').as_deref(), Some("first"));
+
+        // no clipboard to read from
+        registers.set('+', String::from("fourth"));
+        assert_eq!(registers.get('+'), None);
+        assert_eq!(registers.get(UNNAMED).as_deref(), Some("fourth"));
+
+        assert!(Registers::is_register('z'));
+        assert!(Registers::is_register('*'));
+        assert!(!Registers::is_register('A'));
+        assert!(!Registers::is_register('1'));
+    }
+}
@@ -0,0 +1,79 @@
+//! Cleaning up message text before it's drawn, chatters can send pretty much anything
+
+use std::borrow::Cow;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Removes control characters, which could move the cursor or change colors, and caps the
+/// combining marks in each grapheme so "zalgo" text doesn't spill over the lines around it
+///
+/// Tabs become a space, a `max_combining_marks` of 0 means no limit
+pub fn sanitize(text: &str, max_combining_marks: usize) -> Cow<'_, str> {
+    let too_many_marks = |grapheme: &str| {
+        max_combining_marks > 0
+            && grapheme.chars().filter(|c| is_combining_mark(*c)).count() > max_combining_marks
+    };
+
+    if !text.chars().any(char::is_control) && !text.graphemes(true).any(too_many_marks) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut sanitized = String::with_capacity(text.len());
+    for grapheme in text.graphemes(true) {
+        let mut marks = 0;
+
+        for c in grapheme.chars() {
+            if c == '\t' {
+                sanitized.push(' ');
+            } else if c.is_control() {
+                continue;
+            } else if is_combining_mark(c) {
+                marks += 1;
+                if max_combining_marks == 0 || marks <= max_combining_marks {
+                    sanitized.push(c);
+                }
+            } else {
+                sanitized.push(c);
+            }
+        }
+    }
+
+    Cow::Owned(sanitized)
+}
+
+/// The blocks zalgo generators pull from, not every combining mark there is
+fn is_combining_mark(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE20}'..='\u{FE2F}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        assert!(matches!(sanitize("hello there", 2), Cow::Borrowed(_)));
+        assert_eq!(sanitize("a\u{1b}[2Jb\r\nc\td", 2), "a[2Jbc d");
+
+        // é written as e + a combining acute accent is fine
+        assert_eq!(sanitize("cafe\u{301}", 2), "cafe\u{301}");
+        assert_eq!(
+            sanitize("z\u{300}\u{301}\u{302}\u{303}\u{304}a", 2),
+            "z\u{300}\u{301}a"
+        );
+        assert_eq!(
+            sanitize("z\u{300}\u{301}\u{302}", 0),
+            "z\u{300}\u{301}\u{302}"
+        );
+
+        // emoji sequences aren't combining marks
+        assert_eq!(sanitize("👨‍👩‍👧‍👦", 1), "👨‍👩‍👧‍👦");
+    }
+}
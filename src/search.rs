@@ -0,0 +1,142 @@
+//! What `/` searches chat for, like `from:forsen badge:moderator "hi chat"`
+
+/// The parts of a chat line a [`Query`] looks at
+pub struct Searched<'a> {
+    pub login: Option<&'a str>,
+    pub display_name: &'a str,
+    /// badge names without their version, like `moderator`
+    pub badges: &'a [String],
+    pub text: &'a str,
+}
+
+#[derive(Debug, PartialEq)]
+enum Term {
+    /// the sender's login or display name
+    From(String),
+    Badge(String),
+    /// anywhere in the text
+    Text(String),
+}
+
+/// Terms separated by spaces that all have to match, ignoring case
+///
+/// - `from:name` for messages sent by `name`, an `@` in front is fine too
+/// - `badge:name` for senders with that badge, e.g. `badge:moderator` or `badge:vip`
+/// - anything else is looked for in the text, `"in quotes"` to keep spaces in it
+///
+/// A `-` in front of a term matches the messages it doesn't match instead
+#[derive(Debug, PartialEq)]
+pub struct Query {
+    terms: Vec<(bool, Term)>,
+}
+
+impl Query {
+    pub fn parse(query: &str) -> anyhow::Result<Self> {
+        let mut terms = Vec::new();
+
+        for word in words(query) {
+            let (negated, word) = match word.strip_prefix('-') {
+                Some(rest) if !rest.is_empty() => (true, rest.to_string()),
+                _ => (false, word),
+            };
+
+            let term = if let Some(name) = word.strip_prefix("from:") {
+                let name = name.trim_start_matches('@');
+                anyhow::ensure!(!name.is_empty(), "from: needs a name");
+                Term::From(name.to_lowercase())
+            } else if let Some(badge) = word.strip_prefix("badge:") {
+                anyhow::ensure!(!badge.is_empty(), "badge: needs a badge name");
+                Term::Badge(badge.to_lowercase())
+            } else {
+                Term::Text(word.to_lowercase())
+            };
+
+            terms.push((negated, term));
+        }
+
+        anyhow::ensure!(!terms.is_empty(), "nothing to search for");
+        Ok(Self { terms })
+    }
+
+    pub fn matches(&self, message: &Searched) -> bool {
+        let text = message.text.to_lowercase();
+
+        self.terms.iter().all(|(negated, term)| {
+            let matches = match term {
+                Term::From(name) => {
+                    message
+                        .login
+                        .is_some_and(|login| login.eq_ignore_ascii_case(name))
+                        || message.display_name.to_lowercase() == *name
+                }
+                Term::Badge(badge) => message
+                    .badges
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(badge)),
+                Term::Text(words) => text.contains(words.as_str()),
+            };
+
+            matches != *negated
+        })
+    }
+}
+
+/// `query` split at spaces outside of double quotes, the quotes are dropped
+fn words(query: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query() {
+        let badges = [String::from("moderator"), String::from("subscriber")];
+        let message = Searched {
+            login: Some("forsen"),
+            display_name: "Forsen",
+            badges: &badges,
+            text: "Hi chat, time: 12:30",
+        };
+        let matches = |query: &str| Query::parse(query).unwrap().matches(&message);
+
+        assert!(matches("from:forsen"));
+        assert!(matches("from:@FORSEN"));
+        assert!(!matches("from:xqc"));
+        assert!(matches("badge:moderator"));
+        assert!(!matches("badge:vip"));
+        assert!(matches("-badge:vip"));
+        assert!(matches("HI from:forsen"));
+        assert!(!matches("hi from:xqc"));
+        assert!(matches("\"hi chat\""));
+        assert!(!matches("\"chat hi\""));
+        // not a known key, so just text
+        assert!(matches("time:"));
+        assert!(matches("12:30"));
+        assert!(!matches("-12:30"));
+
+        assert!(Query::parse("from:").is_err());
+        assert!(Query::parse(" \"\" ").is_err());
+    }
+}
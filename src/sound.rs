@@ -0,0 +1,120 @@
+//! Sounds for channel events like subs and raids, for when chat is in a terminal off to the side
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use crate::config::Sounds;
+
+/// What the terminal bell is called in the config, anything else is a command to run
+const BELL: &str = "bell";
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Event {
+    Sub,
+    Raid,
+    Bits,
+    Host,
+}
+
+impl Event {
+    /// From a USERNOTICE's `msg-id`, `None` for the ones that don't make a sound
+    pub fn from_user_notice(msg_id: &str) -> Option<Self> {
+        match msg_id {
+            "sub"
+            | "resub"
+            | "subgift"
+            | "submysterygift"
+            | "giftpaidupgrade"
+            | "anongiftpaidupgrade"
+            | "primepaidupgrade" => Some(Self::Sub),
+            "raid" => Some(Self::Raid),
+            _ => None,
+        }
+    }
+
+    fn sound(self, sounds: &Sounds) -> &str {
+        match self {
+            Self::Sub => &sounds.sub,
+            Self::Raid => &sounds.raid,
+            Self::Bits => &sounds.bits,
+            Self::Host => &sounds.host,
+        }
+    }
+}
+
+/// Plays the sounds from the config, each event at most once per cooldown so a gift bomb
+/// doesn't ring fifty times
+#[derive(Default)]
+pub struct Player {
+    last_played: HashMap<Event, Instant>,
+}
+
+impl Player {
+    pub fn play(&mut self, event: Event, sounds: &Sounds, now: Instant) {
+        let sound = event.sound(sounds);
+        if sound.is_empty() || !self.due(event, Duration::from_secs(sounds.cooldown), now) {
+            return;
+        }
+
+        if sound == BELL {
+            let mut stdout = std::io::stdout();
+            let _ = stdout.write_all(b"\x07").and_then(|_| stdout.flush());
+            return;
+        }
+
+        // not waited for, a sound shouldn't hold up chat
+        if let Err(e) = Command::new("sh")
+            .args(["-c", sound])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            tracing::warn!(%e, sound, "failed to run the sound command");
+        }
+    }
+
+    /// Whether `event` last played at least `cooldown` ago, it counts as played now if so
+    fn due(&mut self, event: Event, cooldown: Duration, now: Instant) -> bool {
+        if self
+            .last_played
+            .get(&event)
+            .is_some_and(|last_played| now.duration_since(*last_played) < cooldown)
+        {
+            return false;
+        }
+
+        self.last_played.insert(event, now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cooldown() {
+        let mut player = Player::default();
+        let cooldown = Duration::from_secs(5);
+        let start = Instant::now();
+
+        assert!(player.due(Event::Sub, cooldown, start));
+        assert!(!player.due(Event::Sub, cooldown, start + Duration::from_secs(1)));
+        // every event has its own cooldown
+        assert!(player.due(Event::Raid, cooldown, start + Duration::from_secs(1)));
+        assert!(player.due(Event::Sub, cooldown, start + cooldown));
+    }
+
+    #[test]
+    fn test_event_from_user_notice() {
+        assert_eq!(Event::from_user_notice("resub"), Some(Event::Sub));
+        assert_eq!(Event::from_user_notice("submysterygift"), Some(Event::Sub));
+        assert_eq!(Event::from_user_notice("raid"), Some(Event::Raid));
+        assert_eq!(Event::from_user_notice("announcement"), None);
+    }
+}
@@ -0,0 +1,36 @@
+//! How many terminal cells text takes up, CJK and most emoji take two where everything else
+//! takes one
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Cells `text` takes up
+pub fn width(text: &str) -> usize {
+    text.graphemes(true).map(grapheme_width).sum()
+}
+
+/// Cells a single grapheme takes up, marks and joiners in it don't add to its base character
+pub fn grapheme_width(grapheme: &str) -> usize {
+    // measured as a whole, so emoji sequences like ❤️ or flags come out as the one symbol
+    // terminals draw for them
+    grapheme.width()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_width() {
+        assert_eq!(width("Kappa"), 5);
+        assert_eq!(width("日本語"), 6);
+        assert_eq!(width("hi 👋"), 5);
+        assert_eq!(width("é"), 1);
+        assert_eq!(width("e\u{301}"), 1);
+        assert_eq!(width("❤️"), 2);
+        assert_eq!(width("👨‍👩‍👧"), 2);
+        assert_eq!(width("🇩🇪"), 2);
+        assert_eq!(width("a\u{200B}"), 1);
+        assert_eq!(width(""), 0);
+    }
+}